@@ -0,0 +1,101 @@
+//! Compares the sweep and prune broadphase against the old all-pairs test.
+//!
+//! Run with `cargo bench -p gm --bench broadphase`.
+
+use std::{hint::black_box, time::Instant};
+
+use bevy::math::Vec3;
+use gm::physics::{
+    collisions::broadphase::{Aabb, BroadPhaseProxy, sweep_and_prune},
+    layers::CollisionLayers,
+};
+
+/// Small deterministic generator so every run measures the same scene
+struct Lcg(u64);
+
+impl Lcg {
+    fn next_f32(&mut self) -> f32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// A scene of crates scattered over a floor roughly proportional to their count
+fn crate_scene(count: usize) -> Vec<BroadPhaseProxy> {
+    let mut rng = Lcg(0x5eed);
+    let extent = (count as f32).sqrt() * 3.;
+
+    let mut proxies = vec![BroadPhaseProxy {
+        aabb: Aabb::new(
+            Vec3::new(-extent, -1., -extent),
+            Vec3::new(extent, 0., extent),
+        ),
+        is_static: true,
+        layers: CollisionLayers::default(),
+    }];
+
+    for i in 0..count {
+        let center = Vec3::new(
+            (rng.next_f32() * 2. - 1.) * extent,
+            rng.next_f32() * 10.,
+            (rng.next_f32() * 2. - 1.) * extent,
+        );
+        let half_extents = Vec3::splat(0.5 + rng.next_f32());
+        proxies.push(BroadPhaseProxy {
+            aabb: Aabb::from_center_half_extents(center, half_extents),
+            is_static: i % 10 == 0,
+            layers: CollisionLayers::default(),
+        });
+    }
+
+    proxies
+}
+
+fn all_pairs(proxies: &[BroadPhaseProxy]) -> Vec<(usize, usize)> {
+    let mut pairs = vec![];
+    for i in 0..proxies.len() {
+        for j in (i + 1)..proxies.len() {
+            if proxies[i].is_static && proxies[j].is_static {
+                continue;
+            }
+            if proxies[i].aabb.intersects(&proxies[j].aabb) {
+                pairs.push((i, j));
+            }
+        }
+    }
+    pairs
+}
+
+fn time<F: FnMut() -> usize>(iterations: u32, mut f: F) -> (f64, usize) {
+    let mut found = 0;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        found = black_box(f());
+    }
+    (
+        start.elapsed().as_secs_f64() * 1000. / iterations as f64,
+        found,
+    )
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>10} {:>14} {:>14}",
+        "bodies", "pairs", "sap (ms)", "all-pairs (ms)"
+    );
+
+    for count in [100, 500, 1_000, 2_000, 5_000, 10_000] {
+        let proxies = crate_scene(count);
+        let iterations = (200_000 / count).max(5) as u32;
+
+        let (sap_ms, sap_pairs) = time(iterations, || sweep_and_prune(black_box(&proxies)).len());
+        let (brute_ms, brute_pairs) =
+            time(iterations.min(20), || all_pairs(black_box(&proxies)).len());
+
+        assert_eq!(sap_pairs, brute_pairs, "broadphase missed candidate pairs");
+        println!("{count:>8} {sap_pairs:>10} {sap_ms:>14.3} {brute_ms:>14.3}");
+    }
+}
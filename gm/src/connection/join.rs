@@ -1,51 +1,200 @@
-use std::{
-    io::{Read, Write},
-    net::TcpStream,
-};
+use std::net::{Ipv4Addr, SocketAddr};
 
-use bevy::{ecs::event, prelude::*};
+use bevy::prelude::*;
+use renet::{DisconnectReason, RenetClient};
 use serde::{Deserialize, Serialize};
 
-use crate::gamestate::AppState;
+use super::{
+    prediction::PredictionPlugin,
+    protocol::{ClientMessage, ServerMessage},
+    remote::RemotePlugin,
+    transport::{Channel, ClientTransport, DEFAULT_PORT, Refusal, connection_config},
+};
+use crate::{gamestate::AppState, player::player_data::Player};
 
 #[derive(Event, Serialize, Deserialize)]
 pub struct ConnectedToServerEvent;
 
+/// The connection was lost or refused, or couldn't be opened at all
+#[derive(Event, Debug)]
+pub struct DisconnectedFromServerEvent {
+    pub reason: Option<DisconnectReason>,
+    /// Set when the server refused the handshake
+    pub refusal: Option<Refusal>,
+}
+
+/// Server joined when loading, on the local machine by default
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ServerAddress(pub SocketAddr);
+
+impl Default for ServerAddress {
+    fn default() -> Self {
+        Self((Ipv4Addr::LOCALHOST, DEFAULT_PORT).into())
+    }
+}
+
+/// Connection to the game server, there while joining and connected
+#[derive(Resource, Debug)]
+pub struct ServerConnection {
+    pub client: RenetClient,
+    pub transport: ClientTransport,
+}
+
+impl ServerConnection {
+    pub fn new(server_addr: SocketAddr) -> std::io::Result<Self> {
+        Ok(Self {
+            client: RenetClient::new(connection_config()),
+            transport: ClientTransport::new(server_addr)?,
+        })
+    }
+
+    /// Queue a message on its channel, it goes out at the end of the frame
+    pub fn send(&mut self, message: &ClientMessage) {
+        match message.to_bytes() {
+            Ok(bytes) => self.client.send_message(message.channel(), bytes),
+            Err(e) => error!("couldn't encode {message:?}: {e}"),
+        }
+    }
+}
+
 pub struct MPlayerPlugin;
 
 impl Plugin for MPlayerPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<ConnectedToServerEvent>()
-            //.add_systems(OnEnter(AppState::Loading), send_ping)
-            .add_systems(OnEnter(AppState::Loading), start_playing)
-            .add_systems(Update, check_connected.run_if(in_state(AppState::Loading)));
+            .add_event::<DisconnectedFromServerEvent>()
+            .add_event::<ServerMessage>()
+            .init_resource::<ServerAddress>()
+            .add_plugins((PredictionPlugin, RemotePlugin))
+            .add_systems(OnEnter(AppState::Loading), connect_to_server)
+            .add_systems(
+                PreUpdate,
+                receive_server_packets.run_if(resource_exists::<ServerConnection>),
+            )
+            .add_systems(
+                PostUpdate,
+                send_server_packets.run_if(resource_exists::<ServerConnection>),
+            )
+            .add_systems(
+                Update,
+                (
+                    check_connected.run_if(in_state(AppState::Loading)),
+                    (send_join, handle_kick).run_if(resource_exists::<ServerConnection>),
+                ),
+            );
     }
 }
 
-fn check_connected(
-    mut events: EventReader<ConnectedToServerEvent>,
-    mut next_state: ResMut<NextState<AppState>>,
+fn connect_to_server(
+    mut commands: Commands,
+    address: Res<ServerAddress>,
+    mut disconnected: EventWriter<DisconnectedFromServerEvent>,
 ) {
-    for _ in events.read() {
-        next_state.set(AppState::Playing);
+    match ServerConnection::new(address.0) {
+        Ok(connection) => commands.insert_resource(connection),
+        Err(e) => {
+            error!("couldn't open a connection to {}: {e}", address.0);
+            disconnected.write(DisconnectedFromServerEvent {
+                reason: None,
+                refusal: None,
+            });
+        }
     }
 }
 
-fn start_playing(mut next_state: ResMut<NextState<AppState>>) {
-    next_state.set(AppState::Playing);
-}
+pub(super) fn receive_server_packets(
+    mut commands: Commands,
+    mut connection: ResMut<ServerConnection>,
+    time: Res<Time>,
+    mut connected: EventWriter<ConnectedToServerEvent>,
+    mut disconnected: EventWriter<DisconnectedFromServerEvent>,
+    mut messages: EventWriter<ServerMessage>,
+) {
+    let ServerConnection { client, transport } = connection.as_mut();
+    let was_connected = client.is_connected();
+    if let Err(e) = transport.update(time.delta(), client) {
+        error!("lost the connection to {}: {e}", transport.server_addr());
+        client.disconnect_due_to_transport();
+    }
 
-fn send_ping() {
-    if let Ok(mut stream) = TcpStream::connect("127.0.0.1") {
-        if let Ok(serialized_message) = bincode::serialize(&String::from("ping")) {
-            if stream.write_all(&serialized_message).is_ok() {
-                let mut buffer = [0; 512];
-                if let Ok(bytes) = stream.read(&mut buffer) {
-                    if let Ok(message) = bincode::deserialize::<String>(&buffer[0..bytes]) {
-                        println!("{message}");
-                    }
+    if !was_connected && client.is_connected() {
+        info!("connected to {}", transport.server_addr());
+        connected.write(ConnectedToServerEvent);
+    }
+
+    for channel in Channel::ALL {
+        while let Some(bytes) = client.receive_message(channel) {
+            match ServerMessage::from_bytes(&bytes) {
+                Ok(message) => {
+                    messages.write(message);
                 }
+                Err(e) => warn!("dropped a message from the server: {e}"),
+            }
+        }
+    }
+
+    if client.is_disconnected() {
+        let (reason, refusal) = (client.disconnect_reason(), transport.refusal());
+        match (refusal, reason) {
+            (Some(refusal), _) => {
+                error!("{} refused to connect: {refusal}", transport.server_addr())
+            }
+            (None, Some(reason)) => {
+                info!("disconnected from {}: {reason}", transport.server_addr())
             }
+            (None, None) => info!("disconnected from {}", transport.server_addr()),
+        }
+        disconnected.write(DisconnectedFromServerEvent { reason, refusal });
+        commands.remove_resource::<ServerConnection>();
+    }
+}
+
+fn send_server_packets(mut connection: ResMut<ServerConnection>) {
+    let ServerConnection { client, transport } = connection.as_mut();
+    if let Err(e) = transport.send_packets(client) {
+        error!("couldn't send to {}: {e}", transport.server_addr());
+        client.disconnect_due_to_transport();
+    }
+}
+
+/// Introduce the local player once connected
+fn send_join(
+    mut events: EventReader<ConnectedToServerEvent>,
+    mut connection: ResMut<ServerConnection>,
+    players: Query<&Player>,
+) {
+    for _ in events.read() {
+        let info = players
+            .iter()
+            .next()
+            .map_or_else(|| Player::default().info, |player| player.info.clone());
+        connection.send(&ClientMessage::Join { info });
+    }
+}
+
+fn handle_kick(mut messages: EventReader<ServerMessage>, mut connection: ResMut<ServerConnection>) {
+    for message in messages.read() {
+        if let ServerMessage::Kick { reason } = message {
+            error!(
+                "kicked from {}: {reason}",
+                connection.transport.server_addr()
+            );
+            connection.client.disconnect();
         }
     }
 }
+
+/// Start playing once joined, or alone if the server can't be reached
+fn check_connected(
+    mut events: EventReader<ConnectedToServerEvent>,
+    mut failures: EventReader<DisconnectedFromServerEvent>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for _ in events.read() {
+        next_state.set(AppState::Playing);
+    }
+    for _ in failures.read() {
+        warn!("playing offline");
+        next_state.set(AppState::Playing);
+    }
+}
@@ -1,5 +1,9 @@
 pub mod join;
 pub mod lobby;
+pub mod prediction;
+pub mod protocol;
+pub mod remote;
+pub mod transport;
 
 use std::{
     collections::HashMap,
@@ -0,0 +1,200 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use super::{
+    join::{ServerConnection, receive_server_packets},
+    protocol::{ClientMessage, ServerMessage},
+};
+use crate::{
+    physics::{
+        interpolation::PhysicsInterpolation, layers::CollisionLayers, prelude::PhysicsSet,
+        spatial_query::SpatialQuery,
+    },
+    player::{
+        controller::{CharacterController, PlayerInput, Surroundings, character_filter, step},
+        player_data::Player,
+    },
+};
+
+/// Inputs kept for replay at most, two seconds of them. Older ones are dropped, and the
+/// next correction snaps over whatever they did.
+const MAX_PENDING_INPUTS: usize = 120;
+/// How fast a visible correction fades, as an exponential decay rate per second
+const CORRECTION_DECAY: f32 = 12.;
+/// Corrections longer than this are jumped instead of smoothed, they're teleports
+const MAX_SMOOTHED_CORRECTION: f32 = 4.;
+
+/// Moves the local player as soon as a key is pressed instead of a round trip later, and
+/// puts them back on the server's track whenever a snapshot says where they really are
+pub struct PredictionPlugin;
+
+impl Plugin for PredictionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PreUpdate,
+            (spawn_local_player, reconcile)
+                .chain()
+                .after(receive_server_packets),
+        )
+        .add_systems(
+            FixedUpdate,
+            record_input
+                .in_set(PhysicsSet::Prepare)
+                .run_if(resource_exists::<ServerConnection>),
+        )
+        .add_systems(
+            PostUpdate,
+            smooth_corrections.before(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+/// An input sent to the server but not yet applied by it
+#[derive(Clone, Debug)]
+struct PendingInput {
+    sequence: u32,
+    input: PlayerInput,
+    dir: Quat,
+}
+
+/// The player this client controls, simulated ahead of the server
+#[derive(Component, Debug, Default)]
+pub struct LocalPlayer {
+    /// Sequence of the last input sent
+    sequence: u32,
+    /// Inputs the server hasn't acknowledged, oldest first
+    pending: VecDeque<PendingInput>,
+    /// Tick of the snapshot reconciled with last, older ones arriving late are ignored
+    last_tick: u64,
+    /// Where the player is drawn relative to where they are, faded out so corrections
+    /// don't show as jumps
+    correction: Vec3,
+}
+
+/// Spawn the player the server made for us
+fn spawn_local_player(mut commands: Commands, mut messages: EventReader<ServerMessage>) {
+    for message in messages.read() {
+        if let ServerMessage::Welcome { player } = message {
+            let transform = Transform::from_translation(player.pos.loc);
+            commands.spawn((player.clone(), LocalPlayer::default(), transform));
+        }
+    }
+}
+
+/// Number this tick's input, keep it for replay and send it. Runs before the movement
+/// systems consume it.
+fn record_input(
+    mut connection: ResMut<ServerConnection>,
+    mut query: Query<(&Player, &PlayerInput, &mut LocalPlayer)>,
+) {
+    if !connection.client.is_connected() {
+        return;
+    }
+    for (player, input, mut local) in query.iter_mut() {
+        local.sequence += 1;
+        let pending = PendingInput {
+            sequence: local.sequence,
+            input: input.clone(),
+            dir: player.pos.dir,
+        };
+        connection.send(&ClientMessage::Input {
+            sequence: pending.sequence,
+            input: pending.input.clone(),
+            dir: pending.dir,
+        });
+        local.pending.push_back(pending);
+        if local.pending.len() > MAX_PENDING_INPUTS {
+            local.pending.pop_front();
+        }
+    }
+}
+
+type LocalPlayerQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static mut Player,
+        &'static mut CharacterController,
+        &'static mut LocalPlayer,
+        Option<&'static mut PhysicsInterpolation>,
+        Option<&'static CollisionLayers>,
+    ),
+>;
+
+/// Rewind the local player to the newest state the server sent and replay the inputs it
+/// hasn't applied yet on top. Whatever the prediction got wrong is drawn away over a few
+/// frames instead of at once.
+fn reconcile(
+    mut messages: EventReader<ServerMessage>,
+    mut query: LocalPlayerQuery,
+    spatial: SpatialQuery,
+    surroundings: Surroundings,
+    time: Res<Time<Fixed>>,
+) {
+    let Ok((entity, mut player, mut controller, mut local, interpolation, layers)) =
+        query.single_mut()
+    else {
+        return;
+    };
+    let newest = messages
+        .read()
+        .filter_map(|message| match message {
+            ServerMessage::Snapshot(snapshot) => Some(snapshot),
+            _ => None,
+        })
+        .filter_map(|snapshot| {
+            let state = snapshot.players.iter().find(|p| p.id == player.info.id)?;
+            Some((snapshot.tick, state))
+        })
+        .max_by_key(|(tick, _)| *tick);
+    let Some((tick, state)) = newest else {
+        return;
+    };
+    if tick <= local.last_tick {
+        return;
+    }
+    local.last_tick = tick;
+    local
+        .pending
+        .retain(|pending| pending.sequence > state.last_input);
+
+    let predicted = player.pos.loc;
+    let dir = player.pos.dir;
+    let dt = time.timestep().as_secs_f32();
+    let filter = character_filter(entity, layers);
+
+    // the server doesn't send what the player stands on, so look for it again
+    player.pos = state.pos.clone();
+    controller.find_ground(&spatial, &mut player.pos, &filter);
+    for pending in &local.pending {
+        player.pos.dir = pending.dir;
+        player.pos.swimming = surroundings.is_swimming(player.pos.loc);
+        let movement = surroundings.movement_state(&player, &controller);
+        player.pos = step(&movement, &pending.input, dt).pos;
+        controller.move_player(&spatial, &mut player.pos, &filter, dt);
+    }
+    player.pos.dir = dir;
+
+    let error = predicted - player.pos.loc;
+    if error == Vec3::ZERO {
+        return;
+    }
+    // keep blending between ticks from the corrected position, the offset covers the rest
+    if let Some(mut interpolation) = interpolation {
+        interpolation.previous_translation -= error;
+        interpolation.translation -= error;
+    }
+    local.correction += error;
+    if local.correction.length() > MAX_SMOOTHED_CORRECTION {
+        local.correction = Vec3::ZERO;
+    }
+}
+
+fn smooth_corrections(mut query: Query<(&mut LocalPlayer, &mut Transform)>, time: Res<Time>) {
+    for (mut local, mut transform) in query.iter_mut() {
+        local.correction *= (-CORRECTION_DECAY * time.delta_secs()).exp();
+        transform.translation += local.correction;
+    }
+}
@@ -0,0 +1,130 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::transport::Channel;
+use crate::{
+    physics::bodies::{RigidbodyComponent, Velocity},
+    player::{
+        controller::PlayerInput,
+        player_data::{Player, PlayerPositioning},
+        player_info::{PlayerId, PlayerInfo, PlayerUsername},
+    },
+};
+
+/// Bumped whenever a message changes shape. Clients of another version are refused during
+/// the handshake, before any message is decoded.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Identifies a networked rigid body the same way on the server and every client, since
+/// their `Entity`s differ
+#[derive(
+    Component, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct NetworkId(pub u64);
+
+/// Where a networked rigid body is at a server tick
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct BodyState {
+    pub id: NetworkId,
+    pub center: Vec3,
+    pub rotation: Quat,
+    pub velocity: Velocity,
+}
+
+/// Where a player is at a server tick
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlayerState {
+    pub id: PlayerId,
+    pub pos: PlayerPositioning,
+    /// Sequence of the last input of theirs the server has applied
+    pub last_input: u32,
+}
+
+/// Authoritative state of everything that moves, sent every server tick
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    pub tick: u64,
+    /// Seconds of simulation on the server when it was taken, clients draw remote entities
+    /// on this clock
+    pub time: f64,
+    pub players: Vec<PlayerState>,
+    pub bodies: Vec<BodyState>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ClientMessage {
+    /// First message once connected, the server ignores everything else until it arrives.
+    /// The server gives the player their id, `info.id` is ignored.
+    Join {
+        info: PlayerInfo,
+    },
+    /// Movement keys and look direction of one fixed tick, numbered from 1 so the server
+    /// can apply them in order and say which ones it has
+    Input {
+        sequence: u32,
+        input: PlayerInput,
+        dir: Quat,
+    },
+    Chat(String),
+}
+
+#[derive(Event, Clone, Debug, Serialize, Deserialize)]
+pub enum ServerMessage {
+    /// Answer to `ClientMessage::Join`, with the player the server spawned for this client
+    /// and the id it gave them
+    Welcome {
+        player: Player,
+    },
+    /// The server is dropping this client, who shouldn't reconnect without fixing `reason`
+    Kick {
+        reason: String,
+    },
+    Snapshot(WorldSnapshot),
+    Chat {
+        from: PlayerUsername,
+        text: String,
+    },
+    SpawnPlayer(Player),
+    DespawnPlayer(PlayerId),
+    SpawnBody {
+        id: NetworkId,
+        body: RigidbodyComponent,
+    },
+    DespawnBody(NetworkId),
+}
+
+impl ClientMessage {
+    /// Inputs are sent every frame, so a lost one is better replaced by the next than resent
+    pub fn channel(&self) -> Channel {
+        match self {
+            ClientMessage::Input { .. } => Channel::Unreliable,
+            ClientMessage::Join { .. } | ClientMessage::Chat(_) => Channel::ReliableOrdered,
+        }
+    }
+
+    pub fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> bincode::Result<Self> {
+        bincode::deserialize(bytes)
+    }
+}
+
+impl ServerMessage {
+    /// Snapshots are sent every tick, so a lost one is better replaced by the next than resent
+    pub fn channel(&self) -> Channel {
+        match self {
+            ServerMessage::Snapshot(_) => Channel::Unreliable,
+            _ => Channel::ReliableOrdered,
+        }
+    }
+
+    pub fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> bincode::Result<Self> {
+        bincode::deserialize(bytes)
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+
+use super::{
+    join::receive_server_packets,
+    prediction::LocalPlayer,
+    protocol::{BodyState, NetworkId, ServerMessage, WorldSnapshot},
+};
+use crate::{
+    physics::{bodies::RigidbodyComponent, kinematic::KinematicTarget},
+    player::{
+        player_data::{Player, PlayerPositioning},
+        player_info::PlayerId,
+    },
+};
+
+/// Snapshots kept per entity at most, a second of them at the server's snapshot rate
+const MAX_SAMPLES: usize = 30;
+/// How much of the gap between the estimated and measured server clock is closed per
+/// snapshot, low so network jitter doesn't shake everything
+const CLOCK_SMOOTHING: f64 = 0.05;
+/// Clock error past which the estimate is reset instead of eased, after a hitch
+const MAX_CLOCK_DRIFT: f64 = 0.5;
+
+/// Draws other players and networked bodies a little in the past, blending between the
+/// snapshots around that time so they move smoothly between server updates
+pub struct RemotePlugin;
+
+impl Plugin for RemotePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InterpolationSettings>()
+            .init_resource::<ServerClock>()
+            .init_resource::<RemoteEntities>()
+            .add_systems(
+                PreUpdate,
+                (spawn_remote_entities, buffer_snapshots)
+                    .chain()
+                    .after(receive_server_packets),
+            )
+            .add_systems(Update, (interpolate_players, interpolate_bodies));
+    }
+}
+
+/// How remote entities are drawn, can be changed while playing
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct InterpolationSettings {
+    /// How far in the past remote entities are drawn. Longer rides out more lost and late
+    /// snapshots, at the cost of showing things later.
+    pub delay: Duration,
+    /// Longest an entity keeps moving on its last velocity once snapshots stop coming,
+    /// before it freezes
+    pub max_extrapolation: Duration,
+}
+
+impl Default for InterpolationSettings {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(100),
+            max_extrapolation: Duration::from_millis(250),
+        }
+    }
+}
+
+/// Estimate of the server's clock, from the time stamped on its snapshots
+#[derive(Resource, Default, Debug)]
+pub struct ServerClock {
+    /// Server time minus local time, `None` until the first snapshot
+    offset: Option<f64>,
+}
+
+impl ServerClock {
+    pub fn now(&self, local: f64) -> Option<f64> {
+        Some(local + self.offset?)
+    }
+
+    fn measure(&mut self, server: f64, local: f64) {
+        let measured = server - local;
+        self.offset = match self.offset {
+            Some(offset) if (measured - offset).abs() < MAX_CLOCK_DRIFT => {
+                Some(offset + (measured - offset) * CLOCK_SMOOTHING)
+            }
+            _ => Some(measured),
+        };
+    }
+}
+
+/// Client entities standing in for the server's players and bodies
+#[derive(Resource, Default, Debug)]
+pub struct RemoteEntities {
+    pub players: HashMap<PlayerId, Entity>,
+    pub bodies: HashMap<NetworkId, Entity>,
+}
+
+/// Another client's player. Kept apart from `Player` so the local simulation leaves them
+/// to the server.
+#[derive(Component, Clone, Debug)]
+pub struct RemotePlayer(pub Player);
+
+/// States that can be blended between snapshots and carried on past the last one
+pub trait Interpolate: Clone {
+    fn interpolate(&self, next: &Self, t: f32) -> Self;
+    fn extrapolate(&self, dt: f32) -> Self;
+}
+
+impl Interpolate for PlayerPositioning {
+    fn interpolate(&self, next: &Self, t: f32) -> Self {
+        let nearest = if t < 0.5 { self } else { next };
+        Self {
+            loc: self.loc.lerp(next.loc, t),
+            dir: self.dir.slerp(next.dir, t),
+            vel: self.vel.lerp(next.vel, t),
+            ..nearest.clone()
+        }
+    }
+
+    fn extrapolate(&self, dt: f32) -> Self {
+        Self {
+            loc: self.loc + self.vel * dt,
+            ..self.clone()
+        }
+    }
+}
+
+impl Interpolate for BodyState {
+    fn interpolate(&self, next: &Self, t: f32) -> Self {
+        let mut state = *self;
+        state.center = self.center.lerp(next.center, t);
+        state.rotation = self.rotation.slerp(next.rotation, t);
+        state.velocity.linear = self.velocity.linear.lerp(next.velocity.linear, t);
+        state.velocity.angular = self.velocity.angular.lerp(next.velocity.angular, t);
+        state
+    }
+
+    fn extrapolate(&self, dt: f32) -> Self {
+        let turn = Quat::from_scaled_axis(self.velocity.angular * dt);
+        let mut state = *self;
+        state.center += self.velocity.linear * dt;
+        state.rotation = (turn * self.rotation).normalize();
+        state
+    }
+}
+
+/// States of a networked entity from the last snapshots, oldest first, each with the
+/// server time it was sent at
+#[derive(Component, Debug)]
+pub struct SnapshotBuffer<T: Interpolate + Send + Sync + 'static> {
+    samples: VecDeque<(f64, T)>,
+}
+
+impl<T: Interpolate + Send + Sync + 'static> Default for SnapshotBuffer<T> {
+    fn default() -> Self {
+        Self {
+            samples: VecDeque::new(),
+        }
+    }
+}
+
+impl<T: Interpolate + Send + Sync + 'static> SnapshotBuffer<T> {
+    /// Add a state, unless one at least as new is already there
+    pub fn push(&mut self, time: f64, state: T) {
+        if self.samples.back().is_some_and(|(last, _)| *last >= time) {
+            return;
+        }
+        self.samples.push_back((time, state));
+        if self.samples.len() > MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+    }
+
+    /// The state at `time`, blended from the two around it. Past the newest it's carried
+    /// on by its velocity for up to `max_extrapolation`, then held.
+    pub fn sample(&mut self, time: f64, max_extrapolation: f64) -> Option<T> {
+        // only the newest state at or before `time` is still needed
+        while self.samples.get(1).is_some_and(|(next, _)| *next <= time) {
+            self.samples.pop_front();
+        }
+
+        let (from_time, from) = self.samples.front()?;
+        let Some((to_time, to)) = self.samples.get(1) else {
+            let ahead = (time - from_time).clamp(0., max_extrapolation);
+            return Some(from.extrapolate(ahead as f32));
+        };
+        let t = ((time - from_time) / (to_time - from_time)).clamp(0., 1.);
+        Some(from.interpolate(to, t as f32))
+    }
+}
+
+fn spawn_remote_entities(
+    mut commands: Commands,
+    mut messages: EventReader<ServerMessage>,
+    mut remote: ResMut<RemoteEntities>,
+    local: Query<&Player, With<LocalPlayer>>,
+) {
+    let local_id = local.single().ok().map(|player| &player.info.id);
+    for message in messages.read() {
+        match message {
+            ServerMessage::SpawnPlayer(player) if Some(&player.info.id) != local_id => {
+                let entity = commands
+                    .spawn((
+                        RemotePlayer(player.clone()),
+                        SnapshotBuffer::<PlayerPositioning>::default(),
+                        Transform::from_translation(player.pos.loc),
+                        Visibility::default(),
+                    ))
+                    .id();
+                if let Some(old) = remote.players.insert(player.info.id.clone(), entity) {
+                    commands.entity(old).despawn();
+                }
+            }
+            ServerMessage::DespawnPlayer(id) => {
+                if let Some(entity) = remote.players.remove(id) {
+                    commands.entity(entity).despawn();
+                }
+            }
+            // the server simulates it, here it's only moved to where the server says
+            ServerMessage::SpawnBody { id, body } => {
+                let collider = body.collider.clone();
+                let target = KinematicTarget::new(collider.center, collider.rotation);
+                let transform =
+                    Transform::from_translation(collider.center).with_rotation(collider.rotation);
+                let entity = commands
+                    .spawn((
+                        *id,
+                        RigidbodyComponent::new_kinematic(collider),
+                        target,
+                        SnapshotBuffer::<BodyState>::default(),
+                        transform,
+                    ))
+                    .id();
+                if let Some(old) = remote.bodies.insert(*id, entity) {
+                    commands.entity(old).despawn();
+                }
+            }
+            ServerMessage::DespawnBody(id) => {
+                if let Some(entity) = remote.bodies.remove(id) {
+                    commands.entity(entity).despawn();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn buffer_snapshots(
+    mut messages: EventReader<ServerMessage>,
+    mut clock: ResMut<ServerClock>,
+    remote: Res<RemoteEntities>,
+    mut players: Query<&mut SnapshotBuffer<PlayerPositioning>>,
+    mut bodies: Query<&mut SnapshotBuffer<BodyState>>,
+    time: Res<Time<Real>>,
+) {
+    let snapshots = messages.read().filter_map(|message| match message {
+        ServerMessage::Snapshot(snapshot) => Some(snapshot),
+        _ => None,
+    });
+    for WorldSnapshot {
+        time: sent,
+        players: states,
+        bodies: body_states,
+        ..
+    } in snapshots
+    {
+        clock.measure(*sent, time.elapsed_secs_f64());
+        for state in states {
+            let buffer = remote.players.get(&state.id);
+            if let Some(mut buffer) = buffer.and_then(|entity| players.get_mut(*entity).ok()) {
+                buffer.push(*sent, state.pos.clone());
+            }
+        }
+        for state in body_states {
+            let buffer = remote.bodies.get(&state.id);
+            if let Some(mut buffer) = buffer.and_then(|entity| bodies.get_mut(*entity).ok()) {
+                buffer.push(*sent, *state);
+            }
+        }
+    }
+}
+
+/// Server time remote entities are drawn at, `None` before the first snapshot
+fn render_time(
+    clock: &ServerClock,
+    settings: &InterpolationSettings,
+    time: &Time<Real>,
+) -> Option<f64> {
+    Some(clock.now(time.elapsed_secs_f64())? - settings.delay.as_secs_f64())
+}
+
+fn interpolate_players(
+    mut query: Query<(
+        &mut RemotePlayer,
+        &mut SnapshotBuffer<PlayerPositioning>,
+        &mut Transform,
+    )>,
+    clock: Res<ServerClock>,
+    settings: Res<InterpolationSettings>,
+    time: Res<Time<Real>>,
+) {
+    let Some(now) = render_time(&clock, &settings, &time) else {
+        return;
+    };
+    let max_extrapolation = settings.max_extrapolation.as_secs_f64();
+    for (mut player, mut buffer, mut transform) in query.iter_mut() {
+        if let Some(pos) = buffer.sample(now, max_extrapolation) {
+            transform.translation = pos.loc;
+            transform.rotation = pos.dir;
+            player.0.pos = pos;
+        }
+    }
+}
+
+/// Aim networked bodies at their interpolated pose, the physics step moves them there so
+/// the local player still collides with and rides on them
+fn interpolate_bodies(
+    mut query: Query<(&mut SnapshotBuffer<BodyState>, &mut KinematicTarget)>,
+    clock: Res<ServerClock>,
+    settings: Res<InterpolationSettings>,
+    time: Res<Time<Real>>,
+) {
+    let Some(now) = render_time(&clock, &settings, &time) else {
+        return;
+    };
+    let max_extrapolation = settings.max_extrapolation.as_secs_f64();
+    for (mut buffer, mut target) in query.iter_mut() {
+        if let Some(state) = buffer.sample(now, max_extrapolation) {
+            *target = KinematicTarget::new(state.center, state.rotation);
+        }
+    }
+}
@@ -0,0 +1,610 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use renet::{ChannelConfig, ClientId, ConnectionConfig, RenetClient, RenetServer, SendType};
+use serde::{Deserialize, Serialize};
+
+use super::protocol::PROTOCOL_VERSION;
+
+/// Sent with every connection request, so requests from other programs are ignored
+pub const PROTOCOL_ID: u64 = 0x7a67_0001;
+pub const DEFAULT_PORT: u16 = 5000;
+/// Connections a server accepts at once unless told otherwise
+pub const DEFAULT_MAX_CLIENTS: usize = 32;
+
+/// Largest datagram sent or received, renet keeps its packets below this
+const MAX_PACKET_BYTES: usize = 1400;
+/// A connection that hasn't heard anything for this long is dropped
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Time between connection requests while the handshake is in progress
+const CONNECT_RESEND: Duration = Duration::from_millis(250);
+/// Time after which an idle connection sends a keep-alive, so the other end doesn't time out
+const KEEP_ALIVE: Duration = Duration::from_millis(500);
+const CHANNEL_MEMORY_BYTES: usize = 5 * 1024 * 1024;
+const RESEND_TIME: Duration = Duration::from_millis(300);
+
+/// Message channels, the same in both directions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Delivered once each, in the order sent, e.g. chat and spawns
+    ReliableOrdered,
+    /// Delivered once each, in any order
+    ReliableUnordered,
+    /// May be dropped or arrive out of order, e.g. inputs and state updates
+    Unreliable,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 3] = [
+        Channel::ReliableOrdered,
+        Channel::ReliableUnordered,
+        Channel::Unreliable,
+    ];
+
+    fn config(self) -> ChannelConfig {
+        let send_type = match self {
+            Channel::ReliableOrdered => SendType::ReliableOrdered {
+                resend_time: RESEND_TIME,
+            },
+            Channel::ReliableUnordered => SendType::ReliableUnordered {
+                resend_time: RESEND_TIME,
+            },
+            Channel::Unreliable => SendType::Unreliable,
+        };
+        ChannelConfig {
+            channel_id: self.into(),
+            max_memory_usage_bytes: CHANNEL_MEMORY_BYTES,
+            send_type,
+        }
+    }
+}
+
+impl From<Channel> for u8 {
+    fn from(channel: Channel) -> Self {
+        match channel {
+            Channel::ReliableOrdered => 0,
+            Channel::ReliableUnordered => 1,
+            Channel::Unreliable => 2,
+        }
+    }
+}
+
+/// Renet configuration shared by the client and the server
+pub fn connection_config() -> ConnectionConfig {
+    let channels: Vec<ChannelConfig> = Channel::ALL.into_iter().map(Channel::config).collect();
+    ConnectionConfig {
+        server_channels_config: channels.clone(),
+        client_channels_config: channels,
+        ..Default::default()
+    }
+}
+
+/// Why the server refused a connection. Only ever appended to, so any build can read it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Refusal {
+    VersionMismatch { server: u32, client: u32 },
+    ServerFull,
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refusal::VersionMismatch { server, client } => write!(
+                f,
+                "server runs protocol version {server}, this build runs {client}"
+            ),
+            Refusal::ServerFull => write!(f, "server is full"),
+        }
+    }
+}
+
+/// Datagrams exchanged over the socket. Renet's own packets travel as `Payload`s once the
+/// handshake is done.
+///
+/// The request and refusal come first and keep their shape across protocol versions, so
+/// mismatched builds can still tell each other why they can't talk.
+#[derive(Debug, Serialize, Deserialize)]
+enum Packet<'a> {
+    ConnectionRequest { protocol_id: u64, version: u32 },
+    ConnectionDenied(Refusal),
+    ConnectionAccepted { client_id: ClientId },
+    KeepAlive,
+    Payload(#[serde(borrow)] &'a [u8]),
+    Disconnect,
+}
+
+fn send_packet(socket: &UdpSocket, addr: SocketAddr, packet: &Packet) -> io::Result<()> {
+    let bytes = bincode::serialize(packet).map_err(io::Error::other)?;
+    socket.send_to(&bytes, addr)?;
+    Ok(())
+}
+
+/// Read the next datagram off a non-blocking socket into `buffer`, giving its length and
+/// sender, `None` once there are none left
+fn receive_datagram(
+    socket: &UdpSocket,
+    buffer: &mut [u8],
+) -> io::Result<Option<(usize, SocketAddr)>> {
+    loop {
+        match socket.recv_from(buffer) {
+            Ok(received) => return Ok(Some(received)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            // windows reports an earlier send to a closed port on the next receive
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// UDP transport of a `RenetClient`. Call `update` before reading messages each frame and
+/// `send_packets` after writing them.
+#[derive(Debug)]
+pub struct ClientTransport {
+    socket: UdpSocket,
+    server_addr: SocketAddr,
+    /// Protocol version asked for in the handshake, `PROTOCOL_VERSION` but for tests
+    version: u32,
+    client_id: Option<ClientId>,
+    refusal: Option<Refusal>,
+    time: Duration,
+    last_received: Duration,
+    /// `None` until the first packet goes out
+    last_sent: Option<Duration>,
+    /// The server has been told about the disconnect
+    disconnect_sent: bool,
+    buffer: Vec<u8>,
+}
+
+impl ClientTransport {
+    pub fn new(server_addr: SocketAddr) -> io::Result<Self> {
+        let local: SocketAddr = match server_addr {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            server_addr,
+            version: PROTOCOL_VERSION,
+            client_id: None,
+            refusal: None,
+            time: Duration::ZERO,
+            last_received: Duration::ZERO,
+            last_sent: None,
+            disconnect_sent: false,
+            buffer: vec![0; MAX_PACKET_BYTES],
+        })
+    }
+
+    /// Id the server gave this client, once connected
+    pub fn client_id(&self) -> Option<ClientId> {
+        self.client_id
+    }
+
+    /// Why the server refused to connect, if it did
+    pub fn refusal(&self) -> Option<Refusal> {
+        self.refusal
+    }
+
+    pub fn server_addr(&self) -> SocketAddr {
+        self.server_addr
+    }
+
+    /// Receive pending packets into `client` and advance it by `dt`
+    pub fn update(&mut self, dt: Duration, client: &mut RenetClient) -> io::Result<()> {
+        self.time += dt;
+        if client.is_disconnected() {
+            return Ok(());
+        }
+
+        while let Some((len, addr)) = receive_datagram(&self.socket, &mut self.buffer)? {
+            if addr != self.server_addr {
+                continue;
+            }
+            let Ok(packet) = bincode::deserialize(&self.buffer[..len]) else {
+                continue;
+            };
+            self.last_received = self.time;
+            match packet {
+                Packet::ConnectionAccepted { client_id } => {
+                    if client.is_connecting() {
+                        self.client_id = Some(client_id);
+                        client.set_connected();
+                    }
+                }
+                Packet::ConnectionDenied(refusal) => {
+                    if client.is_connecting() {
+                        self.refusal = Some(refusal);
+                        self.disconnect_sent = true;
+                        client.disconnect_due_to_transport();
+                    }
+                }
+                Packet::Disconnect => {
+                    // nothing left to tell the server
+                    self.disconnect_sent = true;
+                    client.disconnect_due_to_transport();
+                }
+                Packet::Payload(payload) => {
+                    if client.is_connected() {
+                        client.process_packet(payload);
+                    }
+                }
+                Packet::KeepAlive | Packet::ConnectionRequest { .. } => {}
+            }
+        }
+
+        if self.time.saturating_sub(self.last_received) > TIMEOUT {
+            client.disconnect_due_to_transport();
+        }
+        client.update(dt);
+        Ok(())
+    }
+
+    /// Send the packets `client` has queued, along with handshake and keep-alive packets
+    pub fn send_packets(&mut self, client: &mut RenetClient) -> io::Result<()> {
+        if client.is_disconnected() {
+            if !self.disconnect_sent {
+                self.disconnect_sent = true;
+                send_packet(&self.socket, self.server_addr, &Packet::Disconnect)?;
+            }
+            return Ok(());
+        }
+
+        let since_sent = self.last_sent.map(|sent| self.time - sent);
+        if client.is_connecting() {
+            if since_sent.is_none_or(|since| since >= CONNECT_RESEND) {
+                self.send(&Packet::ConnectionRequest {
+                    protocol_id: PROTOCOL_ID,
+                    version: self.version,
+                })?;
+            }
+            return Ok(());
+        }
+
+        let payloads = client.get_packets_to_send();
+        for payload in payloads.iter() {
+            self.send(&Packet::Payload(payload))?;
+        }
+        if payloads.is_empty() && since_sent.is_none_or(|since| since >= KEEP_ALIVE) {
+            self.send(&Packet::KeepAlive)?;
+        }
+        Ok(())
+    }
+
+    /// Disconnect `client` and tell the server right away
+    pub fn disconnect(&mut self, client: &mut RenetClient) -> io::Result<()> {
+        client.disconnect();
+        self.send_packets(client)
+    }
+
+    fn send(&mut self, packet: &Packet) -> io::Result<()> {
+        self.last_sent = Some(self.time);
+        send_packet(&self.socket, self.server_addr, packet)
+    }
+}
+
+#[derive(Debug)]
+struct ClientEndpoint {
+    id: ClientId,
+    last_received: Duration,
+    last_sent: Duration,
+}
+
+/// UDP transport of a `RenetServer`, accepting clients that complete the handshake. Call
+/// `update` before reading messages each tick and `send_packets` after writing them.
+#[derive(Debug)]
+pub struct ServerTransport {
+    socket: UdpSocket,
+    clients: HashMap<SocketAddr, ClientEndpoint>,
+    /// Connections kept at most, requests past it are refused
+    max_clients: usize,
+    next_client_id: ClientId,
+    time: Duration,
+    buffer: Vec<u8>,
+}
+
+impl ServerTransport {
+    pub fn new(addr: SocketAddr) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            clients: HashMap::new(),
+            max_clients: DEFAULT_MAX_CLIENTS,
+            next_client_id: 1,
+            time: Duration::ZERO,
+            buffer: vec![0; MAX_PACKET_BYTES],
+        })
+    }
+
+    pub fn with_max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = max_clients;
+        self
+    }
+
+    /// Address the server is bound to, with the port picked by the OS when bound to port 0
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn client_addr(&self, client_id: ClientId) -> Option<SocketAddr> {
+        self.clients
+            .iter()
+            .find(|(_, client)| client.id == client_id)
+            .map(|(addr, _)| *addr)
+    }
+
+    /// Receive pending packets into `server`, adding and removing connections, then advance
+    /// it by `dt`
+    pub fn update(&mut self, dt: Duration, server: &mut RenetServer) -> io::Result<()> {
+        self.time += dt;
+
+        while let Some((len, addr)) = receive_datagram(&self.socket, &mut self.buffer)? {
+            // anything that doesn't parse isn't one of ours
+            let Ok(packet) = bincode::deserialize(&self.buffer[..len]) else {
+                continue;
+            };
+            match packet {
+                Packet::ConnectionRequest {
+                    protocol_id,
+                    version,
+                } => {
+                    if protocol_id != PROTOCOL_ID {
+                        continue;
+                    }
+                    if version != PROTOCOL_VERSION {
+                        let refusal = Refusal::VersionMismatch {
+                            server: PROTOCOL_VERSION,
+                            client: version,
+                        };
+                        send_packet(&self.socket, addr, &Packet::ConnectionDenied(refusal))?;
+                        continue;
+                    }
+                    if !self.clients.contains_key(&addr) && self.clients.len() >= self.max_clients {
+                        let refusal = Packet::ConnectionDenied(Refusal::ServerFull);
+                        send_packet(&self.socket, addr, &refusal)?;
+                        continue;
+                    }
+                    let client = self.clients.entry(addr).or_insert_with(|| {
+                        let id = self.next_client_id;
+                        self.next_client_id += 1;
+                        server.add_connection(id);
+                        ClientEndpoint {
+                            id,
+                            last_received: self.time,
+                            last_sent: self.time,
+                        }
+                    });
+                    client.last_received = self.time;
+                    client.last_sent = self.time;
+                    // answered every time, in case an earlier answer was lost
+                    let accepted = Packet::ConnectionAccepted {
+                        client_id: client.id,
+                    };
+                    send_packet(&self.socket, addr, &accepted)?;
+                }
+                Packet::Payload(payload) => {
+                    if let Some(client) = self.clients.get_mut(&addr) {
+                        client.last_received = self.time;
+                        // the connection can only be missing if it was just removed
+                        let _ = server.process_packet_from(payload, client.id);
+                    }
+                }
+                Packet::KeepAlive => {
+                    if let Some(client) = self.clients.get_mut(&addr) {
+                        client.last_received = self.time;
+                    }
+                }
+                Packet::Disconnect => {
+                    if let Some(client) = self.clients.remove(&addr) {
+                        server.remove_connection(client.id);
+                    }
+                }
+                Packet::ConnectionAccepted { .. } | Packet::ConnectionDenied(_) => {}
+            }
+        }
+
+        let time = self.time;
+        self.clients.retain(|_, client| {
+            let alive = time.saturating_sub(client.last_received) <= TIMEOUT;
+            if !alive {
+                server.remove_connection(client.id);
+            }
+            alive
+        });
+        server.update(dt);
+        Ok(())
+    }
+
+    /// Send the packets `server` has queued for each client, and tell clients the server
+    /// disconnected that they're gone
+    pub fn send_packets(&mut self, server: &mut RenetServer) -> io::Result<()> {
+        let mut gone = Vec::new();
+        for (addr, client) in self.clients.iter_mut() {
+            if !server.is_connected(client.id) {
+                send_packet(&self.socket, *addr, &Packet::Disconnect)?;
+                gone.push(*addr);
+                continue;
+            }
+            let Ok(payloads) = server.get_packets_to_send(client.id) else {
+                continue;
+            };
+            for payload in payloads.iter() {
+                send_packet(&self.socket, *addr, &Packet::Payload(payload))?;
+            }
+            if !payloads.is_empty() {
+                client.last_sent = self.time;
+            } else if self.time - client.last_sent >= KEEP_ALIVE {
+                client.last_sent = self.time;
+                send_packet(&self.socket, *addr, &Packet::KeepAlive)?;
+            }
+        }
+
+        for addr in gone {
+            if let Some(client) = self.clients.remove(&addr) {
+                server.remove_connection(client.id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Disconnect every client and tell them right away, before shutting down
+    pub fn disconnect_all(&mut self, server: &mut RenetServer) -> io::Result<()> {
+        server.disconnect_all();
+        self.send_packets(server)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use renet::ServerEvent;
+
+    use super::*;
+
+    const STEP: Duration = Duration::from_millis(5);
+
+    /// A server and a client talking over the loopback interface
+    struct Loopback {
+        server: RenetServer,
+        server_transport: ServerTransport,
+        client: RenetClient,
+        client_transport: ClientTransport,
+        events: Vec<ServerEvent>,
+    }
+
+    impl Loopback {
+        fn new() -> Self {
+            let server_transport = ServerTransport::new((Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+            let server_addr = server_transport.local_addr().unwrap();
+            Self {
+                server: RenetServer::new(connection_config()),
+                server_transport,
+                client: RenetClient::new(connection_config()),
+                client_transport: ClientTransport::new(server_addr).unwrap(),
+                events: Vec::new(),
+            }
+        }
+
+        /// Exchange packets both ways until `done` holds, for at most a second
+        fn pump_until(&mut self, mut done: impl FnMut(&mut Self) -> bool) -> bool {
+            for _ in 0..200 {
+                self.client_transport
+                    .update(STEP, &mut self.client)
+                    .unwrap();
+                self.server_transport
+                    .update(STEP, &mut self.server)
+                    .unwrap();
+                while let Some(event) = self.server.get_event() {
+                    self.events.push(event);
+                }
+                if done(self) {
+                    return true;
+                }
+                self.client_transport
+                    .send_packets(&mut self.client)
+                    .unwrap();
+                self.server_transport
+                    .send_packets(&mut self.server)
+                    .unwrap();
+                thread::sleep(STEP);
+            }
+            false
+        }
+
+        fn client_id(&self) -> Option<ClientId> {
+            self.events.iter().find_map(|event| match event {
+                ServerEvent::ClientConnected { client_id } => Some(*client_id),
+                _ => None,
+            })
+        }
+    }
+
+    #[test]
+    fn messages_cross_every_channel_both_ways() {
+        let mut net = Loopback::new();
+        assert!(net.pump_until(|net| net.client.is_connected() && net.client_id().is_some()));
+        let client_id = net.client_id().unwrap();
+        assert_eq!(net.client_transport.client_id(), Some(client_id));
+
+        for channel in Channel::ALL {
+            let id = u8::from(channel);
+            net.client.send_message(channel, vec![id]);
+            net.server.send_message(client_id, channel, vec![id + 10]);
+        }
+        let mut to_server = Vec::new();
+        let mut to_client = Vec::new();
+        let all_arrived = net.pump_until(|net| {
+            for channel in Channel::ALL {
+                while let Some(bytes) = net.server.receive_message(client_id, channel) {
+                    to_server.push((u8::from(channel), bytes.to_vec()));
+                }
+                while let Some(bytes) = net.client.receive_message(channel) {
+                    to_client.push((u8::from(channel), bytes.to_vec()));
+                }
+            }
+            to_server.len() == Channel::ALL.len() && to_client.len() == Channel::ALL.len()
+        });
+        assert!(
+            all_arrived,
+            "to server {to_server:?}, to client {to_client:?}"
+        );
+        for (channel, bytes) in to_server {
+            assert_eq!(bytes, vec![channel]);
+        }
+        for (channel, bytes) in to_client {
+            assert_eq!(bytes, vec![channel + 10]);
+        }
+    }
+
+    #[test]
+    fn server_sees_clients_come_and_go() {
+        let mut net = Loopback::new();
+        assert!(net.pump_until(|net| net.client_id().is_some()));
+        let client_id = net.client_id().unwrap();
+
+        net.client_transport.disconnect(&mut net.client).unwrap();
+        let disconnected = net.pump_until(|net| {
+            net.events.iter().any(|event| {
+                matches!(event, ServerEvent::ClientDisconnected { client_id: id, .. } if *id == client_id)
+            })
+        });
+        assert!(disconnected);
+        assert!(net.server_transport.client_addr(client_id).is_none());
+    }
+
+    #[test]
+    fn other_protocol_versions_are_refused() {
+        let mut net = Loopback::new();
+        net.client_transport.version = PROTOCOL_VERSION + 1;
+        assert!(net.pump_until(|net| net.client.is_disconnected()));
+        assert_eq!(
+            net.client_transport.refusal(),
+            Some(Refusal::VersionMismatch {
+                server: PROTOCOL_VERSION,
+                client: PROTOCOL_VERSION + 1,
+            })
+        );
+        assert!(net.events.is_empty());
+    }
+
+    #[test]
+    fn full_servers_refuse_new_clients() {
+        let mut net = Loopback::new();
+        net.server_transport = ServerTransport::new((Ipv4Addr::LOCALHOST, 0).into())
+            .unwrap()
+            .with_max_clients(0);
+        let server_addr = net.server_transport.local_addr().unwrap();
+        net.client_transport = ClientTransport::new(server_addr).unwrap();
+
+        assert!(net.pump_until(|net| net.client.is_disconnected()));
+        assert_eq!(net.client_transport.refusal(), Some(Refusal::ServerFull));
+        assert!(net.events.is_empty());
+    }
+}
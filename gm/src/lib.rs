@@ -4,4 +4,5 @@ pub mod physics;
 pub mod ui;
 pub mod startscreen;
 pub mod gamestate;
-pub mod connection;
\ No newline at end of file
+pub mod connection;
+pub mod terrain;
\ No newline at end of file
@@ -3,12 +3,14 @@ pub mod gamestate;
 pub mod items;
 pub mod physics;
 pub mod player;
+pub mod terrain;
 pub mod ui;
 use bevy::{prelude::*, window::PresentMode};
 use connection::join::MPlayerPlugin;
-use gamestate::GameStatePlugin;
-use physics::prelude::ZphyPlugin;
+use gamestate::{AppState, GameStatePlugin};
+use physics::prelude::{PhysicsDebugPlugin, ZphyPlugin};
 use player::PlayerPlugin;
+use terrain::TerrainPlugin;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     App::new()
@@ -23,8 +25,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }))
         .add_plugins(MPlayerPlugin)
         .add_plugins(GameStatePlugin)
-        .add_plugins(ZphyPlugin)
+        // join the server before playing, `check_connected` moves on once it answers
+        .insert_state(AppState::Loading)
+        .add_plugins(ZphyPlugin::default())
+        .add_plugins(PhysicsDebugPlugin)
         .add_plugins(PlayerPlugin)
+        .add_plugins(TerrainPlugin)
         .run();
     Ok(())
 }
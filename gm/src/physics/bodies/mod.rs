@@ -1,18 +1,26 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::gamestate::AppState;
-
-use super::prelude::*;
+use super::{
+    forces::{ExternalForce, ExternalImpulse, Gravity},
+    prelude::*,
+};
 
 pub struct RigidBodyPlugin;
 
 impl Plugin for RigidBodyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, apply_forces.run_if(in_state(AppState::Playing)));
+        app.init_resource::<Gravity>()
+            .add_observer(place_at_transform)
+            .add_systems(FixedUpdate, apply_forces.in_set(PhysicsSet::Solve))
+            .add_systems(
+                FixedUpdate,
+                integrate_positions.in_set(PhysicsSet::Integrate),
+            );
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum RigidbodyType {
     Static,
     Dynamic,
@@ -29,7 +37,7 @@ impl From<RigidbodyComponent> for RigidbodyType {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Velocity {
     pub linear: Vec3,
     pub angular: Vec3,
@@ -52,14 +60,16 @@ impl Velocity {
     };
 }
 
-#[derive(Clone, Default, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub enum RigidBodyState {
+    /// Resting, skipped by integration and the narrowphase until something wakes it
     Asleep,
     #[default]
     Awake,
 }
 
-#[derive(Copy, Clone)]
+/// Exponential velocity decay rates per second, see `linear_factor`
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Damping {
     pub linear: f32,
     pub angular: f32,
@@ -68,13 +78,27 @@ pub struct Damping {
 impl Default for Damping {
     fn default() -> Self {
         Self {
-            linear: 0.05,
-            angular: 0.05,
+            linear: 3.,
+            angular: 3.,
         }
     }
 }
 
-#[derive(Component, Clone)]
+impl Damping {
+    /// Factor to scale linear velocity by over a step of `dt` seconds
+    pub fn linear_factor(&self, dt: f32) -> f32 {
+        (-self.linear * dt).exp()
+    }
+
+    /// Factor to scale angular velocity by over a step of `dt` seconds
+    pub fn angular_factor(&self, dt: f32) -> f32 {
+        (-self.angular * dt).exp()
+    }
+}
+
+/// A body starts at its entity's `Transform` when it has one. From then on the collider
+/// owns the pose, and interpolation writes it back to the `Transform` of moving bodies.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct RigidbodyComponent {
     pub state: RigidBodyState,
     pub rbt: RigidbodyType,
@@ -82,10 +106,18 @@ pub struct RigidbodyComponent {
     pub velocity: Velocity,
     pub inverse_mass: f32,
     pub friction: f32,
+    /// Constant torque turning the body every step, in world space
     pub torque: Vec3,
     pub damping: Damping,
     pub inverse_inertia_tensor: Mat3,
     pub restitution: f32,
+    /// Seconds the body has been moving slower than the sleep thresholds
+    pub sleep_timer: f32,
+    /// Sweep the body along its velocity every step so it can't tunnel through thin
+    /// colliders, see `ccd`. Only dynamic bodies are swept.
+    pub ccd: bool,
+    /// Multiplier of `Gravity` for this body, 0 to let it float
+    pub gravity_scale: f32,
 }
 
 fn cube_inertia_tensor(mass: f32, size: Vec3) -> Mat3 {
@@ -100,6 +132,37 @@ fn cube_inertia_tensor(mass: f32, size: Vec3) -> Mat3 {
     Mat3::from_diagonal(Vec3::new(ix, iy, iz))
 }
 
+fn sphere_inertia_tensor(mass: f32, radius: f32) -> Mat3 {
+    Mat3::from_diagonal(Vec3::splat(0.4 * mass * radius * radius))
+}
+
+fn ellipsoid_inertia_tensor(mass: f32, radii: Vec3) -> Mat3 {
+    let Vec3 { x: a, y: b, z: c } = radii;
+
+    let ix = 0.2 * mass * (b * b + c * c);
+    let iy = 0.2 * mass * (a * a + c * c);
+    let iz = 0.2 * mass * (a * a + b * b);
+
+    Mat3::from_diagonal(Vec3::new(ix, iy, iz))
+}
+
+/// Capsule along local Y, treated as a cylinder plus two hemispheres of equal density
+fn capsule_inertia_tensor(mass: f32, radius: f32, half_length: f32) -> Mat3 {
+    let r = radius;
+    let h = half_length * 2.;
+
+    let cylinder_volume = std::f32::consts::PI * r * r * h;
+    let spheres_volume = 4. / 3. * std::f32::consts::PI * r * r * r;
+    let cylinder_mass = mass * cylinder_volume / (cylinder_volume + spheres_volume);
+    let spheres_mass = mass - cylinder_mass;
+
+    let iy = cylinder_mass * r * r * 0.5 + spheres_mass * 0.4 * r * r;
+    let ix = cylinder_mass * (h * h / 12. + r * r / 4.)
+        + spheres_mass * (0.4 * r * r + h * h / 4. + 3. * h * r / 8.);
+
+    Mat3::from_diagonal(Vec3::new(ix, iy, ix))
+}
+
 impl RigidbodyComponent {
     #[allow(clippy::too_many_arguments)]
     pub fn new_dynamic(
@@ -112,10 +175,19 @@ impl RigidbodyComponent {
         damping: Damping,
         restitution: f32,
     ) -> Self {
-        let mut inertia_tensor = Mat3::ZERO;
-        if collider.collider_shape == ColliderShape::Cuboid {
-            inertia_tensor = cube_inertia_tensor(mass, collider.half_extents * 2.);
-        }
+        let inertia_tensor = match collider.collider_shape {
+            ColliderShape::Cuboid => cube_inertia_tensor(mass, collider.half_extents * 2.),
+            ColliderShape::Sphere => sphere_inertia_tensor(mass, collider.radius()),
+            ColliderShape::Capsule => {
+                capsule_inertia_tensor(mass, collider.radius(), collider.capsule_half_length())
+            }
+            ColliderShape::Ellipsoid => ellipsoid_inertia_tensor(mass, collider.half_extents),
+            // approximated by the bounding box, around the collider's center
+            ColliderShape::ConvexHull
+            | ColliderShape::TriMesh
+            | ColliderShape::Compound
+            | ColliderShape::Heightfield => cube_inertia_tensor(mass, collider.half_extents * 2.),
+        };
 
         Self {
             state: RigidBodyState::Awake,
@@ -128,6 +200,9 @@ impl RigidbodyComponent {
             damping,
             inverse_inertia_tensor: inertia_tensor.inverse(),
             restitution,
+            sleep_timer: 0.,
+            ccd: false,
+            gravity_scale: 1.,
         }
     }
 
@@ -136,67 +211,181 @@ impl RigidbodyComponent {
             state: RigidBodyState::Awake,
             rbt: RigidbodyType::Static,
             inverse_mass: 0.,
-            friction: 0.,
+            friction: 0.5,
             velocity: Velocity::new(Vec3::ZERO, Vec3::ZERO),
             torque: Vec3::ZERO,
             damping: Damping::default(),
             inverse_inertia_tensor: Mat3::ZERO,
             restitution: 0.,
             collider,
+            sleep_timer: 0.,
+            ccd: false,
+            gravity_scale: 1.,
         }
     }
 
-    pub fn new_kinematic(mass: f32, collider: Collider) -> Self {
+    /// Body moved only by its velocity or a `KinematicTarget`. Gravity and contacts don't
+    /// affect it, it pushes everything else as if infinitely heavy.
+    pub fn new_kinematic(collider: Collider) -> Self {
         Self {
             state: RigidBodyState::Awake,
             rbt: RigidbodyType::Kinematic,
-            inverse_mass: 1. / mass,
-            friction: 0.1,
+            inverse_mass: 0.,
+            friction: 0.5,
             collider,
             velocity: Velocity::ZERO,
             torque: Vec3::ZERO,
             damping: Damping::default(),
             inverse_inertia_tensor: Mat3::ZERO,
             restitution: 0.,
+            sleep_timer: 0.,
+            ccd: false,
+            gravity_scale: 1.,
         }
     }
 
+    /// Turn on continuous collision detection, for bullets and other fast bodies
+    pub fn with_ccd(mut self) -> Self {
+        self.ccd = true;
+        self
+    }
+
+    pub fn with_gravity_scale(mut self, gravity_scale: f32) -> Self {
+        self.gravity_scale = gravity_scale;
+        self
+    }
+
+    pub fn is_sleeping(&self) -> bool {
+        self.state == RigidBodyState::Asleep
+    }
+
+    /// Whether the body is guaranteed not to move this step
+    pub fn is_resting(&self) -> bool {
+        self.rbt == RigidbodyType::Static || self.is_sleeping()
+    }
+
+    pub fn wake_up(&mut self) {
+        self.state = RigidBodyState::Awake;
+        self.sleep_timer = 0.;
+    }
+
+    /// Put the body to rest, dropping whatever velocity it had left
+    pub fn sleep(&mut self) {
+        self.state = RigidBodyState::Asleep;
+        self.velocity = Velocity::ZERO;
+    }
+
     pub fn get_inverse_inertia_world(&self, rotation: &Quat) -> Mat3 {
         let rot_mat = Mat3::from_quat(*rotation);
         rot_mat * self.inverse_inertia_tensor * rot_mat.transpose()
     }
+
+    /// How far a point riding on the body was carried by its last step of `dt` seconds
+    pub fn point_motion(&self, point: Vec3, dt: f32) -> Vec3 {
+        if self.is_resting() {
+            return Vec3::ZERO;
+        }
+        let previous_center = self.collider.center - self.velocity.linear * dt;
+        let turn = Quat::from_scaled_axis(self.velocity.angular * dt);
+        self.collider.center + turn * (point - previous_center) - point
+    }
+
+    /// Change the body's momentum through its center, doesn't wake it
+    pub fn apply_impulse(&mut self, impulse: Vec3) {
+        self.velocity.linear += impulse * self.inverse_mass;
+    }
+
+    /// Change the body's momentum at a world space `point`, spinning it through its
+    /// inertia tensor, doesn't wake it
+    pub fn apply_impulse_at_point(&mut self, impulse: Vec3, point: Vec3) {
+        self.apply_impulse(impulse);
+        self.apply_angular_impulse((point - self.collider.center).cross(impulse));
+    }
+
+    /// Change the body's angular momentum, in world space, doesn't wake it
+    pub fn apply_angular_impulse(&mut self, angular_impulse: Vec3) {
+        let inverse_inertia = self.get_inverse_inertia_world(&self.collider.rotation);
+        self.velocity.angular += inverse_inertia * angular_impulse;
+    }
 }
 
-fn apply_forces(mut query: Query<(&mut RigidbodyComponent, &mut Transform)>, time: Res<Time>) {
-    let gravity = -9.81;
+/// Advance velocities by gravity, external forces and damping, ahead of the constraint
+/// solver. Only dynamic bodies are pushed, and pushing a sleeping one wakes it.
+pub(crate) fn apply_forces(
+    mut query: Query<(
+        &mut RigidbodyComponent,
+        Option<&ExternalForce>,
+        Option<&mut ExternalImpulse>,
+    )>,
+    gravity: Res<Gravity>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
 
-    for (mut body, mut transform) in query.iter_mut() {
-        if body.rbt == RigidbodyType::Static {
+    for (mut body, force, impulse) in query.iter_mut() {
+        if body.rbt != RigidbodyType::Dynamic {
             continue;
         }
 
-        let linear_damping = body.damping.linear;
-        let angular_damping = body.damping.angular;
-        body.velocity.linear *= 1.0 - linear_damping;
-        body.velocity.angular *= 1.0 - angular_damping;
-        let inverse_mass = body.inverse_mass;
-        body.velocity.linear.y += gravity * (1. / inverse_mass) * time.delta_secs();
+        let force = force.copied().unwrap_or_default();
+        let impulse = impulse
+            .filter(|impulse| !impulse.is_zero())
+            .map(|mut impulse| std::mem::take(&mut *impulse))
+            .unwrap_or_default();
+        if body.is_sleeping() {
+            if force.is_zero() && impulse.is_zero() && body.torque == Vec3::ZERO {
+                continue;
+            }
+            body.wake_up();
+        }
+
+        let linear_damping = body.damping.linear_factor(dt);
+        let angular_damping = body.damping.angular_factor(dt);
+        body.velocity.linear *= linear_damping;
+        body.velocity.angular *= angular_damping;
+
+        let acceleration = gravity.0 * body.gravity_scale + force.force * body.inverse_mass;
+        body.velocity.linear += acceleration * dt;
+        body.apply_impulse(impulse.impulse);
+        let torque = force.torque + body.torque;
+        body.apply_angular_impulse(torque * dt + impulse.angular_impulse);
+    }
+}
+
+fn place_at_transform(
+    trigger: Trigger<OnAdd, RigidbodyComponent>,
+    mut query: Query<(&mut RigidbodyComponent, &Transform)>,
+) {
+    let Ok((mut body, transform)) = query.get_mut(trigger.target()) else {
+        return;
+    };
+    body.collider.center = transform.translation;
+    body.collider.rotation = transform.rotation;
+    body.collider.update_geometry();
+}
+
+/// Move bodies by their solved velocities
+pub(crate) fn integrate_positions(mut query: Query<&mut RigidbodyComponent>, time: Res<Time>) {
+    let dt = time.delta_secs();
 
-        transform.translation.x += body.velocity.linear.x * time.delta_secs();
-        transform.translation.z += body.velocity.linear.z * time.delta_secs();
-        transform.translation.y += body.velocity.linear.y * time.delta_secs();
+    for mut body in query.iter_mut() {
+        if body.rbt == RigidbodyType::Static || body.is_sleeping() {
+            continue;
+        }
 
+        let linear_velocity = body.velocity.linear;
+        body.collider.center += linear_velocity * dt;
+
+        // kinematic bodies have to land exactly on their target, however slowly they turn
+        let min_angular_speed = match body.rbt {
+            RigidbodyType::Kinematic => 0.,
+            _ => 0.01,
+        };
         let angular_speed = body.velocity.angular.length();
-        if angular_speed > 0.01 {
+        if angular_speed > min_angular_speed {
             let rotation_axis = body.velocity.angular.normalize();
-            let delta_rotation =
-                Quat::from_axis_angle(rotation_axis, angular_speed * time.delta_secs());
+            let delta_rotation = Quat::from_axis_angle(rotation_axis, angular_speed * dt);
             body.collider.rotation = (delta_rotation * body.collider.rotation).normalize();
         }
-
-        body.collider.center = transform.translation;
-        if body.rbt == RigidbodyType::Dynamic {
-            transform.rotation = body.collider.rotation;
-        }
     }
 }
@@ -0,0 +1,150 @@
+use bevy::prelude::*;
+
+use super::{
+    bodies::{RigidbodyComponent, RigidbodyType, integrate_positions},
+    collisions::{
+        Collider,
+        broadphase::{BroadPhase, update_query_proxies},
+    },
+    layers::{CollisionLayers, LayerMask},
+    prelude::PhysicsSet,
+    spatial_query::{SpatialQuery, SpatialQueryFilter},
+};
+
+/// Gap left between a swept body and what it hit, so the next step starts outside it
+const CCD_SKIN: f32 = 0.01;
+/// Most surfaces a CCD body slides along in one step before stopping
+const CCD_MAX_SWEEPS: usize = 4;
+
+pub struct CcdPlugin;
+
+impl Plugin for CcdPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CcdStarts>().add_systems(
+            FixedUpdate,
+            (
+                record_ccd_starts.before(integrate_positions),
+                sweep_ccd_bodies.after(update_query_proxies),
+            )
+                .in_set(PhysicsSet::Integrate),
+        );
+    }
+}
+
+/// Where each CCD body was before `integrate_positions` moved it this step
+#[derive(Resource, Default)]
+struct CcdStarts(Vec<(Entity, Vec3)>);
+
+/// A CCD body swept along its path, ready to be moved
+struct Sweep {
+    entity: Entity,
+    collider: Collider,
+    motion: Vec3,
+    velocity: Vec3,
+    restitution: f32,
+    filter: SpatialQueryFilter,
+}
+
+type BodyQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static RigidbodyComponent,
+        Option<&'static CollisionLayers>,
+    ),
+>;
+
+fn record_ccd_starts(query: Query<(Entity, &RigidbodyComponent)>, mut starts: ResMut<CcdStarts>) {
+    starts.0 = query
+        .iter()
+        .filter(|(_, body)| body.ccd && body.rbt == RigidbodyType::Dynamic && !body.is_sleeping())
+        .map(|(entity, body)| (entity, body.collider.center))
+        .collect();
+}
+
+/// Sweeps CCD bodies along the path they just took. A body that ran into a collider
+/// stops at the time of impact, loses the velocity carrying it into the surface and
+/// slides the rest of the way along it. The next step's narrowphase then sees a regular
+/// contact.
+pub(crate) fn sweep_ccd_bodies(
+    mut world: ParamSet<(
+        BodyQuery,
+        SpatialQuery,
+        Query<&mut RigidbodyComponent>,
+        ResMut<BroadPhase>,
+    )>,
+    starts: Res<CcdStarts>,
+) {
+    let bodies = world.p0();
+    let swept: Vec<Sweep> = starts
+        .0
+        .iter()
+        .filter_map(|&(entity, start)| {
+            let (body, layers) = bodies.get(entity).ok()?;
+            let mut collider = body.collider.clone();
+            collider.center = start;
+            collider.update_geometry();
+            Some(Sweep {
+                entity,
+                collider,
+                motion: body.collider.center - start,
+                velocity: body.velocity.linear,
+                restitution: body.restitution,
+                filter: SpatialQueryFilter::default()
+                    .with_mask(layers.map_or(LayerMask::ALL, |layers| layers.filters))
+                    .with_excluded_entities([entity])
+                    .without_sensors(),
+            })
+        })
+        .collect();
+    if swept.is_empty() {
+        return;
+    }
+
+    let query = world.p1();
+    let hits: Vec<Sweep> = swept
+        .into_iter()
+        .filter_map(|mut sweep| {
+            let mut hit_anything = false;
+            for _ in 0..CCD_MAX_SWEEPS {
+                let distance = sweep.motion.length();
+                let Ok(direction) = Dir3::new(sweep.motion) else {
+                    break;
+                };
+                // overlaps are already contacts for the solver, only new hits need a sweep
+                let Some(hit) =
+                    query.cast_shape_entering(&sweep.collider, direction, distance, &sweep.filter)
+                else {
+                    sweep.collider.center += sweep.motion;
+                    break;
+                };
+
+                hit_anything = true;
+                let travel = (hit.distance - CCD_SKIN).max(0.);
+                sweep.collider.center += direction * travel;
+                sweep.collider.update_geometry();
+                let remaining = direction * (distance - travel);
+                sweep.motion = remaining - hit.normal * remaining.dot(hit.normal).min(0.);
+                let into_surface = sweep.velocity.dot(hit.normal);
+                if into_surface < 0. {
+                    sweep.velocity -= hit.normal * into_surface * (1. + sweep.restitution);
+                }
+            }
+            hit_anything.then_some(sweep)
+        })
+        .collect();
+
+    let mut bodies = world.p2();
+    let mut moved = Vec::with_capacity(hits.len());
+    for sweep in hits {
+        let Ok(mut body) = bodies.get_mut(sweep.entity) else {
+            continue;
+        };
+        body.collider.center = sweep.collider.center;
+        body.velocity.linear = sweep.velocity;
+        moved.push((sweep.entity, body.collider.aabb()));
+    }
+    if !moved.is_empty() {
+        world.p3().move_proxies(moved);
+    }
+}
@@ -0,0 +1,278 @@
+use bevy::prelude::*;
+
+use crate::physics::{
+    bodies::{RigidbodyComponent, RigidbodyType},
+    layers::CollisionLayers,
+};
+
+/// Axis aligned bounding box in world space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_center_half_extents(center: Vec3, half_extents: Vec3) -> Self {
+        Self {
+            min: center - half_extents,
+            max: center + half_extents,
+        }
+    }
+
+    pub fn from_points(points: &[Vec3]) -> Self {
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+        for point in points {
+            min = min.min(*point);
+            max = max.max(*point);
+        }
+        Self { min, max }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+            && self.min.z <= other.max.z
+            && self.max.z >= other.min.z
+    }
+
+    pub fn merge(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn expanded(&self, margin: f32) -> Self {
+        Self {
+            min: self.min - Vec3::splat(margin),
+            max: self.max + Vec3::splat(margin),
+        }
+    }
+}
+
+/// A single entry handed to the broadphase
+#[derive(Clone, Copy, Debug)]
+pub struct BroadPhaseProxy {
+    pub aabb: Aabb,
+    pub is_static: bool,
+    pub layers: CollisionLayers,
+}
+
+/// Sweep and prune over the axis with the largest spread of proxy centers.
+///
+/// Returns index pairs `(i, j)` with `i < j` whose boxes overlap. Pairs where both
+/// proxies are static are never reported since nothing can move them apart or together,
+/// and neither are pairs whose collision layers don't interact.
+pub fn sweep_and_prune(proxies: &[BroadPhaseProxy]) -> Vec<(usize, usize)> {
+    let mut pairs = vec![];
+    let mut order = vec![];
+    sweep_and_prune_into(proxies, &mut order, &mut pairs);
+    pairs
+}
+
+fn sweep_axis(proxies: &[BroadPhaseProxy]) -> usize {
+    if proxies.is_empty() {
+        return 0;
+    }
+
+    let count = proxies.len() as f32;
+    let mut sum = Vec3::ZERO;
+    let mut sum_sq = Vec3::ZERO;
+    for proxy in proxies {
+        let center = proxy.aabb.center();
+        sum += center;
+        sum_sq += center * center;
+    }
+    let variance = sum_sq / count - (sum / count) * (sum / count);
+
+    if variance.x >= variance.y && variance.x >= variance.z {
+        0
+    } else if variance.y >= variance.z {
+        1
+    } else {
+        2
+    }
+}
+
+/// Sort proxy indices by their min along the sweep axis, which is returned
+fn sort_along_sweep_axis(proxies: &[BroadPhaseProxy], order: &mut Vec<usize>) -> usize {
+    let axis = sweep_axis(proxies);
+    order.clear();
+    order.extend(0..proxies.len());
+    order
+        .sort_unstable_by(|&a, &b| proxies[a].aabb.min[axis].total_cmp(&proxies[b].aabb.min[axis]));
+    axis
+}
+
+fn sweep_and_prune_into(
+    proxies: &[BroadPhaseProxy],
+    order: &mut Vec<usize>,
+    pairs: &mut Vec<(usize, usize)>,
+) -> usize {
+    pairs.clear();
+    let axis = sort_along_sweep_axis(proxies, order);
+
+    for (position, &i) in order.iter().enumerate() {
+        let a = &proxies[i];
+        for &j in &order[position + 1..] {
+            let b = &proxies[j];
+            // sorted by min, so nothing further along can overlap on this axis
+            if b.aabb.min[axis] > a.aabb.max[axis] {
+                break;
+            }
+            if a.is_static && b.is_static {
+                continue;
+            }
+            if !a.layers.interacts_with(&b.layers) {
+                continue;
+            }
+            if a.aabb.intersects(&b.aabb) {
+                pairs.push((i.min(j), i.max(j)));
+            }
+        }
+    }
+    axis
+}
+
+/// Candidate pairs from the broadphase, consumed by the narrowphase. Its proxies, sorted
+/// along the sweep axis, also cull the candidates of spatial queries.
+#[derive(Resource, Default)]
+pub struct BroadPhase {
+    pub pairs: Vec<(Entity, Entity)>,
+    entities: Vec<Entity>,
+    proxies: Vec<BroadPhaseProxy>,
+    order: Vec<usize>,
+    axis: usize,
+    index_pairs: Vec<(usize, usize)>,
+}
+
+impl BroadPhase {
+    /// Bodies whose proxy overlaps `bounds`, as of the last update
+    pub(crate) fn overlapping(&self, bounds: Aabb) -> impl Iterator<Item = Entity> + '_ {
+        let axis = self.axis;
+        self.order
+            .iter()
+            // sorted by min, so nothing further along can overlap on this axis
+            .take_while(move |&&i| self.proxies[i].aabb.min[axis] <= bounds.max[axis])
+            .filter(move |&&i| self.proxies[i].aabb.intersects(&bounds))
+            .map(|&i| self.entities[i])
+    }
+
+    /// Move the proxies of bodies moved after the last update, so spatial queries later in
+    /// the step still find them
+    pub(crate) fn move_proxies(&mut self, moved: impl IntoIterator<Item = (Entity, Aabb)>) {
+        for (entity, aabb) in moved {
+            if let Some(i) = self.entities.iter().position(|&e| e == entity) {
+                self.proxies[i].aabb = aabb;
+            }
+        }
+        self.axis = sort_along_sweep_axis(&self.proxies, &mut self.order);
+    }
+}
+
+type ProxyQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static RigidbodyComponent,
+        Option<&'static CollisionLayers>,
+    ),
+>;
+
+fn collect_proxies(
+    query: &ProxyQuery,
+    entities: &mut Vec<Entity>,
+    proxies: &mut Vec<BroadPhaseProxy>,
+) {
+    entities.clear();
+    proxies.clear();
+    for (entity, body, layers) in query.iter() {
+        entities.push(entity);
+        proxies.push(BroadPhaseProxy {
+            aabb: body.collider.aabb(),
+            is_static: body.rbt == RigidbodyType::Static,
+            layers: layers.copied().unwrap_or_default(),
+        });
+    }
+}
+
+/// Body added since the proxies were last built, spatial queries check it without them
+#[derive(Component)]
+pub(crate) struct Unindexed;
+
+pub(crate) fn mark_unindexed(trigger: Trigger<OnAdd, RigidbodyComponent>, mut commands: Commands) {
+    commands.entity(trigger.target()).insert(Unindexed);
+}
+
+/// Every body is in the proxies just built, so none of them stays unindexed
+fn clear_unindexed(commands: &mut Commands, unindexed: &Query<Entity, With<Unindexed>>) {
+    for entity in unindexed.iter() {
+        commands.entity(entity).try_remove::<Unindexed>();
+    }
+}
+
+pub(crate) fn update_broadphase(
+    mut commands: Commands,
+    query: ProxyQuery,
+    unindexed: Query<Entity, With<Unindexed>>,
+    mut broadphase: ResMut<BroadPhase>,
+) {
+    let BroadPhase {
+        pairs,
+        entities,
+        proxies,
+        order,
+        axis,
+        index_pairs,
+    } = &mut *broadphase;
+
+    collect_proxies(&query, entities, proxies);
+    clear_unindexed(&mut commands, &unindexed);
+    *axis = sweep_and_prune_into(proxies, order, index_pairs);
+
+    pairs.clear();
+    // ordered so pairs and their keys don't depend on how the query iterates
+    pairs.extend(index_pairs.iter().map(|&(i, j)| {
+        let (a, b) = (entities[i], entities[j]);
+        (a.min(b), a.max(b))
+    }));
+    pairs.sort_unstable();
+}
+
+/// Move the proxies to where integration left the bodies, so spatial queries later in
+/// the step and between steps cull against current bounds. Pairs are left alone.
+pub(crate) fn update_query_proxies(
+    mut commands: Commands,
+    query: ProxyQuery,
+    unindexed: Query<Entity, With<Unindexed>>,
+    mut broadphase: ResMut<BroadPhase>,
+) {
+    let BroadPhase {
+        entities,
+        proxies,
+        order,
+        axis,
+        ..
+    } = &mut *broadphase;
+
+    collect_proxies(&query, entities, proxies);
+    clear_unindexed(&mut commands, &unindexed);
+    *axis = sort_along_sweep_axis(proxies, order);
+}
@@ -0,0 +1,352 @@
+use bevy::{
+    math::{DMat2, DMat3, DVec2, DVec3},
+    prelude::*,
+};
+
+use super::{Collider, broadphase::Aabb};
+
+const CAST_MAX_ITERATIONS: usize = 64;
+/// How close the cast has to get to the target to count as touching it
+const CAST_TOLERANCE: f32 = 1e-3;
+
+/// First point of contact of a ray or shape cast
+#[derive(Clone, Copy, Debug)]
+pub struct CastHit {
+    /// Distance travelled along the cast direction
+    pub distance: f32,
+    /// World space point on the target
+    pub point: Vec3,
+    /// Target surface normal at `point`, facing back towards the cast
+    pub normal: Vec3,
+}
+
+/// Point of the Minkowski difference, along with the target's own support point
+#[derive(Clone, Copy, Debug)]
+struct CastVertex {
+    point: Vec3,
+    target: Vec3,
+}
+
+/// Cast a ray against `target`. `direction` must be normalized.
+pub fn ray_cast(
+    target: &Collider,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> Option<CastHit> {
+    let end = origin + direction * max_distance;
+    if let Some(parts) = target.world_parts_in(&Aabb::new(origin.min(end), origin.max(end))) {
+        return closest_hit(
+            parts
+                .iter()
+                .map(|part| ray_cast(part, origin, direction, max_distance)),
+        );
+    }
+
+    let support = |d: Vec3| {
+        let point = target.support(d);
+        CastVertex {
+            point,
+            target: point,
+        }
+    };
+    gjk_cast(support, target.center, origin, direction, max_distance)
+}
+
+/// Sweep `caster` from its current pose along `direction` and find where it first touches
+/// `target`. `direction` must be normalized.
+pub fn shape_cast(
+    caster: &Collider,
+    target: &Collider,
+    direction: Vec3,
+    max_distance: f32,
+) -> Option<CastHit> {
+    sweep(caster, target, direction, max_distance, true)
+}
+
+/// Like `shape_cast`, but a target, or part of a composite target, that `caster` already
+/// overlaps is skipped instead of hit at a distance of zero
+pub fn shape_cast_entering(
+    caster: &Collider,
+    target: &Collider,
+    direction: Vec3,
+    max_distance: f32,
+) -> Option<CastHit> {
+    sweep(caster, target, direction, max_distance, false)
+}
+
+fn sweep(
+    caster: &Collider,
+    target: &Collider,
+    direction: Vec3,
+    max_distance: f32,
+    hit_overlaps: bool,
+) -> Option<CastHit> {
+    if let Some(parts) = caster.world_parts() {
+        return closest_hit(
+            parts
+                .iter()
+                .map(|part| sweep(part, target, direction, max_distance, hit_overlaps)),
+        );
+    }
+    let start = caster.aabb();
+    let swept = start.merge(&Aabb::from_center_half_extents(
+        start.center() + direction * max_distance,
+        start.half_extents(),
+    ));
+    if let Some(parts) = target.world_parts_in(&swept) {
+        return closest_hit(
+            parts
+                .iter()
+                .map(|part| sweep(caster, part, direction, max_distance, hit_overlaps)),
+        );
+    }
+
+    // the caster's center travels along the ray, against the target grown by the caster
+    let support = |d: Vec3| {
+        let point = target.support(d);
+        CastVertex {
+            point: point - (caster.support(-d) - caster.center),
+            target: point,
+        }
+    };
+    gjk_cast(
+        support,
+        target.center,
+        caster.center,
+        direction,
+        max_distance,
+    )
+    .filter(|hit| hit_overlaps || hit.distance > 0.)
+}
+
+fn closest_hit(hits: impl Iterator<Item = Option<CastHit>>) -> Option<CastHit> {
+    hits.flatten()
+        .min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
+
+/// GJK ray cast against a convex set given by its support function, after
+/// "Ray Casting against General Convex Objects" by Gino van den Bergen.
+/// Runs in double precision: the simplex can be far larger than the gap being measured.
+fn gjk_cast(
+    support: impl Fn(Vec3) -> CastVertex,
+    inside: Vec3,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> Option<CastHit> {
+    let (origin, direction) = (origin.as_dvec3(), direction.as_dvec3());
+    let tolerance = CAST_TOLERANCE as f64;
+    let mut distance = 0.;
+    let mut position = origin;
+    let mut normal = DVec3::ZERO;
+    let mut closest = position - inside.as_dvec3();
+    let mut simplex: Vec<CastVertex> = Vec::with_capacity(4);
+    let mut weights = [0.; 4];
+
+    for _ in 0..CAST_MAX_ITERATIONS {
+        if closest.length_squared() < tolerance * tolerance {
+            break;
+        }
+
+        let vertex = support(closest.as_vec3());
+        let w = position - vertex.point.as_dvec3();
+        let closest_dot_w = closest.dot(w);
+        if closest_dot_w > 0. {
+            let closest_dot_direction = closest.dot(direction);
+            // separated and moving apart
+            if closest_dot_direction >= 0. {
+                return None;
+            }
+            distance -= closest_dot_w / closest_dot_direction;
+            if distance > max_distance as f64 {
+                return None;
+            }
+            position = origin + direction * distance;
+            normal = closest;
+        }
+
+        if !simplex
+            .iter()
+            .any(|v| v.point.distance_squared(vertex.point) < 1e-10)
+        {
+            if simplex.len() == 4 {
+                break;
+            }
+            simplex.push(vertex);
+        }
+
+        let offsets: Vec<DVec3> = simplex
+            .iter()
+            .map(|v| position - v.point.as_dvec3())
+            .collect();
+        let (point, new_weights) = closest_on_simplex(&offsets);
+        closest = point;
+
+        let mut kept = 0;
+        for i in 0..simplex.len() {
+            if new_weights[i] > 0. {
+                simplex[kept] = simplex[i];
+                weights[kept] = new_weights[i];
+                kept += 1;
+            }
+        }
+        simplex.truncate(kept);
+    }
+
+    if closest.length_squared() > (tolerance * 10.).powi(2) {
+        return None;
+    }
+
+    if normal == DVec3::ZERO {
+        // overlapping from the start
+        return Some(CastHit {
+            distance: 0.,
+            point: origin.as_vec3(),
+            normal: -direction.as_vec3(),
+        });
+    }
+
+    let point = simplex
+        .iter()
+        .zip(weights)
+        .map(|(vertex, weight)| vertex.target * weight as f32)
+        .sum::<Vec3>();
+
+    Some(CastHit {
+        distance: distance as f32,
+        point,
+        normal: normal.normalize().as_vec3(),
+    })
+}
+
+/// Point of the convex hull of up to four `points` closest to the origin, with the
+/// barycentric weight of each point. Points that don't contribute get a weight of zero.
+fn closest_on_simplex(points: &[DVec3]) -> (DVec3, [f64; 4]) {
+    let mut best = (DVec3::ZERO, [0.; 4]);
+    let mut best_distance = f64::INFINITY;
+
+    // every face of the simplex whose projection of the origin lands inside it is a
+    // candidate, the closest one of those is the answer
+    for subset in 1..(1usize << points.len()) {
+        let indices: Vec<usize> = (0..points.len())
+            .filter(|i| subset & (1 << i) != 0)
+            .collect();
+        let Some(face_weights) =
+            affine_weights(&indices.iter().map(|&i| points[i]).collect::<Vec<_>>())
+        else {
+            continue;
+        };
+        if face_weights.iter().any(|&w| w < 0.) {
+            continue;
+        }
+
+        let mut weights = [0.; 4];
+        let mut point = DVec3::ZERO;
+        for (&i, &weight) in indices.iter().zip(face_weights.iter()) {
+            weights[i] = weight;
+            point += points[i] * weight;
+        }
+
+        let distance = point.length_squared();
+        if distance < best_distance {
+            best_distance = distance;
+            best = (point, weights);
+        }
+    }
+
+    best
+}
+
+/// Barycentric weights of the origin's projection onto the affine hull of `points`
+fn affine_weights(points: &[DVec3]) -> Option<Vec<f64>> {
+    let base = points[0];
+    let edges: Vec<DVec3> = points[1..].iter().map(|&p| p - base).collect();
+
+    let solved: Vec<f64> = match edges.len() {
+        0 => vec![],
+        1 => {
+            let length = edges[0].length_squared();
+            if length < 1e-12 {
+                return None;
+            }
+            vec![-edges[0].dot(base) / length]
+        }
+        2 => {
+            let gram = DMat2::from_cols(
+                DVec2::new(edges[0].dot(edges[0]), edges[0].dot(edges[1])),
+                DVec2::new(edges[1].dot(edges[0]), edges[1].dot(edges[1])),
+            );
+            if gram.determinant().abs() < 1e-12 {
+                return None;
+            }
+            let rhs = DVec2::new(-edges[0].dot(base), -edges[1].dot(base));
+            (gram.inverse() * rhs).to_array().to_vec()
+        }
+        _ => {
+            let matrix = DMat3::from_cols(edges[0], edges[1], edges[2]);
+            if matrix.determinant().abs() < 1e-12 {
+                return None;
+            }
+            // the origin itself, solved directly as base + M * mu = 0
+            (matrix.inverse() * -base).to_array().to_vec()
+        }
+    };
+
+    let mut weights = Vec::with_capacity(points.len());
+    weights.push(1. - solved.iter().sum::<f64>());
+    weights.extend(solved);
+    Some(weights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: Vec3, expected: Vec3) {
+        assert!(actual.distance(expected) < 1e-2, "{actual} != {expected}");
+    }
+
+    fn assert_distance(hit: CastHit, expected: f32) {
+        let distance = hit.distance;
+        assert!((distance - expected).abs() < 1e-2, "distance {distance}");
+    }
+
+    #[test]
+    fn ray_hits_the_near_side_of_a_sphere() {
+        let sphere = Collider::from_sphere(1., Vec3::ZERO);
+
+        let hit = ray_cast(&sphere, Vec3::new(-5., 0., 0.), Vec3::X, 10.).unwrap();
+        assert_distance(hit, 4.);
+        assert_near(hit.point, Vec3::NEG_X);
+        assert_near(hit.normal, Vec3::NEG_X);
+    }
+
+    #[test]
+    fn ray_hits_the_top_cap_of_a_capsule() {
+        let capsule = Collider::from_capsule(0.5, 1., Vec3::ZERO, Quat::IDENTITY);
+
+        let hit = ray_cast(&capsule, Vec3::new(0., 5., 0.), Vec3::NEG_Y, 10.).unwrap();
+        assert_distance(hit, 3.5);
+        assert_near(hit.point, Vec3::new(0., 1.5, 0.));
+        assert_near(hit.normal, Vec3::Y);
+    }
+
+    #[test]
+    fn ray_misses_what_is_out_of_reach_or_aside() {
+        let sphere = Collider::from_sphere(1., Vec3::ZERO);
+
+        assert!(ray_cast(&sphere, Vec3::new(-5., 0., 0.), Vec3::X, 3.).is_none());
+        assert!(ray_cast(&sphere, Vec3::new(-5., 2., 0.), Vec3::X, 10.).is_none());
+    }
+
+    #[test]
+    fn sphere_cast_stops_on_a_box() {
+        let ground = Collider::from_cuboid(Vec3::new(5., 0.5, 5.), Vec3::ZERO, Quat::IDENTITY);
+        let ball = Collider::from_sphere(0.5, Vec3::new(0., 3., 0.));
+
+        let hit = shape_cast(&ball, &ground, Vec3::NEG_Y, 10.).unwrap();
+        assert_distance(hit, 2.);
+        assert_near(hit.normal, Vec3::Y);
+    }
+}
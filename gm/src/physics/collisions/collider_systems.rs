@@ -1,259 +1,141 @@
-use bevy::{ecs::query, prelude::*};
-
-use crate::{physics::bodies::*, player::player_data::Player};
-
-use super::{Collider, ColliderVertexInfo, ContactInfo};
-
-pub(crate) fn update_vertices(
-    mut query: Query<(&mut RigidbodyComponent, &mut Transform), With<RigidbodyComponent>>,
-) {
-    for (mut body, _) in query.iter_mut() {
-        //let rot: [f32; 3] = transform.rotation.to_euler(EulerRot::XYZ).into();
-        body.collider.axes = [
-            body.collider.rotation * Vec3::X,
-            body.collider.rotation * Vec3::Y,
-            body.collider.rotation * Vec3::Z,
-        ];
-
-        body.collider.vertex_info = ColliderVertexInfo::from_cuboid(
-            &body.collider.center,
-            &body.collider.half_extents,
-            &body.collider.rotation,
-        );
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+
+use crate::{
+    physics::{bodies::*, joints::Joint, layers::CollisionLayers},
+    player::{controller::CharacterController, player_data::Player},
+};
+
+use super::{
+    Collider, ColliderShape, ContactInfo,
+    broadphase::BroadPhase,
+    events::{CollisionEnded, CollisionStarted},
+    gjk::gjk_epa,
+    manifold::{
+        ContactManifolds, ContactPoint, SatAxis, cuboid_contact_points, face_contact_points,
+        merge_part_contacts, single_point,
+    },
+};
+
+/// Edge axes must beat face axes by this much to be picked, face contacts are more stable
+const SAT_EDGE_BIAS: f32 = 0.01;
+
+pub(crate) fn update_vertices(mut query: Query<&mut RigidbodyComponent>) {
+    for mut body in query.iter_mut() {
+        body.collider.update_geometry();
     }
 }
 
-fn rotation_to_align(from: Vec3, to: Vec3) -> Quat {
-    let from = from.normalize();
-    let to = to.normalize();
-    let axis = from.cross(to);
-    let angle = from.angle_between(to);
-    if axis.length_squared() < 1e-6 {
-        if from.dot(to) < 0.0 {
-            // Rotate 180° around any perpendicular axis
-            Quat::from_axis_angle(Vec3::X, std::f32::consts::PI)
-        } else {
-            Quat::IDENTITY
-        }
-    } else {
-        Quat::from_axis_angle(axis.normalize(), angle)
-    }
-}
-
-type RigidbodyQuery<'a, 'w> = Query<'w, 'a, (&'a mut RigidbodyComponent, &'a mut Transform)>;
-type PlayerQuery<'a> = Query<'a, 'a, (&'a mut Player, &'a mut Transform)>;
-
-pub fn detect_player_collisions(mut paramset: ParamSet<(RigidbodyQuery, PlayerQuery)>) {
-    let bodies: Vec<(RigidbodyComponent, Transform)> = {
-        let mut binding = paramset.p0();
-        binding
-            .iter_mut()
-            .map(|(rb, tf)| (rb.clone(), *tf))
-            .collect()
-    };
-    if let Ok((mut player, mut transform)) = paramset.p1().single_mut() {
-        for (body, _) in bodies {
-            if body.collider.axes == [Vec3::X, Vec3::Y, Vec3::Z] {
-                if let Some(contact) = aabb_player_vs_collider(
-                    transform.translation,
-                    Vec3::new(1., 5., 1.),
-                    player.pos.vel,
-                    &body.collider,
-                    &body.velocity,
-                ) {
-                    // println!(
-                    //     "collision -- normal: {:?}, penetration: {}",
-                    //     contact.normal, contact.penetration_depth
-                    // );
-                    resolve_player(&mut player, &mut transform, body.clone(), contact);
-                    //println!("{:?}", player.pos.grounded);
-                }
-            } else {
-                // use sat
+type PlayerQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Player,
+        &'static CharacterController,
+        Option<&'static CollisionLayers>,
+    ),
+>;
+
+/// Collision events and wake ups for every player. Their movement is resolved by the
+/// `CharacterController` itself.
+pub fn detect_player_collisions(
+    mut bodies: Query<(Entity, &mut RigidbodyComponent, Option<&CollisionLayers>)>,
+    players: PlayerQuery,
+    mut touching: Local<HashMap<(Entity, Entity), ContactInfo>>,
+    mut started: EventWriter<CollisionStarted>,
+    mut ended: EventWriter<CollisionEnded>,
+) {
+    let mut touched = HashMap::new();
+    for (player_entity, player, controller, player_layers) in players.iter() {
+        let player_layers = player_layers.copied().unwrap_or_default();
+        let shape = controller.contact_collider(player.pos.loc);
+        let velocity = Velocity::new(player.pos.vel, Vec3::ZERO);
+        for (entity, body, layers) in bodies.iter() {
+            if !player_layers.interacts_with(&layers.copied().unwrap_or_default())
+                || !shape.aabb().intersects(&body.collider.aabb())
+            {
+                continue;
+            }
+            if let Some(contact) =
+                get_collision_info(&shape, &velocity, &body.collider, &body.velocity)
+            {
+                touched.insert((player_entity, entity), contact);
             }
         }
     }
-}
-
-fn resolve_player(
-    player: &mut Player,
-    trans: &mut Transform,
-    mut body: RigidbodyComponent,
-    info: ContactInfo,
-) {
-    if info.normal.dot(Vec3::Y) > 0.7 {
-        player.pos.grounded = true;
-    }
 
-    if body.rbt == RigidbodyType::Static {
-        trans.translation += info.normal * info.penetration_depth;
-
-        let into_surface = player.pos.vel.dot(info.normal);
-        if into_surface < 0.0 {
-            player.pos.vel -= info.normal * into_surface;
+    for (&(entity_a, entity_b), contact) in touched.iter() {
+        if !touching.contains_key(&(entity_a, entity_b)) {
+            started.write(CollisionStarted {
+                entity_a,
+                entity_b,
+                contact: contact.clone(),
+            });
         }
     }
-    if body.rbt == RigidbodyType::Dynamic {
-        trans.translation += info.normal * info.penetration_depth;
-        let into_surface = player.pos.vel.dot(info.normal);
-        if into_surface < 0.0 {
-            player.pos.vel -= info.normal * into_surface;
+    for (&(entity_a, entity_b), contact) in touching.iter() {
+        if !touched.contains_key(&(entity_a, entity_b)) {
+            ended.write(CollisionEnded {
+                entity_a,
+                entity_b,
+                contact: contact.clone(),
+            });
         }
-
-        body.velocity.linear += player.pos.vel;
     }
-}
-
-pub fn aabb_player_vs_collider(
-    player_position: Vec3,
-    player_half_size: Vec3,
-    player_velocity: Vec3,
-    other: &Collider,
-    other_velocity: &Velocity,
-) -> Option<ContactInfo> {
-    let min_a = player_position - player_half_size;
-    let max_a = player_position + player_half_size;
 
-    let min_b = other.center - other.half_extents;
-    let max_b = other.center + other.half_extents;
-
-    if (min_a.x > max_b.x || max_a.x < min_b.x)
-        || (min_a.y > max_b.y || max_a.y < min_b.y)
-        || (min_a.z > max_b.z || max_a.z < min_b.z)
-    {
-        return None;
+    // whatever a player bumps into has to react, so it can't stay asleep
+    for &(_, entity) in touched.keys() {
+        let Ok((_, mut body, _)) = bodies.get_mut(entity) else {
+            continue;
+        };
+        if body.rbt == RigidbodyType::Dynamic && body.is_sleeping() && !body.collider.is_sensor {
+            body.wake_up();
+        }
     }
 
-    let overlap_x = (max_a.x - min_b.x).min(max_b.x - min_a.x);
-    let overlap_y = (max_a.y - min_b.y).min(max_b.y - min_a.y);
-    let overlap_z = (max_a.z - min_b.z).min(max_b.z - min_a.z);
-
-    let (penetration_depth, normal) = if overlap_x < overlap_y && overlap_x < overlap_z {
-        (
-            overlap_x,
-            Vec3::X
-                * (if player_position.x < other.center.x {
-                    -1.0
-                } else {
-                    1.0
-                }),
-        )
-    } else if overlap_y < overlap_z {
-        (
-            overlap_y,
-            Vec3::Y
-                * (if player_position.y < other.center.y {
-                    -1.0
-                } else {
-                    1.0
-                }),
-        )
-    } else {
-        (
-            overlap_z,
-            Vec3::Z
-                * (if player_position.z < other.center.z {
-                    -1.0
-                } else {
-                    1.0
-                }),
-        )
-    };
-
-    Some(ContactInfo {
-        normal,
-        penetration_depth,
-        contact_point_a: player_position,
-        a_vel: Velocity {
-            linear: player_velocity,
-            angular: Vec3::ZERO,
-        },
-        contact_point_b: other.center,
-        b_vel: *other_velocity,
-    })
+    *touching = touched;
 }
 
 pub(crate) fn detect_object_collisions(
-    mut query: Query<(&mut RigidbodyComponent, &mut Transform)>,
+    query: Query<&RigidbodyComponent>,
+    joints: Query<&Joint>,
+    broadphase: Res<BroadPhase>,
+    mut manifolds: ResMut<ContactManifolds>,
 ) {
-    let mut items: Vec<_> = query.iter_mut().collect();
-
-    for i in 0..items.len() {
-        for j in (i + 1)..items.len() {
-            let (left, right) = items.split_at_mut(j);
-            let (ref mut body_a, ref mut transform_a) = left[i];
-            let (ref mut body_b, ref mut transform_b) = right[0];
-
-            if let Some(collision_data) = get_collision_info(
-                &body_a.collider,
-                &body_a.velocity,
-                &body_b.collider,
-                &body_b.velocity,
-            ) {
-                // println!(
-                //     "collision -- normal: {:?}, penetration: {}",
-                //     collision_data.normal, collision_data.penetration_depth
-                // );
-                resolve_object_collisions(
-                    body_a,
-                    transform_a,
-                    body_b,
-                    transform_b,
-                    &collision_data,
-                );
-            }
+    manifolds.begin_step();
+
+    let jointed: HashSet<(Entity, Entity)> = joints
+        .iter()
+        .filter(|joint| !joint.collide_connected && !joint.broken)
+        .filter_map(|joint| Some((joint.member_a.entity?, joint.member_b.entity?)))
+        .map(|(a, b)| (a.min(b), a.max(b)))
+        .collect();
+
+    for &(entity_a, entity_b) in &broadphase.pairs {
+        if jointed.contains(&(entity_a, entity_b)) {
+            continue;
         }
-    }
-}
-
-fn resolve_object_collisions(
-    a: &mut RigidbodyComponent,
-    tf_a: &mut Transform,
-    b: &mut RigidbodyComponent,
-    tf_b: &mut Transform,
-    contact: &ContactInfo,
-) {
-    let total_inverse_mass = a.inverse_mass + b.inverse_mass;
-    if total_inverse_mass == 0.0 {
-        return;
-    }
 
-    let correction = contact.normal * (contact.penetration_depth / total_inverse_mass);
+        let Ok([body_a, body_b]) = query.get_many([entity_a, entity_b]) else {
+            continue;
+        };
 
-    let temp_vel = a.velocity.linear;
-
-    if a.rbt != RigidbodyType::Static {
-        a.collider.center += correction * a.inverse_mass;
-        if a.rbt == RigidbodyType::Static {
-            a.velocity.linear = Vec3::ZERO;
+        // nothing in a sleeping pair moves, so its contact can't have changed
+        if body_a.is_resting() && body_b.is_resting() {
+            manifolds.keep((entity_a, entity_b));
+            continue;
         }
-        a.velocity.linear *= 1. - a.friction;
-        a.velocity.angular = Vec3::ZERO;
-        a.velocity.linear += a.restitution * (contact.normal + temp_vel) * (1. - a.damping.linear)
-            + contact.b_vel.linear;
-        tf_a.translation = a.collider.center;
-    }
 
-    if a.rbt == RigidbodyType::Dynamic {
-        let world_down = Vec3::Y;
-        let local_down = a.collider.rotation * world_down;
-        let align_rotation: Vec3 = rotation_to_align(local_down, contact.normal)
-            .to_euler(EulerRot::XYZ)
-            .into();
-        println!("!!!");
-        a.velocity.angular += align_rotation * a.inverse_mass * a.velocity.linear.length().max(6.5);
-    }
-
-    if b.rbt != RigidbodyType::Static {
-        b.collider.center += correction * b.inverse_mass;
-        if b.rbt == RigidbodyType::Static {
-            b.velocity.linear = Vec3::ZERO;
+        if let Some(collision_data) = get_collision_info(
+            &body_a.collider,
+            &body_a.velocity,
+            &body_b.collider,
+            &body_b.velocity,
+        ) {
+            manifolds.insert((entity_a, entity_b), collision_data);
         }
-        b.velocity.angular = Vec3::ZERO;
-        b.velocity.linear *= 1. - b.friction;
-        b.velocity.linear += b.restitution * (contact.normal + temp_vel) * (1. - b.damping.linear)
-            + contact.a_vel.linear;
-        tf_b.translation = b.collider.center;
     }
 }
 
@@ -274,18 +156,117 @@ fn get_overlap(min_a: f32, max_a: f32, min_b: f32, max_b: f32) -> f32 {
     (max_a.min(max_b) - min_a.max(min_b)).max(0.0)
 }
 
-fn get_collision_info(
+/// Narrowphase test between two colliders. The contact normal points from `b` towards `a`.
+pub(crate) fn get_collision_info(
+    a: &Collider,
+    a_vel: &Velocity,
+    b: &Collider,
+    b_vel: &Velocity,
+) -> Option<ContactInfo> {
+    // meshes, compounds and heightfields collide part by part
+    if let Some(parts) = a.world_parts_in(&b.aabb()) {
+        let contacts = parts
+            .iter()
+            .filter_map(|part| get_collision_info(part, a_vel, b, b_vel))
+            .collect();
+        return merge_part_contacts(contacts);
+    }
+    if let Some(parts) = b.world_parts_in(&a.aabb()) {
+        let contacts = parts
+            .iter()
+            .filter_map(|part| get_collision_info(a, a_vel, part, b_vel))
+            .collect();
+        return merge_part_contacts(contacts);
+    }
+
+    match (a.collider_shape, b.collider_shape) {
+        (ColliderShape::Cuboid, ColliderShape::Cuboid) => sat_collision_info(a, a_vel, b, b_vel),
+        (ColliderShape::Sphere, ColliderShape::Sphere) => sphere_collision_info(a, a_vel, b, b_vel),
+        _ => {
+            let (normal, penetration_depth) = gjk_epa(a, b)?;
+            let points = face_contact_points(a, b, normal)
+                .unwrap_or_else(|| single_point(a, b, normal, penetration_depth));
+            let deepest = points
+                .iter()
+                .max_by(|x, y| x.penetration.total_cmp(&y.penetration))
+                .copied()
+                .unwrap_or_default();
+            Some(ContactInfo {
+                normal,
+                penetration_depth,
+                contact_point_a: deepest.point_a,
+                contact_point_b: deepest.point_b,
+                a_vel: *a_vel,
+                b_vel: *b_vel,
+                points,
+            })
+        }
+    }
+}
+
+fn sphere_collision_info(
+    a: &Collider,
+    a_vel: &Velocity,
+    b: &Collider,
+    b_vel: &Velocity,
+) -> Option<ContactInfo> {
+    let offset = a.center - b.center;
+    let distance = offset.length();
+    let penetration_depth = a.radius() + b.radius() - distance;
+    if penetration_depth <= 0. {
+        return None;
+    }
+
+    let normal = if distance > f32::EPSILON {
+        offset / distance
+    } else {
+        Vec3::Y
+    };
+
+    let contact_point_a = a.center - normal * a.radius();
+    let contact_point_b = b.center + normal * b.radius();
+
+    Some(ContactInfo {
+        normal,
+        penetration_depth,
+        contact_point_a,
+        contact_point_b,
+        a_vel: *a_vel,
+        b_vel: *b_vel,
+        points: vec![ContactPoint::new(
+            contact_point_a,
+            contact_point_b,
+            penetration_depth,
+            a,
+            b,
+        )],
+    })
+}
+
+fn sat_collision_info(
     a: &Collider,
     a_vel: &Velocity,
     b: &Collider,
     b_vel: &Velocity,
 ) -> Option<ContactInfo> {
-    let axes = a.get_axes(b);
+    let mut candidates = Vec::with_capacity(15);
+    candidates.extend((0..3).map(|i| (a.axes[i], SatAxis::FaceA(i))));
+    candidates.extend((0..3).map(|i| (b.axes[i], SatAxis::FaceB(i))));
+    for i in 0..3 {
+        for j in 0..3 {
+            let cross = a.axes[i].cross(b.axes[j]);
+            if cross.length_squared() > 1e-6 {
+                candidates.push((cross.normalize(), SatAxis::Edge(i, j)));
+            }
+        }
+    }
+
     let mut min_overlap = f32::INFINITY;
+    let mut best_score = f32::INFINITY;
     let mut collision_axis = Vec3::ZERO;
+    let mut sat_axis = SatAxis::FaceA(0);
 
-    for axis in axes {
-        let axis = axis.normalize();
+    for (axis, candidate) in candidates {
         let (min_a, max_a) = project_collider(a, axis);
         let (min_b, max_b) = project_collider(b, axis);
 
@@ -294,18 +275,99 @@ fn get_collision_info(
             return None;
         }
 
-        if overlap < min_overlap {
+        let score = match candidate {
+            SatAxis::Edge(..) => overlap + SAT_EDGE_BIAS,
+            _ => overlap,
+        };
+        if score < best_score {
+            best_score = score;
             min_overlap = overlap;
             collision_axis = axis;
+            sat_axis = candidate;
         }
     }
 
+    if collision_axis.dot(a.center - b.center) < 0. {
+        collision_axis = -collision_axis;
+    }
+
+    let mut points = cuboid_contact_points(a, b, collision_axis, min_overlap, sat_axis);
+    if points.is_empty() {
+        points = single_point(a, b, collision_axis, min_overlap);
+    }
+    let deepest = points
+        .iter()
+        .max_by(|x, y| x.penetration.total_cmp(&y.penetration))
+        .copied()
+        .unwrap_or_default();
+
     Some(ContactInfo {
         normal: collision_axis,
         penetration_depth: min_overlap,
-        contact_point_a: a.center,
-        contact_point_b: b.center,
+        contact_point_a: deepest.point_a,
+        contact_point_b: deepest.point_b,
         a_vel: *a_vel,
         b_vel: *b_vel,
+        points,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::player_data::PlayerPositioning;
+
+    #[test]
+    fn every_player_collides_and_wakes_what_it_touches() {
+        let mut app = App::new();
+        app.add_event::<CollisionStarted>()
+            .add_event::<CollisionEnded>()
+            .add_systems(Update, (update_vertices, detect_player_collisions).chain());
+
+        // two players far apart, each standing in a sleeping crate of its own
+        let pairs: Vec<(Entity, Entity)> = [Vec3::ZERO, Vec3::new(50., 0., 0.)]
+            .into_iter()
+            .map(|position| {
+                let player = Player {
+                    pos: PlayerPositioning::new(position, Quat::IDENTITY),
+                    ..Default::default()
+                };
+                let player = app
+                    .world_mut()
+                    .spawn((player, CharacterController::default()))
+                    .id();
+                let collider = Collider::from_cuboid(Vec3::splat(1.), position, Quat::IDENTITY);
+                let mut body = RigidbodyComponent::new_dynamic(
+                    1.,
+                    collider,
+                    0.5,
+                    Vec3::ZERO,
+                    Vec3::ZERO,
+                    Vec3::ZERO,
+                    Damping::default(),
+                    0.,
+                );
+                body.sleep();
+                let crate_entity = app.world_mut().spawn(body).id();
+                (player, crate_entity)
+            })
+            .collect();
+
+        app.update();
+
+        let events = app.world().resource::<Events<CollisionStarted>>();
+        let mut started: Vec<(Entity, Entity)> = events
+            .iter_current_update_events()
+            .map(|event| (event.entity_a, event.entity_b))
+            .collect();
+        started.sort_unstable();
+        let mut expected = pairs.clone();
+        expected.sort_unstable();
+        assert_eq!(started, expected);
+
+        for (_, crate_entity) in pairs {
+            let body = app.world().get::<RigidbodyComponent>(crate_entity).unwrap();
+            assert!(!body.is_sleeping());
+        }
+    }
+}
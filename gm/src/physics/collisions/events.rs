@@ -0,0 +1,42 @@
+use bevy::prelude::*;
+
+use super::{ContactInfo, manifold::ContactManifolds};
+
+/// Two colliders started touching, or the player walked into one.
+/// The contact normal points from `entity_b` towards `entity_a`.
+#[derive(Event, Clone, Debug)]
+pub struct CollisionStarted {
+    pub entity_a: Entity,
+    pub entity_b: Entity,
+    pub contact: ContactInfo,
+}
+
+/// Two colliders stopped touching, carrying the last contact they had
+#[derive(Event, Clone, Debug)]
+pub struct CollisionEnded {
+    pub entity_a: Entity,
+    pub entity_b: Entity,
+    pub contact: ContactInfo,
+}
+
+pub(crate) fn emit_collision_events(
+    manifolds: Res<ContactManifolds>,
+    mut started: EventWriter<CollisionStarted>,
+    mut ended: EventWriter<CollisionEnded>,
+) {
+    for (&(entity_a, entity_b), contact) in manifolds.started() {
+        started.write(CollisionStarted {
+            entity_a,
+            entity_b,
+            contact: contact.clone(),
+        });
+    }
+
+    for (&(entity_a, entity_b), contact) in manifolds.ended() {
+        ended.write(CollisionEnded {
+            entity_a,
+            entity_b,
+            contact: contact.clone(),
+        });
+    }
+}
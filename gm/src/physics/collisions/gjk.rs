@@ -0,0 +1,321 @@
+use bevy::prelude::*;
+
+use super::Collider;
+
+const GJK_MAX_ITERATIONS: usize = 64;
+const EPA_MAX_ITERATIONS: usize = 64;
+const EPA_TOLERANCE: f32 = 1e-4;
+
+/// Support point of the Minkowski difference `a - b`
+fn support(a: &Collider, b: &Collider, direction: Vec3) -> Vec3 {
+    a.support(direction) - b.support(-direction)
+}
+
+fn same_direction(direction: Vec3, ao: Vec3) -> bool {
+    direction.dot(ao) > 0.
+}
+
+/// Up to four points of the Minkowski difference, newest first
+#[derive(Clone, Copy, Debug)]
+pub struct Simplex {
+    points: [Vec3; 4],
+    len: usize,
+}
+
+impl Simplex {
+    fn new() -> Self {
+        Self {
+            points: [Vec3::ZERO; 4],
+            len: 0,
+        }
+    }
+
+    fn push_front(&mut self, point: Vec3) {
+        self.points = [point, self.points[0], self.points[1], self.points[2]];
+        self.len = (self.len + 1).min(4);
+    }
+
+    fn set(&mut self, points: &[Vec3]) {
+        self.points[..points.len()].copy_from_slice(points);
+        self.len = points.len();
+    }
+
+    pub fn points(&self) -> &[Vec3] {
+        &self.points[..self.len]
+    }
+}
+
+fn line(simplex: &mut Simplex, direction: &mut Vec3) -> bool {
+    let [a, b, ..] = simplex.points;
+    let ab = b - a;
+    let ao = -a;
+
+    if same_direction(ab, ao) {
+        *direction = ab.cross(ao).cross(ab);
+    } else {
+        simplex.set(&[a]);
+        *direction = ao;
+    }
+
+    false
+}
+
+fn triangle(simplex: &mut Simplex, direction: &mut Vec3) -> bool {
+    let [a, b, c, _] = simplex.points;
+    let ab = b - a;
+    let ac = c - a;
+    let ao = -a;
+    let abc = ab.cross(ac);
+
+    if same_direction(abc.cross(ac), ao) {
+        if same_direction(ac, ao) {
+            simplex.set(&[a, c]);
+            *direction = ac.cross(ao).cross(ac);
+        } else {
+            simplex.set(&[a, b]);
+            return line(simplex, direction);
+        }
+    } else if same_direction(ab.cross(abc), ao) {
+        simplex.set(&[a, b]);
+        return line(simplex, direction);
+    } else if same_direction(abc, ao) {
+        *direction = abc;
+    } else {
+        simplex.set(&[a, c, b]);
+        *direction = -abc;
+    }
+
+    false
+}
+
+fn tetrahedron(simplex: &mut Simplex, direction: &mut Vec3) -> bool {
+    let [a, b, c, d] = simplex.points;
+    let ab = b - a;
+    let ac = c - a;
+    let ad = d - a;
+    let ao = -a;
+
+    let abc = ab.cross(ac);
+    let acd = ac.cross(ad);
+    let adb = ad.cross(ab);
+
+    if same_direction(abc, ao) {
+        simplex.set(&[a, b, c]);
+        return triangle(simplex, direction);
+    }
+    if same_direction(acd, ao) {
+        simplex.set(&[a, c, d]);
+        return triangle(simplex, direction);
+    }
+    if same_direction(adb, ao) {
+        simplex.set(&[a, d, b]);
+        return triangle(simplex, direction);
+    }
+
+    true
+}
+
+fn next_simplex(simplex: &mut Simplex, direction: &mut Vec3) -> bool {
+    match simplex.len {
+        2 => line(simplex, direction),
+        3 => triangle(simplex, direction),
+        4 => tetrahedron(simplex, direction),
+        _ => false,
+    }
+}
+
+/// GJK intersection test, returning the simplex enclosing the origin on overlap
+pub fn gjk(a: &Collider, b: &Collider) -> Option<Simplex> {
+    let mut direction = a.center - b.center;
+    if direction.length_squared() < f32::EPSILON {
+        direction = Vec3::X;
+    }
+
+    let mut simplex = Simplex::new();
+    let first = support(a, b, direction);
+    simplex.push_front(first);
+    direction = -first;
+
+    for _ in 0..GJK_MAX_ITERATIONS {
+        // the origin lies on the current simplex, so the shapes are touching
+        if direction.length_squared() < f32::EPSILON {
+            return Some(simplex);
+        }
+
+        let point = support(a, b, direction);
+        if point.dot(direction) < 0. {
+            return None;
+        }
+
+        simplex.push_front(point);
+        if next_simplex(&mut simplex, &mut direction) {
+            return Some(simplex);
+        }
+    }
+
+    None
+}
+
+/// Grow a degenerate simplex from an early GJK exit into a tetrahedron for EPA
+fn blow_up(a: &Collider, b: &Collider, simplex: &Simplex) -> Option<[Vec3; 4]> {
+    const DIRECTIONS: [Vec3; 6] = [
+        Vec3::X,
+        Vec3::NEG_X,
+        Vec3::Y,
+        Vec3::NEG_Y,
+        Vec3::Z,
+        Vec3::NEG_Z,
+    ];
+
+    let mut points: Vec<Vec3> = simplex.points().to_vec();
+
+    while points.len() < 4 {
+        let candidate =
+            DIRECTIONS
+                .iter()
+                .map(|&d| support(a, b, d))
+                .find(|&p| match points.len() {
+                    0 => true,
+                    1 => p.distance_squared(points[0]) > 1e-8,
+                    2 => {
+                        (points[1] - points[0])
+                            .cross(p - points[0])
+                            .length_squared()
+                            > 1e-8
+                    }
+                    _ => {
+                        let normal = (points[1] - points[0]).cross(points[2] - points[0]);
+                        normal.dot(p - points[0]).abs() > 1e-6
+                    }
+                })?;
+        points.push(candidate);
+    }
+
+    Some([points[0], points[1], points[2], points[3]])
+}
+
+fn face_normal(polytope: &[Vec3], face: [usize; 3]) -> (Vec3, f32) {
+    let [a, b, c] = face.map(|i| polytope[i]);
+    let normal = (b - a).cross(c - a).normalize_or_zero();
+    (normal, normal.dot(a))
+}
+
+fn add_unique_edge(edges: &mut Vec<(usize, usize)>, a: usize, b: usize) {
+    if let Some(reverse) = edges.iter().position(|&edge| edge == (b, a)) {
+        edges.swap_remove(reverse);
+    } else {
+        edges.push((a, b));
+    }
+}
+
+/// Expanding polytope algorithm. Returns the penetration normal in Minkowski space,
+/// pointing from `a` into `b`, and the penetration depth.
+pub fn epa(a: &Collider, b: &Collider, simplex: &Simplex) -> Option<(Vec3, f32)> {
+    let [p0, p1, p2, p3] = if simplex.len == 4 {
+        simplex.points
+    } else {
+        blow_up(a, b, simplex)?
+    };
+
+    let mut polytope = vec![p0, p1, p2, p3];
+    let mut faces: Vec<[usize; 3]> = vec![[0, 1, 2], [0, 3, 1], [0, 2, 3], [1, 3, 2]];
+
+    // wind every face so its normal points out of the tetrahedron
+    let centroid = (p0 + p1 + p2 + p3) * 0.25;
+    for face in faces.iter_mut() {
+        let (normal, _) = face_normal(&polytope, *face);
+        if normal.dot(polytope[face[0]] - centroid) < 0. {
+            face.swap(1, 2);
+        }
+    }
+
+    for _ in 0..EPA_MAX_ITERATIONS {
+        let (normal, distance) = faces
+            .iter()
+            .map(|&face| face_normal(&polytope, face))
+            .filter(|(normal, _)| *normal != Vec3::ZERO)
+            .min_by(|a, b| a.1.total_cmp(&b.1))?;
+
+        let point = support(a, b, normal);
+        let support_distance = normal.dot(point);
+
+        if support_distance - distance < EPA_TOLERANCE {
+            return Some((normal, distance.max(0.)));
+        }
+
+        // remove every face the new point can see, keeping the horizon edges around the hole
+        let mut edges = vec![];
+        faces.retain(|&face| {
+            let (face_normal, _) = face_normal(&polytope, face);
+            let visible = same_direction(face_normal, point - polytope[face[0]]);
+            if visible {
+                add_unique_edge(&mut edges, face[0], face[1]);
+                add_unique_edge(&mut edges, face[1], face[2]);
+                add_unique_edge(&mut edges, face[2], face[0]);
+            }
+            !visible
+        });
+
+        let new_index = polytope.len();
+        polytope.push(point);
+        for (edge_a, edge_b) in edges {
+            faces.push([edge_a, edge_b, new_index]);
+        }
+    }
+
+    // out of iterations, fall back on the best face found so far
+    faces
+        .iter()
+        .map(|&face| face_normal(&polytope, face))
+        .filter(|(normal, _)| *normal != Vec3::ZERO)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(normal, distance)| (normal, distance.max(0.)))
+}
+
+/// Penetration normal (pointing from `b` towards `a`) and depth of two overlapping colliders
+pub fn gjk_epa(a: &Collider, b: &Collider) -> Option<(Vec3, f32)> {
+    let simplex = gjk(a, b)?;
+    let (normal, depth) = epa(a, b, &simplex)?;
+    if depth <= 0. {
+        return None;
+    }
+    Some((-normal, depth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box(center: Vec3) -> Collider {
+        Collider::from_cuboid(Vec3::ONE, center, Quat::IDENTITY)
+    }
+
+    #[test]
+    fn overlapping_boxes_are_pushed_apart_along_the_shallow_axis() {
+        let a = unit_box(Vec3::ZERO);
+        let b = unit_box(Vec3::new(1.5, 0.2, -0.1));
+
+        let (normal, depth) = gjk_epa(&a, &b).unwrap();
+        assert!(normal.distance(Vec3::NEG_X) < 1e-3, "normal {normal}");
+        assert!((depth - 0.5).abs() < 1e-3, "depth {depth}");
+    }
+
+    #[test]
+    fn overlapping_spheres_meet_along_their_centers() {
+        let a = Collider::from_sphere(1., Vec3::ZERO);
+        let b = Collider::from_sphere(1., Vec3::new(0., 1.2, 0.));
+
+        let (normal, depth) = gjk_epa(&a, &b).unwrap();
+        assert!(normal.distance(Vec3::NEG_Y) < 1e-2, "normal {normal}");
+        assert!((depth - 0.8).abs() < 1e-2, "depth {depth}");
+    }
+
+    #[test]
+    fn separated_boxes_dont_collide() {
+        let a = unit_box(Vec3::ZERO);
+        let b = unit_box(Vec3::new(2.5, 0., 0.));
+
+        assert!(gjk(&a, &b).is_none());
+        assert!(gjk_epa(&a, &b).is_none());
+    }
+}
@@ -0,0 +1,239 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{Collider, broadphase::Aabb};
+
+/// Grid of heights over the local XZ plane, centered on the collider's origin.
+/// Sample `(column, row)` sits at `heights[row * columns + column]`, columns running
+/// along X and rows along Z.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Heightfield {
+    pub heights: Vec<f32>,
+    pub columns: usize,
+    pub rows: usize,
+    /// Distance between two samples along X and Z
+    pub cell_size: Vec2,
+}
+
+impl Heightfield {
+    /// Panics unless there are `columns * rows` heights, at least 2 of each, and cells
+    /// of positive size
+    pub fn new(heights: Vec<f32>, columns: usize, rows: usize, cell_size: Vec2) -> Self {
+        assert!(
+            columns >= 2 && rows >= 2,
+            "a heightfield needs at least 2x2 samples"
+        );
+        assert!(
+            cell_size.cmpgt(Vec2::ZERO).all(),
+            "heightfield cells need a positive size"
+        );
+        assert_eq!(heights.len(), columns * rows, "one height per sample");
+        Self {
+            heights,
+            columns,
+            rows,
+            cell_size,
+        }
+    }
+
+    /// Sample `height` at the local XZ position of every grid point
+    pub fn from_fn(
+        columns: usize,
+        rows: usize,
+        cell_size: Vec2,
+        height: impl Fn(Vec2) -> f32,
+    ) -> Self {
+        let origin = -Vec2::new((columns - 1) as f32, (rows - 1) as f32) * cell_size / 2.;
+        let heights = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| Vec2::new(column as f32, row as f32)))
+            .map(|sample| height(origin + sample * cell_size))
+            .collect();
+        Self::new(heights, columns, rows, cell_size)
+    }
+
+    /// Extent of the grid along X and Z
+    pub fn size(&self) -> Vec2 {
+        Vec2::new((self.columns - 1) as f32, (self.rows - 1) as f32) * self.cell_size
+    }
+
+    pub fn height(&self, column: usize, row: usize) -> f32 {
+        self.heights[row * self.columns + column]
+    }
+
+    /// Local position of a sample
+    pub fn point(&self, column: usize, row: usize) -> Vec3 {
+        let xz = Vec2::new(column as f32, row as f32) * self.cell_size - self.size() / 2.;
+        Vec3::new(xz.x, self.height(column, row), xz.y)
+    }
+
+    /// Lowest and highest sample
+    pub fn height_range(&self) -> (f32, f32) {
+        self.heights
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), &h| {
+                (min.min(h), max.max(h))
+            })
+    }
+
+    /// Height of the surface above a local XZ position, `None` outside the grid
+    pub fn height_at(&self, position: Vec2) -> Option<f32> {
+        let grid = (position + self.size() / 2.) / self.cell_size;
+        let last = Vec2::new((self.columns - 1) as f32, (self.rows - 1) as f32);
+        if grid.cmplt(Vec2::ZERO).any() || grid.cmpgt(last).any() {
+            return None;
+        }
+
+        let cell = grid.floor().min(last - 1.);
+        let (column, row) = (cell.x as usize, cell.y as usize);
+        let fraction = grid - cell;
+        let h00 = self.height(column, row);
+        let h10 = self.height(column + 1, row);
+        let h01 = self.height(column, row + 1);
+        let h11 = self.height(column + 1, row + 1);
+        // same diagonal split as `cell_triangles`
+        Some(if fraction.y >= fraction.x {
+            h00 + (h11 - h01) * fraction.x + (h01 - h00) * fraction.y
+        } else {
+            h00 + (h10 - h00) * fraction.x + (h11 - h10) * fraction.y
+        })
+    }
+
+    /// The two triangles of a cell, split along its `(column, row)`-`(column + 1, row + 1)`
+    /// diagonal and wound counter-clockwise seen from above
+    pub fn cell_triangles(&self, column: usize, row: usize) -> [[Vec3; 3]; 2] {
+        let p00 = self.point(column, row);
+        let p10 = self.point(column + 1, row);
+        let p01 = self.point(column, row + 1);
+        let p11 = self.point(column + 1, row + 1);
+        [[p00, p01, p11], [p00, p11, p10]]
+    }
+
+    /// Triangles of the cells under `bounds` as convex colliders in the heightfield's
+    /// local frame. Each triangle is extruded a cell deep, so anything sinking into the
+    /// terrain is pushed back out of the top and never through it.
+    pub(crate) fn parts_in(&self, bounds: &Aabb) -> Vec<Collider> {
+        let half_size = self.size() / 2.;
+        let last = Vec2::new((self.columns - 2) as f32, (self.rows - 2) as f32);
+        let first = ((bounds.min.xz() + half_size) / self.cell_size)
+            .floor()
+            .max(Vec2::ZERO);
+        let end = ((bounds.max.xz() + half_size) / self.cell_size)
+            .floor()
+            .min(last);
+        if first.cmpgt(end).any() {
+            return vec![];
+        }
+
+        let depth = self.cell_size.max_element();
+        let mut parts = vec![];
+        for row in first.y as usize..=end.y as usize {
+            for column in first.x as usize..=end.x as usize {
+                for triangle in self.cell_triangles(column, row) {
+                    let top = triangle.iter().fold(f32::MIN, |top, p| top.max(p.y));
+                    let bottom = triangle.iter().fold(f32::MAX, |low, p| low.min(p.y)) - depth;
+                    if top < bounds.min.y || bottom > bounds.max.y {
+                        continue;
+                    }
+
+                    let middle = triangle.iter().sum::<Vec3>() / 3.;
+                    let points = triangle
+                        .iter()
+                        .flat_map(|&p| [p, Vec3::new(p.x, bottom, p.z)])
+                        .map(|p| p - middle)
+                        .collect();
+                    parts.push(Collider::from_hull_points(points, middle, Quat::IDENTITY));
+                }
+            }
+        }
+        parts
+    }
+
+    /// Vertices of the hull around every extruded triangle of `parts_in`, in the local
+    /// frame. Their support is the heightfield's.
+    pub(crate) fn hull(&self) -> Vec<Vec3> {
+        let depth = self.cell_size.max_element();
+        // lowest bottom among the extruded triangles touching each sample
+        let mut bottoms = vec![f32::MAX; self.heights.len()];
+        for row in 0..self.rows - 1 {
+            for column in 0..self.columns - 1 {
+                let corners = [(0, 0), (0, 1), (1, 1), (1, 0)]
+                    .map(|(dc, dr)| (row + dr) * self.columns + column + dc);
+                for triangle in [[0, 1, 2], [0, 2, 3]] {
+                    let indices = triangle.map(|i| corners[i]);
+                    let bottom = indices
+                        .iter()
+                        .fold(f32::MAX, |low, &i| low.min(self.heights[i]))
+                        - depth;
+                    for i in indices {
+                        bottoms[i] = bottoms[i].min(bottom);
+                    }
+                }
+            }
+        }
+
+        let points: Vec<Vec3> = (0..self.rows)
+            .flat_map(|row| (0..self.columns).map(move |column| (column, row)))
+            .flat_map(|(column, row)| {
+                let top = self.point(column, row);
+                let bottom = bottoms[row * self.columns + column];
+                [top, top.with_y(bottom)]
+            })
+            .collect();
+        super::hull::convex_hull(&points)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bumpy() -> Heightfield {
+        Heightfield::from_fn(4, 3, Vec2::new(1.5, 0.75), |p| {
+            (p.x * 1.3).sin() * 2. + p.y * p.x * 0.4 - p.y
+        })
+    }
+
+    #[test]
+    fn height_at_lies_on_the_cell_triangles() {
+        let field = bumpy();
+        for row in 0..field.rows - 1 {
+            for column in 0..field.columns - 1 {
+                for triangle in field.cell_triangles(column, row) {
+                    for weights in [Vec3::splat(1. / 3.), Vec3::new(0.6, 0.3, 0.1)] {
+                        let point = triangle[0] * weights.x
+                            + triangle[1] * weights.y
+                            + triangle[2] * weights.z;
+                        let height = field.height_at(point.xz()).unwrap();
+                        assert!(
+                            (height - point.y).abs() < 1e-4,
+                            "cell ({column}, {row}): {height} != {}",
+                            point.y
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn height_at_matches_the_samples_and_stops_at_the_edges() {
+        let field = bumpy();
+        for row in 0..field.rows {
+            for column in 0..field.columns {
+                let point = field.point(column, row);
+                let height = field.height_at(point.xz()).unwrap();
+                assert!((height - point.y).abs() < 1e-4, "{height} != {}", point.y);
+            }
+        }
+
+        let corner = field.size() / 2.;
+        assert!(field.height_at(corner + Vec2::X * 0.01).is_none());
+        assert!(field.height_at(-corner - Vec2::Y * 0.01).is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn cells_need_a_positive_size() {
+        Heightfield::new(vec![0.; 4], 2, 2, Vec2::new(1., 0.));
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+
+/// Points closer than this are merged, and a point has to be this far outside a face to see it
+const HULL_EPSILON: f32 = 1e-5;
+
+/// Vertices of the convex hull of `points`, found by adding one point at a time to a
+/// starting tetrahedron. Flat or degenerate point sets are returned deduplicated but
+/// otherwise untouched, which still gives the right support points.
+pub fn convex_hull(points: &[Vec3]) -> Vec<Vec3> {
+    let unique = dedup(points);
+
+    let Some(start) = initial_tetrahedron(&unique) else {
+        return unique;
+    };
+    let interior = start.iter().map(|&i| unique[i]).sum::<Vec3>() / 4.;
+
+    let outward = |face: [usize; 3]| {
+        let [a, b, c] = face.map(|i| unique[i]);
+        if (b - a).cross(c - a).dot(a - interior) < 0. {
+            [face[0], face[2], face[1]]
+        } else {
+            face
+        }
+    };
+    let mut faces: Vec<[usize; 3]> = [[0, 1, 2], [0, 1, 3], [0, 2, 3], [1, 2, 3]]
+        .into_iter()
+        .map(|face| outward(face.map(|i| start[i])))
+        .collect();
+
+    for point in 0..unique.len() {
+        if start.contains(&point) {
+            continue;
+        }
+
+        let p = unique[point];
+        let visible: Vec<bool> = faces
+            .iter()
+            .map(|face| {
+                let [a, b, c] = face.map(|i| unique[i]);
+                let normal = (b - a).cross(c - a).normalize_or_zero();
+                normal.dot(p - a) > HULL_EPSILON
+            })
+            .collect();
+        if !visible.contains(&true) {
+            continue;
+        }
+
+        // edges of the visible region that border a hidden face, kept in winding order
+        let visible_edges: HashSet<(usize, usize)> = faces
+            .iter()
+            .zip(&visible)
+            .filter(|(_, visible)| **visible)
+            .flat_map(|(face, _)| [(face[0], face[1]), (face[1], face[2]), (face[2], face[0])])
+            .collect();
+        let horizon: Vec<(usize, usize)> = visible_edges
+            .iter()
+            .filter(|(a, b)| !visible_edges.contains(&(*b, *a)))
+            .copied()
+            .collect();
+
+        let mut kept = Vec::with_capacity(faces.len() + horizon.len());
+        for (face, visible) in faces.iter().zip(&visible) {
+            if !visible {
+                kept.push(*face);
+            }
+        }
+        kept.extend(horizon.into_iter().map(|(a, b)| [a, b, point]));
+        faces = kept;
+    }
+
+    let mut used: Vec<usize> = faces.iter().flatten().copied().collect();
+    used.sort_unstable();
+    used.dedup();
+    used.into_iter().map(|i| unique[i]).collect()
+}
+
+/// Four points spanning a volume, `None` when every point lies on one plane
+fn initial_tetrahedron(points: &[Vec3]) -> Option<[usize; 4]> {
+    let first = 0;
+    let second = (0..points.len())
+        .max_by(|&i, &j| {
+            let distance = |k: usize| points[k].distance_squared(points[first]);
+            distance(i).total_cmp(&distance(j))
+        })
+        .filter(|&i| points[i].distance_squared(points[first]) > HULL_EPSILON)?;
+
+    let line = (points[second] - points[first]).normalize();
+    let line_distance = |k: usize| {
+        let offset = points[k] - points[first];
+        (offset - line * offset.dot(line)).length_squared()
+    };
+    let third = (0..points.len())
+        .max_by(|&i, &j| line_distance(i).total_cmp(&line_distance(j)))
+        .filter(|&i| line_distance(i) > HULL_EPSILON)?;
+
+    let normal = (points[second] - points[first])
+        .cross(points[third] - points[first])
+        .normalize();
+    let plane_distance = |k: usize| normal.dot(points[k] - points[first]).abs();
+    let fourth = (0..points.len())
+        .max_by(|&i, &j| plane_distance(i).total_cmp(&plane_distance(j)))
+        .filter(|&i| plane_distance(i) > HULL_EPSILON)?;
+
+    Some([first, second, third, fourth])
+}
+
+/// `points` in order without the ones within `HULL_EPSILON` of an earlier one. Points are
+/// bucketed in a grid of `HULL_EPSILON` cells, so only the neighboring cells are searched.
+fn dedup(points: &[Vec3]) -> Vec<Vec3> {
+    let cell = |point: Vec3| (point / HULL_EPSILON).floor().to_array().map(|c| c as i64);
+    let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+    let mut unique: Vec<Vec3> = Vec::with_capacity(points.len());
+    for &point in points {
+        let [x, y, z] = cell(point);
+        let near = (-1..=1)
+            .flat_map(|dx| (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| [dx, dy, dz])))
+            .filter_map(|[dx, dy, dz]| grid.get(&[x + dx, y + dy, z + dz]))
+            .flatten()
+            .any(|&kept| unique[kept].distance_squared(point) < HULL_EPSILON * HULL_EPSILON);
+        if !near {
+            grid.entry([x, y, z]).or_default().push(unique.len());
+            unique.push(point);
+        }
+    }
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::collisions::Collider;
+
+    fn cube_corners() -> Vec<Vec3> {
+        (0..8)
+            .map(|i| {
+                Vec3::new((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32) * 2. - 1.
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dedup_merges_points_closer_than_epsilon() {
+        let a = Vec3::new(0.3, -1.2, 4.);
+        let b = Vec3::new(-2., 0.5, 1.);
+        let unique = dedup(&[a, a, b, a + Vec3::splat(HULL_EPSILON * 0.1), b]);
+        assert_eq!(unique, vec![a, b]);
+    }
+
+    #[test]
+    fn hull_keeps_only_the_corners() {
+        let corners = cube_corners();
+        let mut points = corners.clone();
+        // exact and near duplicates, interior points and points on the faces
+        points.extend(corners.iter().map(|&c| c + Vec3::splat(HULL_EPSILON * 0.1)));
+        points.extend(corners.iter().rev());
+        points.extend([
+            Vec3::ZERO,
+            Vec3::new(0.2, -0.5, 0.7),
+            Vec3::X,
+            Vec3::new(0.5, 1., 0.),
+        ]);
+
+        let hull = convex_hull(&points);
+        assert_eq!(hull.len(), 8, "{hull:?}");
+        for corner in corners {
+            assert!(hull.iter().any(|&p| p.distance(corner) < 1e-4), "{corner}");
+        }
+    }
+
+    #[test]
+    fn flat_points_are_only_deduplicated() {
+        let square = [Vec3::ZERO, Vec3::X, Vec3::Z, Vec3::ONE.with_y(0.), Vec3::X];
+        assert_eq!(convex_hull(&square).len(), 4);
+    }
+
+    #[test]
+    fn hull_collider_supports_its_corners() {
+        let rotation = Quat::from_rotation_y(std::f32::consts::FRAC_PI_4);
+        let center = Vec3::new(3., 1., -2.);
+        let mut points = cube_corners();
+        points.push(Vec3::new(0.1, 0.2, 0.3));
+        let collider = Collider::from_convex_hull(&points, center, rotation);
+
+        assert_eq!(collider.hull_points().len(), 8);
+        for corner in cube_corners() {
+            let world = center + rotation * corner;
+            let support = collider.support(world - center);
+            assert!(support.distance(world) < 1e-4, "{support} != {world}");
+        }
+    }
+}
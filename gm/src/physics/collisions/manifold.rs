@@ -0,0 +1,527 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{Collider, ColliderShape, ContactInfo};
+
+/// Most points a single manifold keeps
+pub const MAX_MANIFOLD_POINTS: usize = 4;
+/// How far (in local space) a contact may drift and still count as the same contact
+const CONTACT_MATCH_DISTANCE: f32 = 0.05;
+/// Clipped points this far above the reference face are still kept, to avoid flicker
+const CONTACT_SLOP: f32 = 0.01;
+/// Hull points within this fraction of the hull's size from the furthest one share its face
+const HULL_FACE_TOLERANCE: f32 = 0.02;
+/// Parts of a composite whose normals are closer than this to the deepest one share its manifold
+const PART_NORMAL_AGREEMENT: f32 = 0.9;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct ContactPoint {
+    /// World space point on collider a
+    pub point_a: Vec3,
+    /// World space point on collider b
+    pub point_b: Vec3,
+    /// `point_a` in a's local frame
+    pub local_a: Vec3,
+    /// `point_b` in b's local frame
+    pub local_b: Vec3,
+    pub penetration: f32,
+    /// Impulse the solver applied along the normal, kept to warm start the next step
+    pub normal_impulse: f32,
+    /// Friction impulse the solver applied, kept to warm start the next step
+    pub tangent_impulse: Vec3,
+}
+
+impl ContactPoint {
+    pub fn new(point_a: Vec3, point_b: Vec3, penetration: f32, a: &Collider, b: &Collider) -> Self {
+        Self {
+            point_a,
+            point_b,
+            local_a: a.rotation.inverse() * (point_a - a.center),
+            local_b: b.rotation.inverse() * (point_b - b.center),
+            penetration,
+            normal_impulse: 0.,
+            tangent_impulse: Vec3::ZERO,
+        }
+    }
+}
+
+/// Manifolds of every touching pair, keyed by `(entity_a, entity_b)` with `entity_a < entity_b`.
+/// Rebuilt every step, carrying solver impulses over from matching points of the last one.
+#[derive(Resource, Default)]
+pub struct ContactManifolds {
+    pub contacts: BTreeMap<(Entity, Entity), ContactInfo>,
+    previous: BTreeMap<(Entity, Entity), ContactInfo>,
+}
+
+impl ContactManifolds {
+    /// Start a new step, keeping last step's manifolds around for matching
+    pub fn begin_step(&mut self) {
+        self.previous = std::mem::take(&mut self.contacts);
+    }
+
+    /// Store this step's contact for a pair, inheriting impulses from persisting points
+    pub fn insert(&mut self, key: (Entity, Entity), mut contact: ContactInfo) {
+        if let Some(old) = self.previous.get(&key) {
+            for point in contact.points.iter_mut() {
+                let matching = old.points.iter().find(|old_point| {
+                    old_point.local_a.distance(point.local_a) < CONTACT_MATCH_DISTANCE
+                        && old_point.local_b.distance(point.local_b) < CONTACT_MATCH_DISTANCE
+                });
+                if let Some(old_point) = matching {
+                    point.normal_impulse = old_point.normal_impulse;
+                    point.tangent_impulse = old_point.tangent_impulse;
+                }
+            }
+        }
+        self.contacts.insert(key, contact);
+    }
+
+    /// Replace every manifold with ones saved earlier, see `PhysicsSnapshot`
+    pub fn restore(&mut self, contacts: BTreeMap<(Entity, Entity), ContactInfo>) {
+        self.contacts = contacts;
+        self.previous.clear();
+    }
+
+    /// Carry last step's manifold of a pair over unchanged, for pairs that weren't retested
+    pub fn keep(&mut self, key: (Entity, Entity)) {
+        if let Some(contact) = self.previous.get(&key) {
+            self.contacts.insert(key, contact.clone());
+        }
+    }
+
+    /// Pairs touching this step that weren't touching the last one
+    pub fn started(&self) -> impl Iterator<Item = (&(Entity, Entity), &ContactInfo)> {
+        self.contacts
+            .iter()
+            .filter(|(key, _)| !self.previous.contains_key(key))
+    }
+
+    /// Pairs that stopped touching this step, with their last contact
+    pub fn ended(&self) -> impl Iterator<Item = (&(Entity, Entity), &ContactInfo)> {
+        self.previous
+            .iter()
+            .filter(|(key, _)| !self.contacts.contains_key(key))
+    }
+
+    pub fn get(&self, a: Entity, b: Entity) -> Option<&ContactInfo> {
+        self.contacts.get(&(a.min(b), a.max(b)))
+    }
+}
+
+/// Which SAT axis separated two boxes the least
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SatAxis {
+    FaceA(usize),
+    FaceB(usize),
+    Edge(usize, usize),
+}
+
+/// Contact points of two overlapping boxes.
+///
+/// `normal` points from `b` towards `a` and `depth` is the SAT overlap along it.
+pub fn cuboid_contact_points(
+    a: &Collider,
+    b: &Collider,
+    normal: Vec3,
+    depth: f32,
+    axis: SatAxis,
+) -> Vec<ContactPoint> {
+    match axis {
+        SatAxis::FaceA(_) => clip_faces(a, b, -normal)
+            .into_iter()
+            .map(|(on_reference, on_incident, penetration)| {
+                ContactPoint::new(on_reference, on_incident, penetration, a, b)
+            })
+            .collect(),
+        SatAxis::FaceB(_) => clip_faces(b, a, normal)
+            .into_iter()
+            .map(|(on_reference, on_incident, penetration)| {
+                ContactPoint::new(on_incident, on_reference, penetration, a, b)
+            })
+            .collect(),
+        SatAxis::Edge(edge_a, edge_b) => {
+            let (point_a, point_b) = edge_contact(a, b, normal, edge_a, edge_b);
+            vec![ContactPoint::new(point_a, point_b, depth, a, b)]
+        }
+    }
+}
+
+/// Clip the incident face of `incident` against the face of `reference` facing along
+/// `reference_normal`. Returns `(point on reference, point on incident, penetration)`.
+fn clip_faces(
+    reference: &Collider,
+    incident: &Collider,
+    reference_normal: Vec3,
+) -> Vec<(Vec3, Vec3, f32)> {
+    let (reference_axis, _) = most_aligned_axis(reference, reference_normal);
+    let face_center = reference.center + reference_normal * reference.half_extents[reference_axis];
+
+    let polygon = incident_face(incident, reference_normal);
+
+    // clip against the four side planes of the reference face
+    let mut clipped = polygon.to_vec();
+    for side in [(reference_axis + 1) % 3, (reference_axis + 2) % 3] {
+        let side_normal = reference.axes[side];
+        let extent = reference.half_extents[side];
+        let offset = side_normal.dot(reference.center);
+        clipped = clip_polygon(&clipped, side_normal, offset + extent);
+        clipped = clip_polygon(&clipped, -side_normal, -offset + extent);
+    }
+
+    let points: Vec<(Vec3, Vec3, f32)> = clipped
+        .into_iter()
+        .filter_map(|point| {
+            let separation = reference_normal.dot(point - face_center);
+            if separation > CONTACT_SLOP {
+                return None;
+            }
+            let on_reference = point - reference_normal * separation;
+            Some((on_reference, point, (-separation).max(0.)))
+        })
+        .collect();
+
+    reduce_points(points)
+}
+
+/// Box axis most parallel to `direction`, and whether it points the same way
+fn most_aligned_axis(collider: &Collider, direction: Vec3) -> (usize, f32) {
+    let mut best = 0;
+    let mut best_dot: f32 = 0.;
+    for (i, axis) in collider.axes.iter().enumerate() {
+        let dot = axis.dot(direction);
+        if dot.abs() > best_dot.abs() {
+            best = i;
+            best_dot = dot;
+        }
+    }
+    (best, best_dot.signum())
+}
+
+/// Corners of the face of `incident` most opposed to `reference_normal`
+fn incident_face(incident: &Collider, reference_normal: Vec3) -> [Vec3; 4] {
+    let (axis, sign) = most_aligned_axis(incident, reference_normal);
+    let face_normal = incident.axes[axis] * -sign;
+    let center = incident.center + face_normal * incident.half_extents[axis];
+
+    let u = incident.axes[(axis + 1) % 3] * incident.half_extents[(axis + 1) % 3];
+    let v = incident.axes[(axis + 2) % 3] * incident.half_extents[(axis + 2) % 3];
+
+    [
+        center + u + v,
+        center - u + v,
+        center - u - v,
+        center + u - v,
+    ]
+}
+
+/// Sutherland-Hodgman against the half space `plane_normal . p <= offset`
+fn clip_polygon(polygon: &[Vec3], plane_normal: Vec3, offset: f32) -> Vec<Vec3> {
+    let mut output = Vec::with_capacity(polygon.len() + 4);
+    if polygon.is_empty() {
+        return output;
+    }
+
+    let mut start = polygon[polygon.len() - 1];
+    let mut start_distance = plane_normal.dot(start) - offset;
+    for &end in polygon {
+        let end_distance = plane_normal.dot(end) - offset;
+
+        if start_distance <= 0. && end_distance <= 0. {
+            output.push(end);
+        } else if start_distance <= 0. {
+            let t = start_distance / (start_distance - end_distance);
+            output.push(start.lerp(end, t));
+        } else if end_distance <= 0. {
+            let t = start_distance / (start_distance - end_distance);
+            output.push(start.lerp(end, t));
+            output.push(end);
+        }
+
+        start = end;
+        start_distance = end_distance;
+    }
+
+    output
+}
+
+/// Cut a clipped polygon down to the four points that best cover its area
+fn reduce_points(points: Vec<(Vec3, Vec3, f32)>) -> Vec<(Vec3, Vec3, f32)> {
+    reduce_by(points, |point| point.2, |point| point.1)
+}
+
+/// Keep the deepest point, then whichever point is furthest from everything kept so far
+fn reduce_by<T>(
+    mut points: Vec<T>,
+    depth: impl Fn(&T) -> f32,
+    position: impl Fn(&T) -> Vec3,
+) -> Vec<T> {
+    if points.len() <= MAX_MANIFOLD_POINTS {
+        return points;
+    }
+
+    let mut kept = Vec::with_capacity(MAX_MANIFOLD_POINTS);
+
+    // deepest point first, it matters most for resolving penetration
+    let deepest = (0..points.len())
+        .max_by(|&i, &j| depth(&points[i]).total_cmp(&depth(&points[j])))
+        .unwrap_or(0);
+    kept.push(points.swap_remove(deepest));
+
+    while kept.len() < MAX_MANIFOLD_POINTS && !points.is_empty() {
+        let furthest = (0..points.len())
+            .max_by(|&i, &j| {
+                let distance = |index: usize| {
+                    kept.iter()
+                        .map(|k| position(k).distance_squared(position(&points[index])))
+                        .fold(f32::MAX, f32::min)
+                };
+                distance(i).total_cmp(&distance(j))
+            })
+            .unwrap_or(0);
+        kept.push(points.swap_remove(furthest));
+    }
+
+    kept
+}
+
+/// Contacts of a composite's parts folded into one manifold around the deepest part's
+/// normal. Parts pushing some other way are left for a later step.
+pub fn merge_part_contacts(contacts: Vec<ContactInfo>) -> Option<ContactInfo> {
+    let deepest = contacts
+        .iter()
+        .max_by(|a, b| a.penetration_depth.total_cmp(&b.penetration_depth))?
+        .clone();
+    let points = contacts
+        .iter()
+        .filter(|contact| contact.normal.dot(deepest.normal) > PART_NORMAL_AGREEMENT)
+        .flat_map(|contact| contact.points.iter().copied())
+        .collect();
+
+    Some(ContactInfo {
+        points: reduce_by(points, |point| point.penetration, |point| point.point_b),
+        ..deepest
+    })
+}
+
+/// World space corners of the face of a box or hull furthest along `direction`, in
+/// winding order. A hull can give fewer than three when an edge or corner is furthest.
+fn support_face(collider: &Collider, direction: Vec3) -> Option<Vec<Vec3>> {
+    match collider.collider_shape {
+        ColliderShape::Cuboid => Some(incident_face(collider, -direction).to_vec()),
+        ColliderShape::ConvexHull => {
+            let local = collider.rotation.inverse() * direction;
+            let points = collider.hull_points();
+            let furthest = points
+                .iter()
+                .map(|point| point.dot(local))
+                .fold(f32::MIN, f32::max);
+            let tolerance = HULL_FACE_TOLERANCE * collider.half_extents.max_element();
+            let mut face: Vec<Vec3> = points
+                .iter()
+                .copied()
+                .filter(|point| point.dot(local) >= furthest - tolerance)
+                .collect();
+
+            let middle = face.iter().sum::<Vec3>() / face.len() as f32;
+            let (u, v) = local.any_orthonormal_pair();
+            let angle = |point: &Vec3| (*point - middle).dot(v).atan2((*point - middle).dot(u));
+            face.sort_by(|a, b| angle(a).total_cmp(&angle(b)));
+
+            Some(
+                face.into_iter()
+                    .map(|point| collider.center + collider.rotation * point)
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Manifold of two flat faced shapes from a GJK normal, by clipping the face of one
+/// against the face of the other. `None` when neither side has a face along the normal.
+pub fn face_contact_points(a: &Collider, b: &Collider, normal: Vec3) -> Option<Vec<ContactPoint>> {
+    let face_a = support_face(a, -normal)?;
+    let face_b = support_face(b, normal)?;
+
+    let points = if face_b.len() >= 3 {
+        clip_polygons(&face_b, &face_a, normal)
+            .into_iter()
+            .map(|(on_b, on_a, penetration)| ContactPoint::new(on_a, on_b, penetration, a, b))
+            .collect::<Vec<_>>()
+    } else if face_a.len() >= 3 {
+        clip_polygons(&face_a, &face_b, -normal)
+            .into_iter()
+            .map(|(on_a, on_b, penetration)| ContactPoint::new(on_a, on_b, penetration, a, b))
+            .collect()
+    } else {
+        return None;
+    };
+
+    (!points.is_empty()).then_some(points)
+}
+
+/// Clip `incident` against the side planes of the convex polygon `reference`, whose face
+/// points along `reference_normal`. Returns `(point on reference, point on incident, penetration)`.
+fn clip_polygons(
+    reference: &[Vec3],
+    incident: &[Vec3],
+    reference_normal: Vec3,
+) -> Vec<(Vec3, Vec3, f32)> {
+    let middle = reference.iter().sum::<Vec3>() / reference.len() as f32;
+
+    let mut clipped = incident.to_vec();
+    for (i, &start) in reference.iter().enumerate() {
+        let end = reference[(i + 1) % reference.len()];
+        let mut side_normal = (end - start).cross(reference_normal).normalize_or_zero();
+        if side_normal.dot(middle - start) > 0. {
+            side_normal = -side_normal;
+        }
+        clipped = clip_polygon(&clipped, side_normal, side_normal.dot(start));
+    }
+
+    let points = clipped
+        .into_iter()
+        .filter_map(|point| {
+            let separation = reference_normal.dot(point - middle);
+            if separation > CONTACT_SLOP {
+                return None;
+            }
+            let on_reference = point - reference_normal * separation;
+            Some((on_reference, point, (-separation).max(0.)))
+        })
+        .collect();
+
+    reduce_points(points)
+}
+
+/// Closest points between the two colliding edges of an edge-edge contact
+fn edge_contact(
+    a: &Collider,
+    b: &Collider,
+    normal: Vec3,
+    edge_a: usize,
+    edge_b: usize,
+) -> (Vec3, Vec3) {
+    // the edge of a nearest b lies towards -normal, the edge of b nearest a towards +normal
+    let edge_center = |collider: &Collider, edge: usize, towards: Vec3| {
+        let mut center = collider.center;
+        for i in 0..3 {
+            if i != edge {
+                let sign = collider.axes[i].dot(towards).signum();
+                center += collider.axes[i] * collider.half_extents[i] * sign;
+            }
+        }
+        center
+    };
+
+    let center_a = edge_center(a, edge_a, -normal);
+    let center_b = edge_center(b, edge_b, normal);
+    let direction_a = a.axes[edge_a];
+    let direction_b = b.axes[edge_b];
+
+    // closest points of the two edge lines, clamped to the edge lengths
+    let offset = center_a - center_b;
+    let d = direction_a.dot(direction_b);
+    let denominator = 1. - d * d;
+    let (t_a, t_b) = if denominator.abs() < 1e-6 {
+        (0., 0.)
+    } else {
+        let e = direction_a.dot(offset);
+        let f = direction_b.dot(offset);
+        ((d * f - e) / denominator, (f - d * e) / denominator)
+    };
+    let t_a = t_a.clamp(-a.half_extents[edge_a], a.half_extents[edge_a]);
+    let t_b = t_b.clamp(-b.half_extents[edge_b], b.half_extents[edge_b]);
+
+    (center_a + direction_a * t_a, center_b + direction_b * t_b)
+}
+
+/// Single point manifold for shapes without flat faces
+pub fn single_point(a: &Collider, b: &Collider, normal: Vec3, depth: f32) -> Vec<ContactPoint> {
+    vec![ContactPoint::new(
+        a.support(-normal),
+        b.support(normal),
+        depth,
+        a,
+        b,
+    )]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::{bodies::Velocity, collisions::collider_systems::get_collision_info};
+
+    const KEY: (Entity, Entity) = (Entity::from_raw(1), Entity::from_raw(2));
+
+    /// Contact of a box resting slightly sunk into the ground at `position`
+    fn resting_box(position: Vec3) -> ContactInfo {
+        let still = Velocity {
+            linear: Vec3::ZERO,
+            angular: Vec3::ZERO,
+        };
+        let ground = Collider::from_cuboid(Vec3::new(5., 0.5, 5.), Vec3::ZERO, Quat::IDENTITY);
+        let cube = Collider::from_cuboid(Vec3::splat(0.5), position, Quat::IDENTITY);
+        get_collision_info(&cube, &still, &ground, &still).unwrap()
+    }
+
+    /// Solve a step by giving every point its own impulses
+    fn solved(manifolds: &mut ContactManifolds) -> Vec<ContactPoint> {
+        let contact = manifolds.contacts.get_mut(&KEY).unwrap();
+        for (i, point) in contact.points.iter_mut().enumerate() {
+            point.normal_impulse = i as f32 + 1.;
+            point.tangent_impulse = Vec3::new(i as f32, 0., -0.5);
+        }
+        contact.points.clone()
+    }
+
+    #[test]
+    fn resting_box_has_a_full_manifold() {
+        let contact = resting_box(Vec3::new(0., 0.95, 0.));
+        assert_eq!(contact.points.len(), MAX_MANIFOLD_POINTS);
+        for point in &contact.points {
+            assert!((point.penetration - 0.05).abs() < 1e-3, "{point:?}");
+        }
+    }
+
+    #[test]
+    fn persisting_points_keep_their_impulses() {
+        let mut manifolds = ContactManifolds::default();
+        manifolds.begin_step();
+        manifolds.insert(KEY, resting_box(Vec3::new(0., 0.95, 0.)));
+        let previous = solved(&mut manifolds);
+
+        manifolds.begin_step();
+        manifolds.insert(KEY, resting_box(Vec3::new(0.01, 0.95, 0.005)));
+        let points = &manifolds.get(KEY.1, KEY.0).unwrap().points;
+        assert_eq!(points.len(), previous.len());
+        for point in points {
+            let old = previous
+                .iter()
+                .min_by(|a, b| {
+                    let distance = |p: &ContactPoint| p.local_a.distance(point.local_a);
+                    distance(a).total_cmp(&distance(b))
+                })
+                .unwrap();
+            assert_eq!(point.normal_impulse, old.normal_impulse);
+            assert_eq!(point.tangent_impulse, old.tangent_impulse);
+        }
+    }
+
+    #[test]
+    fn moved_points_start_from_zero() {
+        let mut manifolds = ContactManifolds::default();
+        manifolds.begin_step();
+        manifolds.insert(KEY, resting_box(Vec3::new(0., 0.95, 0.)));
+        solved(&mut manifolds);
+
+        manifolds.begin_step();
+        manifolds.insert(KEY, resting_box(Vec3::new(1., 0.95, 0.)));
+        for point in &manifolds.get(KEY.0, KEY.1).unwrap().points {
+            assert_eq!(point.normal_impulse, 0.);
+            assert_eq!(point.tangent_impulse, Vec3::ZERO);
+        }
+    }
+}
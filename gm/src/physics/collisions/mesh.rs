@@ -0,0 +1,200 @@
+use bevy::{
+    prelude::*,
+    render::mesh::{PrimitiveTopology, VertexAttributeValues},
+    scene::SceneInstanceReady,
+};
+
+use super::{Collider, broadphase::Aabb};
+use crate::physics::bodies::{Damping, RigidbodyComponent};
+
+/// Vertex positions of a mesh with `transform` applied, `None` without float positions
+pub fn mesh_vertices(mesh: &Mesh, transform: &Transform) -> Option<Vec<Vec3>> {
+    let VertexAttributeValues::Float32x3(positions) = mesh.attribute(Mesh::ATTRIBUTE_POSITION)?
+    else {
+        return None;
+    };
+    Some(
+        positions
+            .iter()
+            .map(|&position| transform.transform_point(Vec3::from_array(position)))
+            .collect(),
+    )
+}
+
+/// Triangles of a triangle list mesh, indexed or not. Empty for every other topology.
+pub fn mesh_triangles(mesh: &Mesh) -> Vec<[u32; 3]> {
+    if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+        return vec![];
+    }
+
+    let indices: Vec<u32> = match mesh.indices() {
+        Some(indices) => indices.iter().map(|index| index as u32).collect(),
+        None => (0..mesh.count_vertices() as u32).collect(),
+    };
+    indices
+        .chunks_exact(3)
+        .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+        .collect()
+}
+
+impl Collider {
+    /// Triangle mesh collider of a render mesh, placed by `transform` with its scale baked
+    /// into the vertices
+    pub fn trimesh_from_mesh(mesh: &Mesh, transform: &Transform) -> Option<Self> {
+        let local = Transform::from_scale(transform.scale);
+        Some(Self::from_trimesh(
+            &mesh_vertices(mesh, &local)?,
+            &mesh_triangles(mesh),
+            transform.translation,
+            transform.rotation,
+        ))
+    }
+
+    /// Convex hull of a render mesh, placed by `transform` with its scale baked into the hull
+    pub fn convex_hull_from_mesh(mesh: &Mesh, transform: &Transform) -> Option<Self> {
+        let local = Transform::from_scale(transform.scale);
+        Some(Self::from_convex_hull(
+            &mesh_vertices(mesh, &local)?,
+            transform.translation,
+            transform.rotation,
+        ))
+    }
+}
+
+/// Builds a rigidbody for the entity from the meshes of its `SceneRoot` once the scene
+/// has spawned, then removes itself. The entity's `Transform` is the body's pose.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct SceneCollider {
+    pub shape: SceneColliderShape,
+    /// Mass of a dynamic body, `None` for a static one
+    pub mass: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SceneColliderShape {
+    /// Every triangle of the scene, for level geometry
+    TriMesh,
+    /// One hull around the whole scene, for simple props
+    ConvexHull,
+    /// A hull for each mesh of the scene, for props made of several parts
+    Compound,
+}
+
+impl SceneCollider {
+    /// Static triangle mesh for level geometry
+    pub fn level() -> Self {
+        Self {
+            shape: SceneColliderShape::TriMesh,
+            mass: None,
+        }
+    }
+
+    /// Dynamic prop with a collider of the given `shape`
+    pub fn prop(shape: SceneColliderShape, mass: f32) -> Self {
+        Self {
+            shape,
+            mass: Some(mass),
+        }
+    }
+}
+
+/// A mesh of the scene, in the scene root's frame
+struct SceneMesh {
+    vertices: Vec<Vec3>,
+    triangles: Vec<[u32; 3]>,
+}
+
+pub(crate) fn build_scene_colliders(
+    trigger: Trigger<SceneInstanceReady>,
+    mut commands: Commands,
+    roots: Query<(&SceneCollider, &Transform)>,
+    children: Query<&Children>,
+    nodes: Query<(&Transform, Option<&Mesh3d>)>,
+    meshes: Res<Assets<Mesh>>,
+) {
+    let root = trigger.target();
+    let Ok((scene_collider, root_transform)) = roots.get(root) else {
+        return;
+    };
+
+    // global transforms aren't propagated yet, so compose the hierarchy by hand
+    let mut scene_meshes = vec![];
+    let mut stack = vec![(root, Transform::from_scale(root_transform.scale))];
+    while let Some((entity, transform)) = stack.pop() {
+        let Ok(node_children) = children.get(entity) else {
+            continue;
+        };
+        for child in node_children.iter() {
+            let Ok((local, mesh)) = nodes.get(child) else {
+                continue;
+            };
+            let transform = transform.mul_transform(*local);
+            let mesh = mesh.and_then(|mesh| meshes.get(&mesh.0));
+            if let Some(vertices) = mesh.and_then(|mesh| mesh_vertices(mesh, &transform)) {
+                scene_meshes.push(SceneMesh {
+                    vertices,
+                    triangles: mesh.map(mesh_triangles).unwrap_or_default(),
+                });
+            }
+            stack.push((child, transform));
+        }
+    }
+
+    commands.entity(root).remove::<SceneCollider>();
+    if scene_meshes.is_empty() {
+        warn!("scene of {root} has no meshes to build a collider from");
+        return;
+    }
+
+    let (center, rotation) = (root_transform.translation, root_transform.rotation);
+    let collider = match scene_collider.shape {
+        SceneColliderShape::TriMesh => {
+            let mut vertices = vec![];
+            let mut triangles = vec![];
+            for mesh in &scene_meshes {
+                let offset = vertices.len() as u32;
+                triangles.extend(mesh.triangles.iter().map(|t| t.map(|i| i + offset)));
+                vertices.extend_from_slice(&mesh.vertices);
+            }
+            Collider::from_trimesh(&vertices, &triangles, center, rotation)
+        }
+        SceneColliderShape::ConvexHull => {
+            let vertices: Vec<Vec3> = scene_meshes
+                .iter()
+                .flat_map(|mesh| mesh.vertices.iter().copied())
+                .collect();
+            Collider::from_convex_hull(&vertices, center, rotation)
+        }
+        SceneColliderShape::Compound => {
+            let parts = scene_meshes
+                .iter()
+                .map(|mesh| {
+                    // centered on its own bounds, so each part gets a tight bounding box
+                    let middle = Aabb::from_points(&mesh.vertices).center();
+                    let points: Vec<Vec3> = mesh
+                        .vertices
+                        .iter()
+                        .map(|vertex| *vertex - middle)
+                        .collect();
+                    Collider::from_convex_hull(&points, middle, Quat::IDENTITY)
+                })
+                .collect();
+            Collider::from_compound(parts, center, rotation)
+        }
+    };
+
+    let body = match scene_collider.mass {
+        Some(mass) => RigidbodyComponent::new_dynamic(
+            mass,
+            collider,
+            0.5,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Damping::default(),
+            0.,
+        ),
+        None => RigidbodyComponent::new_static(collider),
+    };
+    commands.entity(root).insert(body);
+}
@@ -1,40 +1,73 @@
+pub mod broadphase;
+pub mod cast;
 pub mod collider_systems;
+pub mod events;
+pub mod gjk;
+pub mod heightfield;
+pub mod hull;
+pub mod manifold;
+pub mod mesh;
 
-use bevy::{prelude::*, ui::update};
-use collider_systems::{detect_object_collisions, detect_player_collisions, update_vertices};
+use std::sync::Arc;
 
-use crate::player::controller::player_movement;
+use bevy::prelude::*;
+use broadphase::{Aabb, BroadPhase, mark_unindexed, update_broadphase, update_query_proxies};
+use collider_systems::{detect_object_collisions, detect_player_collisions, update_vertices};
+use events::{CollisionEnded, CollisionStarted, emit_collision_events};
+use heightfield::Heightfield;
+use manifold::{ContactManifolds, ContactPoint};
+use mesh::build_scene_colliders;
+use serde::{Deserialize, Serialize};
 
-use super::bodies::Velocity;
+use super::{
+    bodies::{Velocity, integrate_positions},
+    prelude::PhysicsSet,
+};
 
 pub struct CollisionPlugin;
 impl Plugin for CollisionPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            (
-                update_vertices,
-                detect_object_collisions.after(update_vertices),
-                detect_player_collisions
-                    .after(update_vertices)
-                    .before(player_movement),
-            )
-                .run_if(in_state(crate::gamestate::AppState::Playing)),
-        );
+        app.init_resource::<BroadPhase>()
+            .init_resource::<ContactManifolds>()
+            .add_event::<CollisionStarted>()
+            .add_event::<CollisionEnded>()
+            .add_observer(build_scene_colliders)
+            .add_observer(mark_unindexed)
+            .add_systems(
+                FixedUpdate,
+                (
+                    update_vertices.in_set(PhysicsSet::Prepare),
+                    update_broadphase.in_set(PhysicsSet::BroadPhase),
+                    (
+                        detect_object_collisions,
+                        emit_collision_events.after(detect_object_collisions),
+                        detect_player_collisions,
+                    )
+                        .in_set(PhysicsSet::NarrowPhase),
+                    update_query_proxies
+                        .after(integrate_positions)
+                        .in_set(PhysicsSet::Integrate),
+                ),
+            );
     }
 }
 
-#[derive(Debug)]
+/// Contact between two colliders, the normal pointing from b towards a
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContactInfo {
     pub normal: Vec3,
     pub penetration_depth: f32,
+    /// Deepest point on a
     pub contact_point_a: Vec3,
     pub a_vel: Velocity,
+    /// Deepest point on b
     pub contact_point_b: Vec3,
     pub b_vel: Velocity,
+    /// Manifold of up to four contact points, each with its own penetration
+    pub points: Vec<ContactPoint>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ColliderVertexInfo {
     pub vertices: Vec<Vec3>,
 }
@@ -60,7 +93,15 @@ impl ColliderVertexInfo {
     }
 }
 
-#[derive(Clone)]
+/// Shape dimensions live in `half_extents`, which is always the half size of the
+/// shape's local bounding box:
+/// - `Cuboid`: the box half extents
+/// - `Sphere`: the radius on every axis
+/// - `Capsule`: `(radius, half_length + radius, radius)`, with the segment along local Y
+/// - `Ellipsoid`: the three radii
+/// - `ConvexHull`, `TriMesh`, `Compound`, `Heightfield`: the furthest extent of `geometry`
+///   on each axis, so the box stays centered on `center`
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Collider {
     pub collider_shape: ColliderShape,
     pub center: Vec3,
@@ -68,15 +109,46 @@ pub struct Collider {
     pub axes: [Vec3; 3],
     pub half_extents: Vec3,
     pub vertex_info: ColliderVertexInfo,
+    /// Reports overlaps through collision events without ever being pushed apart
+    pub is_sensor: bool,
+    /// Points or parts of the shapes that don't fit in `half_extents`, shared between clones
+    pub geometry: Option<Arc<ColliderGeometry>>,
 }
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
 pub enum ColliderShape {
     #[default]
     Cuboid,
     Capsule,
     Sphere,
     Ellipsoid,
+    ConvexHull,
+    /// Triangles of a mesh, meant for static level geometry
+    TriMesh,
+    /// Several convex shapes moving as one
+    Compound,
+    /// Terrain given by a grid of heights, only ever static
+    Heightfield,
+}
+
+/// Composite shapes keep the hull of all their parts next to them, so `Collider::support`
+/// doesn't go through every part
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ColliderGeometry {
+    /// Hull vertices relative to the collider's center, in its local frame
+    Points(Vec<Vec3>),
+    /// Convex parts, their `center` and `rotation` relative to the collider's. `hull` is
+    /// the hull of their vertices, `None` when a part is curved or a box.
+    Parts {
+        parts: Vec<Collider>,
+        hull: Option<Vec<Vec3>>,
+    },
+    /// Heights centered on the collider's center, in its local frame, with the hull of
+    /// the extruded triangles
+    Heightfield {
+        heightfield: Heightfield,
+        hull: Vec<Vec3>,
+    },
 }
 
 impl Collider {
@@ -102,18 +174,370 @@ impl Collider {
         axes
     }
 
+    pub fn aabb(&self) -> Aabb {
+        let rotation = Mat3::from_quat(self.rotation);
+        let half_extents = match self.collider_shape {
+            ColliderShape::Sphere => Vec3::splat(self.radius()),
+            ColliderShape::Capsule => {
+                (rotation.y_axis * self.capsule_half_length()).abs() + Vec3::splat(self.radius())
+            }
+            ColliderShape::Ellipsoid => {
+                // exact bounds of a rotated ellipsoid, the length of each row of R * diag(radii)
+                let scaled = rotation * Mat3::from_diagonal(self.half_extents);
+                let rows = scaled.transpose();
+                Vec3::new(
+                    rows.x_axis.length(),
+                    rows.y_axis.length(),
+                    rows.z_axis.length(),
+                )
+            }
+            ColliderShape::Cuboid
+            | ColliderShape::ConvexHull
+            | ColliderShape::TriMesh
+            | ColliderShape::Compound
+            | ColliderShape::Heightfield => {
+                rotation.x_axis.abs() * self.half_extents.x
+                    + rotation.y_axis.abs() * self.half_extents.y
+                    + rotation.z_axis.abs() * self.half_extents.z
+            }
+        };
+
+        Aabb::from_center_half_extents(self.center, half_extents)
+    }
+
+    /// Volume enclosed by the shape, composites count their whole bounding box
+    pub fn volume(&self) -> f32 {
+        use std::f32::consts::PI;
+
+        let extents = self.half_extents;
+        match self.collider_shape {
+            ColliderShape::Sphere => 4. / 3. * PI * self.radius().powi(3),
+            ColliderShape::Capsule => {
+                let radius = self.radius();
+                PI * radius * radius * (4. / 3. * radius + 2. * self.capsule_half_length())
+            }
+            ColliderShape::Ellipsoid => 4. / 3. * PI * extents.x * extents.y * extents.z,
+            ColliderShape::Cuboid
+            | ColliderShape::ConvexHull
+            | ColliderShape::TriMesh
+            | ColliderShape::Compound
+            | ColliderShape::Heightfield => 8. * extents.x * extents.y * extents.z,
+        }
+    }
+
+    /// Radius of a sphere or capsule
+    pub fn radius(&self) -> f32 {
+        self.half_extents.x
+    }
+
+    /// Half the length of a capsule's inner segment, excluding the caps
+    pub fn capsule_half_length(&self) -> f32 {
+        (self.half_extents.y - self.half_extents.x).max(0.)
+    }
+
+    /// World space end points of a capsule's inner segment
+    pub fn capsule_segment(&self) -> (Vec3, Vec3) {
+        let offset = self.axes[1] * self.capsule_half_length();
+        (self.center - offset, self.center + offset)
+    }
+
+    /// Convex parts of a `TriMesh`, `Compound` or `Heightfield` placed in world space,
+    /// `None` for convex shapes
+    pub fn world_parts(&self) -> Option<Vec<Collider>> {
+        self.world_parts_in(&self.aabb())
+    }
+
+    /// Like `world_parts`, but only the parts overlapping `bounds`. A heightfield only
+    /// builds the triangles of the cells under `bounds`.
+    pub fn world_parts_in(&self, bounds: &Aabb) -> Option<Vec<Collider>> {
+        let parts = match self.geometry.as_deref()? {
+            ColliderGeometry::Points(_) => return None,
+            ColliderGeometry::Parts { parts, .. } => parts.clone(),
+            ColliderGeometry::Heightfield { heightfield, .. } => {
+                heightfield.parts_in(&self.local_bounds(bounds))
+            }
+        };
+        Some(
+            parts
+                .into_iter()
+                .map(|mut part| {
+                    part.center = self.center + self.rotation * part.center;
+                    part.rotation = self.rotation * part.rotation;
+                    part.update_geometry();
+                    part
+                })
+                .filter(|part| part.aabb().intersects(bounds))
+                .collect(),
+        )
+    }
+
+    /// World space `bounds` as a box in the collider's local frame, containing them
+    fn local_bounds(&self, bounds: &Aabb) -> Aabb {
+        let inverse = self.rotation.inverse();
+        let (center, half_extents) = (bounds.center(), bounds.half_extents());
+        let corners: Vec<Vec3> = (0..8)
+            .map(|i| {
+                let sign = Vec3::new(
+                    if i & 1 == 0 { -1. } else { 1. },
+                    if i & 2 == 0 { -1. } else { 1. },
+                    if i & 4 == 0 { -1. } else { 1. },
+                );
+                inverse * (center + half_extents * sign - self.center)
+            })
+            .collect();
+        Aabb::from_points(&corners)
+    }
+
+    /// Heights of a `Heightfield` collider, `None` for every other shape
+    pub fn heightfield(&self) -> Option<&Heightfield> {
+        match self.geometry.as_deref() {
+            Some(ColliderGeometry::Heightfield { heightfield, .. }) => Some(heightfield),
+            _ => None,
+        }
+    }
+
+    /// Hull vertices in the collider's local frame, empty for every other shape
+    pub fn hull_points(&self) -> &[Vec3] {
+        match self.geometry.as_deref() {
+            Some(ColliderGeometry::Points(points)) => points,
+            _ => &[],
+        }
+    }
+
+    /// Furthest point of the collider along `direction`, in world space.
+    /// For a composite shape that is the support of its parts' combined hull.
+    pub fn support(&self, direction: Vec3) -> Vec3 {
+        let local = self.rotation.inverse() * direction;
+        let furthest = |points: &[Vec3]| {
+            points
+                .iter()
+                .copied()
+                .max_by(|a, b| a.dot(local).total_cmp(&b.dot(local)))
+                .unwrap_or(Vec3::ZERO)
+        };
+
+        let local_point = match self.collider_shape {
+            ColliderShape::Cuboid => Vec3::new(
+                self.half_extents.x.copysign(local.x),
+                self.half_extents.y.copysign(local.y),
+                self.half_extents.z.copysign(local.z),
+            ),
+            ColliderShape::Sphere => local.normalize_or_zero() * self.radius(),
+            ColliderShape::Capsule => {
+                Vec3::Y * self.capsule_half_length().copysign(local.y)
+                    + local.normalize_or_zero() * self.radius()
+            }
+            ColliderShape::Ellipsoid => {
+                let scaled = local * self.half_extents;
+                let length = scaled.length();
+                if length > f32::EPSILON {
+                    scaled * self.half_extents / length
+                } else {
+                    Vec3::ZERO
+                }
+            }
+            ColliderShape::ConvexHull => furthest(self.hull_points()),
+            ColliderShape::TriMesh | ColliderShape::Compound | ColliderShape::Heightfield => {
+                match self.geometry.as_deref() {
+                    Some(ColliderGeometry::Parts {
+                        hull: Some(hull), ..
+                    })
+                    | Some(ColliderGeometry::Heightfield { hull, .. }) => furthest(hull),
+                    // parts are placed in this collider's frame, so are their supports
+                    Some(ColliderGeometry::Parts { parts, hull: None }) => parts
+                        .iter()
+                        .map(|part| part.support(local))
+                        .max_by(|a, b| a.dot(local).total_cmp(&b.dot(local)))
+                        .unwrap_or(Vec3::ZERO),
+                    Some(ColliderGeometry::Points(_)) | None => Vec3::ZERO,
+                }
+            }
+        };
+
+        self.center + self.rotation * local_point
+    }
+
+    /// Recompute the cached axes and vertices after the pose changed
+    pub fn update_geometry(&mut self) {
+        self.axes = [
+            self.rotation * Vec3::X,
+            self.rotation * Vec3::Y,
+            self.rotation * Vec3::Z,
+        ];
+
+        if self.collider_shape == ColliderShape::Cuboid {
+            self.vertex_info =
+                ColliderVertexInfo::from_cuboid(&self.center, &self.half_extents, &self.rotation);
+        }
+    }
+
+    fn from_shape(
+        collider_shape: ColliderShape,
+        half_extents: Vec3,
+        center: Vec3,
+        rotation: Quat,
+    ) -> Self {
+        let mut collider = Self {
+            collider_shape,
+            center,
+            rotation,
+            axes: [Vec3::X, Vec3::Y, Vec3::Z],
+            half_extents,
+            vertex_info: ColliderVertexInfo { vertices: vec![] },
+            is_sensor: false,
+            geometry: None,
+        };
+        collider.update_geometry();
+        collider
+    }
+
+    fn from_geometry(
+        collider_shape: ColliderShape,
+        half_extents: Vec3,
+        geometry: ColliderGeometry,
+        center: Vec3,
+        rotation: Quat,
+    ) -> Self {
+        let mut collider = Self::from_shape(collider_shape, half_extents, center, rotation);
+        collider.geometry = Some(Arc::new(geometry));
+        collider
+    }
+
+    fn from_hull_points(points: Vec<Vec3>, center: Vec3, rotation: Quat) -> Self {
+        let half_extents = points
+            .iter()
+            .fold(Vec3::ZERO, |extent, point| extent.max(point.abs()));
+        Self::from_geometry(
+            ColliderShape::ConvexHull,
+            half_extents,
+            ColliderGeometry::Points(points),
+            center,
+            rotation,
+        )
+    }
+
+    fn from_parts(
+        collider_shape: ColliderShape,
+        parts: Vec<Collider>,
+        center: Vec3,
+        rotation: Quat,
+    ) -> Self {
+        let half_extents = parts.iter().fold(Vec3::ZERO, |extent, part| {
+            let bounds = part.aabb();
+            extent.max(bounds.min.abs()).max(bounds.max.abs())
+        });
+        let hull = parts
+            .iter()
+            .all(|part| part.collider_shape == ColliderShape::ConvexHull)
+            .then(|| {
+                let points: Vec<Vec3> = parts
+                    .iter()
+                    .flat_map(|part| {
+                        part.hull_points()
+                            .iter()
+                            .map(|&point| part.center + part.rotation * point)
+                    })
+                    .collect();
+                hull::convex_hull(&points)
+            });
+        Self::from_geometry(
+            collider_shape,
+            half_extents,
+            ColliderGeometry::Parts { parts, hull },
+            center,
+            rotation,
+        )
+    }
+
+    /// Turn the collider into a sensor, see `is_sensor`
+    pub fn sensor(mut self) -> Self {
+        self.is_sensor = true;
+        self
+    }
+
     pub fn from_cuboid(half_size: Vec3, center: Vec3, rotation: Quat) -> Self {
-        let axes = [rotation * Vec3::X, rotation * Vec3::Y, rotation * Vec3::Z];
+        Self::from_shape(ColliderShape::Cuboid, half_size, center, rotation)
+    }
 
-        let vertex_info = ColliderVertexInfo::from_cuboid(&center, &half_size, &rotation);
+    pub fn from_sphere(radius: f32, center: Vec3) -> Self {
+        Self::from_shape(
+            ColliderShape::Sphere,
+            Vec3::splat(radius),
+            center,
+            Quat::IDENTITY,
+        )
+    }
 
-        Self {
-            collider_shape: ColliderShape::Cuboid,
+    /// Capsule along the local Y axis, `half_length` being half the distance between
+    /// the centers of the two caps
+    pub fn from_capsule(radius: f32, half_length: f32, center: Vec3, rotation: Quat) -> Self {
+        Self::from_shape(
+            ColliderShape::Capsule,
+            Vec3::new(radius, half_length + radius, radius),
             center,
             rotation,
-            axes,
-            half_extents: half_size,
-            vertex_info,
-        }
+        )
+    }
+
+    pub fn from_ellipsoid(radii: Vec3, center: Vec3, rotation: Quat) -> Self {
+        Self::from_shape(ColliderShape::Ellipsoid, radii, center, rotation)
+    }
+
+    /// Convex hull of `points`, given relative to `center` in the collider's local frame
+    pub fn from_convex_hull(points: &[Vec3], center: Vec3, rotation: Quat) -> Self {
+        Self::from_hull_points(hull::convex_hull(points), center, rotation)
+    }
+
+    /// Triangle mesh with `vertices` relative to `center` in the collider's local frame.
+    /// Degenerate triangles and ones indexing past `vertices` are left out.
+    pub fn from_trimesh(
+        vertices: &[Vec3],
+        triangles: &[[u32; 3]],
+        center: Vec3,
+        rotation: Quat,
+    ) -> Self {
+        let parts = triangles
+            .iter()
+            .filter_map(|triangle| {
+                let [a, b, c] = triangle.map(|i| vertices.get(i as usize).copied());
+                let (a, b, c) = (a?, b?, c?);
+                if (b - a).cross(c - a).length_squared() < f32::EPSILON {
+                    return None;
+                }
+                let middle = (a + b + c) / 3.;
+                Some(Self::from_hull_points(
+                    vec![a - middle, b - middle, c - middle],
+                    middle,
+                    Quat::IDENTITY,
+                ))
+            })
+            .collect();
+        Self::from_parts(ColliderShape::TriMesh, parts, center, rotation)
+    }
+
+    /// Several convex colliders moving as one, each placed by its `center` and `rotation`
+    /// relative to the compound's
+    pub fn from_compound(parts: Vec<Collider>, center: Vec3, rotation: Quat) -> Self {
+        Self::from_parts(ColliderShape::Compound, parts, center, rotation)
+    }
+
+    /// Terrain collider, the grid centered on `center` in the collider's local XZ plane
+    pub fn from_heightfield(heightfield: Heightfield, center: Vec3, rotation: Quat) -> Self {
+        let (low, high) = heightfield.height_range();
+        let depth = heightfield.cell_size.max_element();
+        let half_size = heightfield.size() / 2.;
+        let half_extents = Vec3::new(
+            half_size.x,
+            high.abs().max((low - depth).abs()),
+            half_size.y,
+        );
+        let hull = heightfield.hull();
+        Self::from_geometry(
+            ColliderShape::Heightfield,
+            half_extents,
+            ColliderGeometry::Heightfield { heightfield, hull },
+            center,
+            rotation,
+        )
     }
 }
@@ -0,0 +1,278 @@
+use bevy::{color::palettes::css, prelude::*};
+
+use super::{
+    bodies::{RigidbodyComponent, RigidbodyType},
+    collisions::{Collider, ColliderShape, manifold::ContactManifolds},
+    fluids::FluidVolume,
+    joints::{Joint, JointMember, JointType},
+    solver::joint::JointPose,
+};
+use crate::player::{controller::CharacterController, player_data::Player};
+
+/// Seconds of motion drawn by a velocity arrow
+const VELOCITY_ARROW_SECONDS: f32 = 0.25;
+const CONTACT_NORMAL_LENGTH: f32 = 0.5;
+const POINT_SIZE: f32 = 0.05;
+
+/// Draws colliders, contacts, velocities and joints with gizmos, see `PhysicsDebug`.
+/// Left out of `ZphyPlugin`, since it needs a renderer.
+pub struct PhysicsDebugPlugin;
+
+impl Plugin for PhysicsDebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PhysicsDebug>().add_systems(
+            Update,
+            (
+                toggle_physics_debug,
+                draw_physics_debug.run_if(|debug: Res<PhysicsDebug>| debug.enabled),
+            )
+                .chain(),
+        );
+    }
+}
+
+/// What the physics debug overlay draws, switched on and off with `toggle_key`
+#[derive(Resource, Clone, Debug)]
+pub struct PhysicsDebug {
+    pub enabled: bool,
+    pub toggle_key: KeyCode,
+    /// Collider outlines and axes, colored by body type and sleep state
+    pub colliders: bool,
+    pub aabbs: bool,
+    /// Contact points and their normals
+    pub contacts: bool,
+    pub velocities: bool,
+    /// Joint anchors and axes
+    pub joints: bool,
+    /// Bounds of fluid volumes
+    pub fluids: bool,
+}
+
+impl Default for PhysicsDebug {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            toggle_key: KeyCode::F3,
+            colliders: true,
+            aabbs: true,
+            contacts: true,
+            velocities: true,
+            joints: true,
+            fluids: true,
+        }
+    }
+}
+
+fn toggle_physics_debug(mut debug: ResMut<PhysicsDebug>, keyboard: Res<ButtonInput<KeyCode>>) {
+    if keyboard.just_pressed(debug.toggle_key) {
+        debug.enabled = !debug.enabled;
+    }
+}
+
+fn body_color(body: &RigidbodyComponent) -> Color {
+    let color = if body.collider.is_sensor {
+        css::AQUA
+    } else if body.is_sleeping() {
+        css::GRAY
+    } else {
+        match body.rbt {
+            RigidbodyType::Static => css::STEEL_BLUE,
+            RigidbodyType::Dynamic => css::LIME,
+            RigidbodyType::Kinematic => css::GOLD,
+        }
+    };
+    color.into()
+}
+
+fn draw_physics_debug(
+    mut gizmos: Gizmos,
+    debug: Res<PhysicsDebug>,
+    bodies: Query<&RigidbodyComponent>,
+    players: Query<(&Player, &CharacterController)>,
+    joints: Query<&Joint>,
+    fluids: Query<&FluidVolume>,
+    manifolds: Res<ContactManifolds>,
+) {
+    for body in &bodies {
+        let collider = &body.collider;
+        if debug.colliders {
+            draw_collider(&mut gizmos, collider, body_color(body));
+            draw_axes(&mut gizmos, collider);
+        }
+        if debug.aabbs {
+            let aabb = collider.aabb();
+            gizmos.cuboid(
+                Transform::from_translation(aabb.center()).with_scale(aabb.half_extents() * 2.),
+                css::DARK_GRAY,
+            );
+        }
+        if debug.velocities && body.rbt != RigidbodyType::Static && !body.is_sleeping() {
+            let end = collider.center + body.velocity.linear * VELOCITY_ARROW_SECONDS;
+            if end != collider.center {
+                gizmos.arrow(collider.center, end, css::YELLOW);
+            }
+        }
+    }
+
+    if debug.fluids {
+        for fluid in &fluids {
+            let bounds = fluid.bounds;
+            gizmos.cuboid(
+                Transform::from_translation(bounds.center()).with_scale(bounds.half_extents() * 2.),
+                css::DODGER_BLUE,
+            );
+        }
+    }
+
+    for (player, controller) in &players {
+        if debug.colliders {
+            let color = match controller.ground_normal {
+                Some(_) => css::ORANGE,
+                None => css::ORANGE_RED,
+            };
+            draw_collider(
+                &mut gizmos,
+                &controller.collider(player.pos.loc),
+                color.into(),
+            );
+        }
+        if debug.velocities && player.pos.vel != Vec3::ZERO {
+            let end = player.pos.loc + player.pos.vel * VELOCITY_ARROW_SECONDS;
+            gizmos.arrow(player.pos.loc, end, css::YELLOW);
+        }
+    }
+
+    if debug.contacts {
+        for contact in manifolds.contacts.values() {
+            for point in &contact.points {
+                gizmos.sphere(
+                    Isometry3d::from_translation(point.point_a),
+                    POINT_SIZE,
+                    css::RED,
+                );
+                gizmos.sphere(
+                    Isometry3d::from_translation(point.point_b),
+                    POINT_SIZE,
+                    css::RED,
+                );
+                gizmos.arrow(
+                    point.point_b,
+                    point.point_b + contact.normal * CONTACT_NORMAL_LENGTH,
+                    css::RED,
+                );
+            }
+        }
+    }
+
+    if debug.joints {
+        for joint in joints.iter().filter(|joint| !joint.broken) {
+            let pose = |member: &JointMember| match member.entity {
+                Some(entity) => bodies.get(entity).ok().map(|body| JointPose {
+                    center: body.collider.center,
+                    rotation: body.collider.rotation,
+                }),
+                None => Some(JointPose::WORLD),
+            };
+            let (Some(pose_a), Some(pose_b)) = (pose(&joint.member_a), pose(&joint.member_b))
+            else {
+                continue;
+            };
+
+            let anchor_a = pose_a.anchor(&joint.member_a);
+            let anchor_b = pose_b.anchor(&joint.member_b);
+            gizmos.sphere(
+                Isometry3d::from_translation(anchor_a),
+                POINT_SIZE * 2.,
+                css::MAGENTA,
+            );
+            gizmos.sphere(
+                Isometry3d::from_translation(anchor_b),
+                POINT_SIZE * 2.,
+                css::MAGENTA,
+            );
+            gizmos.line(anchor_a, anchor_b, css::MAGENTA);
+            if joint.joint_type != JointType::BallSocket {
+                let axis = pose_a.axis(&joint.member_a);
+                gizmos.line(anchor_a - axis, anchor_a + axis, css::VIOLET);
+            }
+        }
+    }
+}
+
+fn draw_axes(gizmos: &mut Gizmos, collider: &Collider) {
+    let [x, y, z] = collider.axes;
+    let length = collider.half_extents.min_element().max(POINT_SIZE);
+    gizmos.line(collider.center, collider.center + x * length, css::RED);
+    gizmos.line(collider.center, collider.center + y * length, css::GREEN);
+    gizmos.line(collider.center, collider.center + z * length, css::BLUE);
+}
+
+fn draw_collider(gizmos: &mut Gizmos, collider: &Collider, color: Color) {
+    let isometry = Isometry3d::new(collider.center, collider.rotation);
+    match collider.collider_shape {
+        ColliderShape::Cuboid => {
+            let vertices = &collider.vertex_info.vertices;
+            // `ColliderVertexInfo::from_cuboid` orders corners by the bits of their sign
+            for (i, &vertex) in vertices.iter().enumerate() {
+                for bit in [1, 2, 4] {
+                    if i & bit == 0 {
+                        gizmos.line(vertex, vertices[i | bit], color);
+                    }
+                }
+            }
+        }
+        ColliderShape::Sphere => {
+            gizmos.sphere(isometry, collider.radius(), color);
+        }
+        ColliderShape::Capsule => {
+            let capsule = Capsule3d::new(collider.radius(), collider.capsule_half_length() * 2.);
+            gizmos.primitive_3d(&capsule, isometry, color);
+        }
+        ColliderShape::Ellipsoid => {
+            let radii = collider.half_extents;
+            let planes = [
+                (Quat::IDENTITY, radii.xy()),
+                (Quat::from_rotation_x(90f32.to_radians()), radii.xz()),
+                (Quat::from_rotation_y(90f32.to_radians()), radii.zy()),
+            ];
+            for (plane, half_size) in planes {
+                let plane = Isometry3d::new(collider.center, collider.rotation * plane);
+                gizmos.ellipse(plane, half_size, color);
+            }
+        }
+        ColliderShape::ConvexHull => {
+            let points = collider
+                .hull_points()
+                .iter()
+                .map(|point| collider.center + collider.rotation * *point);
+            if collider.hull_points().len() <= 3 {
+                gizmos.linestrip(points.clone().chain(points.take(1)), color);
+            } else {
+                for point in points {
+                    gizmos.cross(Isometry3d::from_translation(point), POINT_SIZE, color);
+                }
+            }
+        }
+        ColliderShape::TriMesh | ColliderShape::Compound => {
+            for part in collider.world_parts().into_iter().flatten() {
+                draw_collider(gizmos, &part, color);
+            }
+        }
+        ColliderShape::Heightfield => {
+            let Some(heightfield) = collider.heightfield() else {
+                return;
+            };
+            let world =
+                |column, row| collider.center + collider.rotation * heightfield.point(column, row);
+            for row in 0..heightfield.rows {
+                gizmos.linestrip(
+                    (0..heightfield.columns).map(|column| world(column, row)),
+                    color,
+                );
+            }
+            for column in 0..heightfield.columns {
+                gizmos.linestrip((0..heightfield.rows).map(|row| world(column, row)), color);
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+
+use super::{
+    bodies::{RigidbodyComponent, RigidbodyType, apply_forces},
+    collisions::{Collider, ColliderShape, broadphase::Aabb},
+    forces::Gravity,
+    prelude::PhysicsSet,
+    solver::solve_constraints,
+};
+
+/// Samples along each local axis of a body when measuring how much of it is submerged
+const BUOYANCY_SAMPLES: usize = 4;
+
+pub struct FluidPlugin;
+
+impl Plugin for FluidPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            apply_buoyancy
+                .in_set(PhysicsSet::Solve)
+                .after(apply_forces)
+                .before(solve_constraints),
+        );
+    }
+}
+
+/// Body of water, or any other fluid, filling `bounds` up to their top. Dynamic bodies
+/// in it are pushed up by the weight of the fluid they displace and slowed down by drag.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct FluidVolume {
+    /// World space region the fluid fills, its surface is `bounds.max.y`
+    pub bounds: Aabb,
+    /// Mass per unit volume, a body lighter than this for its size floats
+    pub density: f32,
+    /// Decay rate per second of a fully submerged body's velocity, which is scaled by
+    /// `exp(-linear_drag * dt)` every step
+    pub linear_drag: f32,
+    /// Decay rate per second of a fully submerged body's angular velocity, like `linear_drag`
+    pub angular_drag: f32,
+}
+
+impl FluidVolume {
+    /// Water filling `bounds`, at 1000 kg per cubic unit
+    pub fn water(bounds: Aabb) -> Self {
+        Self {
+            bounds,
+            density: 1000.,
+            linear_drag: 1.,
+            angular_drag: 1.,
+        }
+    }
+
+    /// Fluid filling the bounding box of a collider
+    pub fn from_collider(collider: &Collider, density: f32) -> Self {
+        Self {
+            density,
+            ..Self::water(collider.aabb())
+        }
+    }
+
+    pub fn surface(&self) -> f32 {
+        self.bounds.max.y
+    }
+
+    /// How far below the surface `point` is, `None` outside the fluid
+    pub fn depth(&self, point: Vec3) -> Option<f32> {
+        let inside = point.cmpge(self.bounds.min).all() && point.cmple(self.bounds.max).all();
+        inside.then(|| self.surface() - point.y)
+    }
+
+    /// Fraction of a box spanning `half_height` above and below `point` that's under
+    /// the surface, 0 when `point` is off to the side of the fluid
+    fn submerged_fraction(&self, point: Vec3, half_height: f32) -> f32 {
+        let (min, max) = (self.bounds.min, self.bounds.max);
+        if point.x < min.x || point.x > max.x || point.z < min.z || point.z > max.z {
+            return 0.;
+        }
+        let height = (half_height * 2.).max(f32::EPSILON);
+        let below_surface = (max.y - (point.y - half_height)) / height;
+        let above_floor = (point.y + half_height - min.y) / height;
+        below_surface.min(above_floor).clamp(0., 1.)
+    }
+}
+
+/// Whether a point given in the collider's local frame lies inside its shape
+fn contains_local(collider: &Collider, point: Vec3) -> bool {
+    match collider.collider_shape {
+        ColliderShape::Sphere => point.length() <= collider.radius(),
+        ColliderShape::Ellipsoid => (point / collider.half_extents).length_squared() <= 1.,
+        ColliderShape::Capsule => {
+            let along = point.y.clamp(
+                -collider.capsule_half_length(),
+                collider.capsule_half_length(),
+            );
+            point.distance(Vec3::Y * along) <= collider.radius()
+        }
+        _ => true,
+    }
+}
+
+/// Push dynamic bodies up by the fluid they displace and drag them. Displacement is
+/// sampled over a grid of cells inside each body, and each cell is pushed where it sits,
+/// so bodies lying unevenly in the fluid get turned upright.
+pub(crate) fn apply_buoyancy(
+    mut bodies: Query<&mut RigidbodyComponent>,
+    fluids: Query<(Entity, &FluidVolume)>,
+    gravity: Res<Gravity>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+    if fluids.is_empty() || dt <= 0. {
+        return;
+    }
+
+    for mut body in bodies.iter_mut() {
+        if body.rbt != RigidbodyType::Dynamic || body.is_sleeping() {
+            continue;
+        }
+        let aabb = body.collider.aabb();
+        // overlapping volumes are settled by entity, not by query order, so replays match
+        let fluid = fluids
+            .iter()
+            .filter(|(_, fluid)| fluid.bounds.intersects(&aabb))
+            .min_by_key(|(entity, _)| *entity);
+        let Some((_, fluid)) = fluid else {
+            continue;
+        };
+
+        let collider = &body.collider;
+        let cell = collider.half_extents / BUOYANCY_SAMPLES as f32;
+        let cell_half_height = collider.axes[0].y.abs() * cell.x
+            + collider.axes[1].y.abs() * cell.y
+            + collider.axes[2].y.abs() * cell.z;
+        let samples: Vec<Vec3> = (0..BUOYANCY_SAMPLES.pow(3))
+            .map(|i| {
+                let grid = UVec3::new(
+                    (i % BUOYANCY_SAMPLES) as u32,
+                    (i / BUOYANCY_SAMPLES % BUOYANCY_SAMPLES) as u32,
+                    (i / (BUOYANCY_SAMPLES * BUOYANCY_SAMPLES)) as u32,
+                );
+                (grid.as_vec3() * 2. + 1.) * cell - collider.half_extents
+            })
+            .filter(|&local| contains_local(collider, local))
+            .map(|local| collider.center + collider.rotation * local)
+            .collect();
+        if samples.is_empty() {
+            continue;
+        }
+
+        let sample_volume = collider.volume() / samples.len() as f32;
+        let lift = -gravity.0 * body.gravity_scale * fluid.density * sample_volume;
+        let mut submerged = 0.;
+        for point in samples.iter() {
+            let fraction = fluid.submerged_fraction(*point, cell_half_height);
+            if fraction > 0. {
+                submerged += fraction;
+                body.apply_impulse_at_point(lift * fraction * dt, *point);
+            }
+        }
+
+        let submerged = submerged / samples.len() as f32;
+        let linear_drag = (-fluid.linear_drag * submerged * dt).exp();
+        let angular_drag = (-fluid.angular_drag * submerged * dt).exp();
+        body.velocity.linear *= linear_drag;
+        body.velocity.angular *= angular_drag;
+    }
+}
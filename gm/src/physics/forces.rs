@@ -0,0 +1,105 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Acceleration pulling every dynamic body, scaled by its `gravity_scale`
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct Gravity(pub Vec3);
+
+impl Default for Gravity {
+    fn default() -> Self {
+        Self(Vec3::new(0., -9.81, 0.))
+    }
+}
+
+/// Force and torque pushing a dynamic body every step until changed, for wind, thrusters
+/// and the like
+#[derive(Component, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct ExternalForce {
+    pub force: Vec3,
+    pub torque: Vec3,
+}
+
+impl ExternalForce {
+    pub fn new(force: Vec3) -> Self {
+        Self {
+            force,
+            torque: Vec3::ZERO,
+        }
+    }
+
+    /// Force applied at a world space `point`, which also turns a body centered on `center`
+    pub fn at_point(force: Vec3, point: Vec3, center: Vec3) -> Self {
+        *Self::default().apply_force_at_point(force, point, center)
+    }
+
+    pub fn apply_force(&mut self, force: Vec3) -> &mut Self {
+        self.force += force;
+        self
+    }
+
+    pub fn apply_force_at_point(&mut self, force: Vec3, point: Vec3, center: Vec3) -> &mut Self {
+        self.force += force;
+        self.torque += (point - center).cross(force);
+        self
+    }
+
+    pub fn apply_torque(&mut self, torque: Vec3) -> &mut Self {
+        self.torque += torque;
+        self
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.force == Vec3::ZERO && self.torque == Vec3::ZERO
+    }
+}
+
+/// Sudden change of momentum, applied to a dynamic body on the next step and then
+/// cleared, for explosions, hits and throws
+#[derive(Component, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct ExternalImpulse {
+    pub impulse: Vec3,
+    pub angular_impulse: Vec3,
+}
+
+impl ExternalImpulse {
+    pub fn new(impulse: Vec3) -> Self {
+        Self {
+            impulse,
+            angular_impulse: Vec3::ZERO,
+        }
+    }
+
+    /// Impulse applied at a world space `point`, which also spins a body centered on `center`
+    pub fn at_point(impulse: Vec3, point: Vec3, center: Vec3) -> Self {
+        *Self::default().apply_impulse_at_point(impulse, point, center)
+    }
+
+    pub fn apply_impulse(&mut self, impulse: Vec3) -> &mut Self {
+        self.impulse += impulse;
+        self
+    }
+
+    pub fn apply_impulse_at_point(
+        &mut self,
+        impulse: Vec3,
+        point: Vec3,
+        center: Vec3,
+    ) -> &mut Self {
+        self.impulse += impulse;
+        self.angular_impulse += (point - center).cross(impulse);
+        self
+    }
+
+    pub fn apply_angular_impulse(&mut self, angular_impulse: Vec3) -> &mut Self {
+        self.angular_impulse += angular_impulse;
+        self
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.impulse == Vec3::ZERO && self.angular_impulse == Vec3::ZERO
+    }
+}
@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+
+use super::{
+    bodies::{RigidbodyComponent, RigidbodyType},
+    prelude::PhysicsSet,
+};
+
+pub struct InterpolationPlugin;
+
+impl Plugin for InterpolationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            (
+                insert_body_interpolation.in_set(PhysicsSet::Prepare),
+                push_body_poses.in_set(PhysicsSet::Sync),
+            ),
+        )
+        .add_systems(
+            RunFixedMainLoop,
+            interpolate_transforms.in_set(RunFixedMainLoopSystem::AfterFixedMainLoop),
+        );
+    }
+}
+
+/// Poses from the last two physics steps, blended into the `Transform` every frame
+/// so rendering stays smooth no matter how the frame rate lines up with the tick rate
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PhysicsInterpolation {
+    pub previous_translation: Vec3,
+    pub previous_rotation: Quat,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    /// Leave `Transform::rotation` alone, for entities that are rotated by input instead
+    pub translation_only: bool,
+}
+
+impl PhysicsInterpolation {
+    pub fn new(translation: Vec3, rotation: Quat) -> Self {
+        Self {
+            previous_translation: translation,
+            previous_rotation: rotation,
+            translation,
+            rotation,
+            translation_only: false,
+        }
+    }
+
+    pub fn translation_only(translation: Vec3) -> Self {
+        Self {
+            translation_only: true,
+            ..Self::new(translation, Quat::IDENTITY)
+        }
+    }
+
+    /// Record the pose produced by the latest physics step
+    pub fn push(&mut self, translation: Vec3, rotation: Quat) {
+        self.previous_translation = self.translation;
+        self.previous_rotation = self.rotation;
+        self.translation = translation;
+        self.rotation = rotation;
+    }
+
+    /// Jump straight to a pose without blending from the old one
+    pub fn teleport(&mut self, translation: Vec3, rotation: Quat) {
+        *self = Self {
+            translation_only: self.translation_only,
+            ..Self::new(translation, rotation)
+        };
+    }
+}
+
+fn insert_body_interpolation(
+    mut commands: Commands,
+    query: Query<(Entity, &RigidbodyComponent), Without<PhysicsInterpolation>>,
+) {
+    for (entity, body) in query.iter() {
+        if body.rbt == RigidbodyType::Static {
+            continue;
+        }
+        commands.entity(entity).insert(PhysicsInterpolation::new(
+            body.collider.center,
+            body.collider.rotation,
+        ));
+    }
+}
+
+fn push_body_poses(mut query: Query<(&RigidbodyComponent, &mut PhysicsInterpolation)>) {
+    for (body, mut interpolation) in query.iter_mut() {
+        interpolation.push(body.collider.center, body.collider.rotation);
+    }
+}
+
+fn interpolate_transforms(
+    mut query: Query<(&PhysicsInterpolation, &mut Transform)>,
+    time: Res<Time<Fixed>>,
+) {
+    let alpha = time.overstep_fraction();
+
+    for (interpolation, mut transform) in query.iter_mut() {
+        transform.translation = interpolation
+            .previous_translation
+            .lerp(interpolation.translation, alpha);
+        if !interpolation.translation_only {
+            transform.rotation = interpolation
+                .previous_rotation
+                .slerp(interpolation.rotation, alpha);
+        }
+    }
+}
@@ -1,118 +1,172 @@
 use bevy::prelude::*;
-use bevy::render::render_resource::ShaderType;
+use serde::{Deserialize, Serialize};
 
-use crate::physics::bodies::RigidbodyComponent;
-
-/// The main struct for a Joint that holds different types of constraints
-#[derive(Component, Clone, Debug)]
+/// Constraint between two bodies, or between a body and the world.
+/// Solved together with the contacts, see `solver::joint`.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct Joint {
-    pub member: JointMember,
+    pub member_a: JointMember,
+    pub member_b: JointMember,
     pub joint_type: JointType,
+    /// Angle range in radians for hinges, distance range along the axis for sliders
+    pub limits: Option<MemberLimit<f32>>,
+    pub motor: Option<JointMotor>,
+    /// Force above which the joint snaps, `None` for unbreakable
+    pub break_force: Option<f32>,
+    /// Set once the joint snapped, broken joints are ignored by the solver
+    pub broken: bool,
+    /// Rotation of b relative to a when the joint was first solved, the zero of hinge angles
+    pub reference_rotation: Option<Quat>,
+    /// Let the two bodies collide with each other
+    pub collide_connected: bool,
+    /// What the solver applied last step, to warm start the next one
+    pub impulses: JointImpulses,
+}
+
+/// Accumulated impulses of a joint, in world space so they carry over while the joint
+/// turns. Each row of the next step starts from their part along it.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct JointImpulses {
+    /// Keeping the anchors together
+    pub linear: Vec3,
+    /// Keeping the bodies from turning where the joint doesn't let them
+    pub angular: Vec3,
+    /// Holding a limit, linear for sliders and angular for hinges
+    pub limit: Vec3,
+    pub motor: Vec3,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum JointType {
+    /// Keeps the anchors together, rotation is free
     BallSocket,
+    /// Keeps the anchors together along the axis, translation along it is free
     Slider,
+    /// Keeps the anchors together, only rotation around the axis is free
     Hinge,
 }
 
-#[derive(Clone, Debug)]
+/// One side of a joint
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct JointMember {
-    pub entity: Entity,
-    pub direction: Vec3,
-    pub limits: MemberLimit<Vec3>,
+    /// Body the joint is attached to, `None` attaches it to the world
+    pub entity: Option<Entity>,
+    /// Attachment point in the body's local frame, or in world space for the world
+    pub anchor: Vec3,
+    /// Hinge or slider axis in the body's local frame, or in world space for the world
+    pub axis: Vec3,
 }
 
 impl JointMember {
-    pub fn new(entity: Entity, direction: Vec3, limits: MemberLimit<Vec3>) -> Self {
+    pub fn new(entity: Entity, anchor: Vec3, axis: Vec3) -> Self {
+        Self {
+            entity: Some(entity),
+            anchor,
+            axis: axis.normalize_or(Vec3::Y),
+        }
+    }
+
+    pub fn world(anchor: Vec3, axis: Vec3) -> Self {
         Self {
-            entity,
-            direction,
-            limits,
+            entity: None,
+            anchor,
+            axis: axis.normalize_or(Vec3::Y),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct MemberLimit<N> {
     pub min: N,
     pub max: N,
 }
 
-impl MemberLimit<Vec3> {
-    pub fn new(min: Vec3, max: Vec3) -> Self {
-        if min.size() < max.size() {
-            panic!()
+impl MemberLimit<f32> {
+    pub fn new(min: f32, max: f32) -> Self {
+        Self {
+            min: min.min(max),
+            max: min.max(max),
         }
-
-        Self { min, max }
     }
+}
 
-    pub fn clamp_position(&self, position: Vec3, direction: Vec3) -> Vec3 {
-        // First, project the position onto the direction
-        let pos_along_dir = position.dot(direction);
-
-        // Clamp the projected position between the min and max bounds
-        let clamped_pos_along_dir =
-            pos_along_dir.clamp(self.min.dot(direction), self.max.dot(direction));
-
-        // Reconstruct the position, using the direction and the clamped value
-        direction * clamped_pos_along_dir
-    }
+/// Drives a hinge's rotation or a slider's translation towards a target speed
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct JointMotor {
+    /// Radians per second for hinges, units per second for sliders
+    pub target_velocity: f32,
+    /// Strongest force (or torque) the motor can apply
+    pub max_force: f32,
 }
 
 impl Joint {
-    pub fn new(member: JointMember, joint_type: JointType) -> Self {
-        Self { member, joint_type }
-    }
-
-    // Apply force to enforce the joint's constraint
-    pub fn enforce(
-        mut query: Query<(&mut Joint, &mut RigidbodyComponent, &mut Transform)>,
-        time: Res<Time>,
-    ) {
-        if let Ok((joint, mut rb, mut transform)) = query.single_mut() {
-            match joint.joint_type {
-                JointType::BallSocket => joint.enforce_ball_socket(&mut rb, &mut transform),
-                JointType::Slider => joint.enforce_slider(&mut rb, &mut transform),
-                JointType::Hinge => joint.enforce_hinge(&mut rb, &mut transform),
-            }
+    /// Panics if both members are the same body
+    pub fn new(member_a: JointMember, member_b: JointMember, joint_type: JointType) -> Self {
+        assert!(
+            member_a.entity.is_none() || member_a.entity != member_b.entity,
+            "a joint needs two different bodies"
+        );
+        Self {
+            member_a,
+            member_b,
+            joint_type,
+            limits: None,
+            motor: None,
+            break_force: None,
+            broken: false,
+            reference_rotation: None,
+            collide_connected: true,
+            impulses: JointImpulses::default(),
         }
     }
 
-    fn enforce_ball_socket(&self, rb: &mut RigidbodyComponent, transform: &mut Transform) {
-        // Ball-Socket constraint: No translation, only rotation allowed
-        // This will enforce no positional movement but free rotation
-
-        let direction = transform.translation; // assuming the direction is the position relative to the joint origin
-        let limit = self.member.limits.clone(); // assuming all members have the same limit
-
-        let clamped_position = limit.clamp_position(transform.translation, direction);
-        transform.translation = clamped_position;
+    pub fn ball_socket(member_a: JointMember, member_b: JointMember) -> Self {
+        Self::new(member_a, member_b, JointType::BallSocket)
     }
 
-    fn enforce_slider(&self, rb: &mut RigidbodyComponent, transform: &mut Transform) {
-        // Slider constraint: Allow motion along one axis (e.g., along the X-axis), no rotation
+    pub fn hinge(member_a: JointMember, member_b: JointMember) -> Self {
+        Self::new(member_a, member_b, JointType::Hinge)
+    }
 
-        let direction = transform.translation; // Get position along the axis
-        let limit = self.member.limits.clone(); // Assume limits
+    pub fn slider(member_a: JointMember, member_b: JointMember) -> Self {
+        Self::new(member_a, member_b, JointType::Slider)
+    }
 
-        let clamped_position = limit.clamp_position(transform.translation, direction);
-        transform.translation = clamped_position;
+    pub fn with_limits(mut self, min: f32, max: f32) -> Self {
+        self.limits = Some(MemberLimit::new(min, max));
+        self
+    }
 
-        // Apply forces to enforce translation along direction
+    pub fn with_motor(mut self, target_velocity: f32, max_force: f32) -> Self {
+        self.motor = Some(JointMotor {
+            target_velocity,
+            max_force,
+        });
+        self
     }
 
-    fn enforce_hinge(&self, rb: &mut RigidbodyComponent, transform: &mut Transform) {
-        // Hinge constraint: Allow rotation around one axis, no translation along that axis
-        rb.velocity.linear = Vec3::ZERO;
+    pub fn with_break_force(mut self, break_force: f32) -> Self {
+        self.break_force = Some(break_force);
+        self
+    }
 
-        let direction = transform.translation; // Axis for rotation
-        let limit = self.member.limits.clone(); // Assume limits
+    /// Stop the two bodies from colliding, for parts that overlap where they join
+    pub fn without_contacts(mut self) -> Self {
+        self.collide_connected = false;
+        self
+    }
 
-        let clamped_position = limit.clamp_position(transform.translation, direction);
-        println!("clamped joint!");
-        transform.translation = clamped_position;
+    /// Bodies connected by the joint, skipping the world
+    pub fn entities(&self) -> impl Iterator<Item = Entity> {
+        [self.member_a.entity, self.member_b.entity]
+            .into_iter()
+            .flatten()
     }
 }
+
+/// A joint snapped because the force on it went over its `break_force`
+#[derive(Event, Clone, Copy, Debug)]
+pub struct JointBroken {
+    pub joint: Entity,
+    pub force: f32,
+}
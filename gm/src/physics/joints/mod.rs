@@ -1,12 +1,17 @@
 pub mod joint_system;
+pub mod ragdoll;
 
 use bevy::prelude::*;
-use joint_system::*;
+pub use joint_system::*;
+use ragdoll::pose_ragdoll_bones;
 
 pub struct JointPlugin;
 
 impl Plugin for JointPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, Joint::enforce);
+        app.add_event::<JointBroken>().add_systems(
+            PostUpdate,
+            pose_ragdoll_bones.before(TransformSystem::TransformPropagate),
+        );
     }
 }
@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use super::{Joint, JointMember};
+use crate::physics::{
+    bodies::{Damping, RigidbodyComponent},
+    collisions::Collider,
+};
+
+/// Ragdolls go limp, so they lose much less speed to damping than props do
+const RAGDOLL_DAMPING: Damping = Damping {
+    linear: 0.1,
+    angular: 1.,
+};
+const RAGDOLL_FRICTION: f32 = 0.6;
+
+/// How a ragdoll bone hangs off its parent
+#[derive(Clone, Copy, Debug)]
+pub enum RagdollJoint {
+    BallSocket,
+    /// Bends around `axis`, given in the character's space at rest, between `min` and
+    /// `max` radians
+    Hinge {
+        axis: Vec3,
+        min: f32,
+        max: f32,
+    },
+}
+
+/// One capsule of a ragdoll, running from `head` to `tail` in the character's space at
+/// rest. `head` is where it joins its parent.
+#[derive(Clone, Debug)]
+pub struct RagdollBone {
+    /// Name of the glTF bone it stands in for, without any `prefix:` like `mixamorig:`
+    pub name: String,
+    /// Index of the parent bone, which always comes earlier in `Ragdoll::bones`
+    pub parent: Option<usize>,
+    pub head: Vec3,
+    pub tail: Vec3,
+    pub radius: f32,
+    pub mass: f32,
+    pub joint: RagdollJoint,
+}
+
+/// Skeleton description a ragdoll is built from, see `Ragdoll::spawn`
+#[derive(Clone, Debug, Default)]
+pub struct Ragdoll {
+    pub bones: Vec<RagdollBone>,
+}
+
+/// Entities making up a spawned ragdoll, so it can be despawned again
+#[derive(Clone, Debug)]
+pub struct SpawnedRagdoll {
+    /// One body per bone, in the order of `Ragdoll::bones`
+    pub bodies: Vec<Entity>,
+    pub joints: Vec<Entity>,
+}
+
+impl Ragdoll {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a bone hanging off the bone called `parent`, or the root with `None`.
+    /// Panics if there's no bone called `parent` yet.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_bone(
+        mut self,
+        name: impl Into<String>,
+        parent: Option<&str>,
+        head: Vec3,
+        tail: Vec3,
+        radius: f32,
+        mass: f32,
+        joint: RagdollJoint,
+    ) -> Self {
+        let parent = parent.map(|parent| {
+            self.bone_index(parent)
+                .unwrap_or_else(|| panic!("no ragdoll bone called {parent}"))
+        });
+        self.bones.push(RagdollBone {
+            name: name.into(),
+            parent,
+            head,
+            tail,
+            radius,
+            mass,
+            joint,
+        });
+        self
+    }
+
+    pub fn bone_index(&self, name: &str) -> Option<usize> {
+        self.bones.iter().position(|bone| bone.name == name)
+    }
+
+    /// Eleven bone humanoid standing on the origin and facing -Z, arms hanging down.
+    /// Bones are named after the Mixamo rig.
+    pub fn humanoid(height: f32, mass: f32) -> Self {
+        let h = height;
+        let point = |x: f32, y: f32| Vec3::new(x, y, 0.) * h;
+        let elbow = RagdollJoint::Hinge {
+            axis: Vec3::X,
+            min: 0.,
+            max: 2.5,
+        };
+        let knee = RagdollJoint::Hinge {
+            axis: Vec3::X,
+            min: -2.4,
+            max: 0.,
+        };
+
+        let mut ragdoll = Self::new()
+            .with_bone(
+                "Hips",
+                None,
+                point(0., 0.5),
+                point(0., 0.6),
+                0.07 * h,
+                0.14 * mass,
+                RagdollJoint::BallSocket,
+            )
+            .with_bone(
+                "Spine",
+                Some("Hips"),
+                point(0., 0.6),
+                point(0., 0.78),
+                0.085 * h,
+                0.28 * mass,
+                RagdollJoint::BallSocket,
+            )
+            .with_bone(
+                "Head",
+                Some("Spine"),
+                point(0., 0.82),
+                point(0., 0.98),
+                0.055 * h,
+                0.08 * mass,
+                RagdollJoint::BallSocket,
+            );
+        for (side, x) in [("Left", -1.), ("Right", 1.)] {
+            ragdoll = ragdoll
+                .with_bone(
+                    format!("{side}Arm"),
+                    Some("Spine"),
+                    point(0.12 * x, 0.76),
+                    point(0.13 * x, 0.6),
+                    0.03 * h,
+                    0.035 * mass,
+                    RagdollJoint::BallSocket,
+                )
+                .with_bone(
+                    format!("{side}ForeArm"),
+                    Some(&format!("{side}Arm")),
+                    point(0.13 * x, 0.6),
+                    point(0.13 * x, 0.45),
+                    0.028 * h,
+                    0.025 * mass,
+                    elbow,
+                )
+                .with_bone(
+                    format!("{side}UpLeg"),
+                    Some("Hips"),
+                    point(0.055 * x, 0.5),
+                    point(0.055 * x, 0.27),
+                    0.045 * h,
+                    0.11 * mass,
+                    RagdollJoint::BallSocket,
+                )
+                .with_bone(
+                    format!("{side}Leg"),
+                    Some(&format!("{side}UpLeg")),
+                    point(0.055 * x, 0.27),
+                    point(0.055 * x, 0.04),
+                    0.035 * h,
+                    0.08 * mass,
+                    knee,
+                );
+        }
+        ragdoll
+    }
+
+    /// Spawn a body per bone and a joint per parent link, all moving at `velocity`.
+    /// Bones found in `skeleton` start in its pose, the rest at rest under `transform`.
+    /// Jointed bones don't collide with each other.
+    ///
+    /// Bodies following a skeleton get a `RagdollLink` that poses its bone from then on,
+    /// so whatever animates the skeleton should be stopped.
+    pub fn spawn(
+        &self,
+        commands: &mut Commands,
+        transform: &Transform,
+        skeleton: Option<&RagdollSkeleton>,
+        velocity: Vec3,
+    ) -> SpawnedRagdoll {
+        // rest frames of every capsule, to express anchors, axes and hinge zeros in
+        let rest: Vec<(Vec3, Quat)> = self
+            .bones
+            .iter()
+            .map(|bone| {
+                let (head, tail) = (
+                    transform.transform_point(bone.head),
+                    transform.transform_point(bone.tail),
+                );
+                let rotation =
+                    Quat::from_rotation_arc(Vec3::Y, (tail - head).normalize_or(Vec3::Y));
+                ((head + tail) / 2., rotation)
+            })
+            .collect();
+
+        let mut bodies = Vec::with_capacity(self.bones.len());
+        for (index, bone) in self.bones.iter().enumerate() {
+            let length = bone.head.distance(bone.tail);
+            let posed = skeleton.and_then(|skeleton| skeleton.get(&bone.name));
+            let (rest_center, rest_rotation) = rest[index];
+            let (center, rotation) = match posed {
+                // bones point along their local +Y, as Blender and Mixamo export them.
+                // Turned the short way from rest, so the capsule's twist matches the joints'.
+                Some((_, pose)) => {
+                    let direction = pose.rotation() * Vec3::Y;
+                    let turn = Quat::from_rotation_arc(rest_rotation * Vec3::Y, direction);
+                    (
+                        pose.translation() + direction * length / 2.,
+                        turn * rest_rotation,
+                    )
+                }
+                None => (rest_center, rest_rotation),
+            };
+
+            let collider = Collider::from_capsule(
+                bone.radius,
+                (length / 2. - bone.radius).max(0.),
+                center,
+                rotation,
+            );
+            let body_transform = Transform::from_translation(center).with_rotation(rotation);
+            let mut entity = commands.spawn((
+                RigidbodyComponent::new_dynamic(
+                    bone.mass,
+                    collider,
+                    RAGDOLL_FRICTION,
+                    velocity,
+                    Vec3::ZERO,
+                    Vec3::ZERO,
+                    RAGDOLL_DAMPING,
+                    0.,
+                ),
+                body_transform,
+            ));
+            if let Some(&(bone_entity, pose)) = posed {
+                entity.insert(RagdollLink {
+                    bone: bone_entity,
+                    offset: pose.reparented_to(&GlobalTransform::from(body_transform)),
+                    order: index,
+                });
+            }
+            bodies.push(entity.id());
+        }
+
+        let mut joints = vec![];
+        for (index, bone) in self.bones.iter().enumerate() {
+            let Some(parent) = bone.parent else {
+                continue;
+            };
+            let (parent_center, parent_rotation) = rest[parent];
+            let (center, rotation) = rest[index];
+            let anchor = transform.transform_point(bone.head);
+            let axis = match bone.joint {
+                RagdollJoint::BallSocket => Vec3::Y,
+                RagdollJoint::Hinge { axis, .. } => transform.rotation * axis,
+            };
+            let member_a = JointMember::new(
+                bodies[parent],
+                parent_rotation.inverse() * (anchor - parent_center),
+                parent_rotation.inverse() * axis,
+            );
+            let member_b = JointMember::new(
+                bodies[index],
+                rotation.inverse() * (anchor - center),
+                rotation.inverse() * axis,
+            );
+
+            let mut joint = match bone.joint {
+                RagdollJoint::BallSocket => Joint::ball_socket(member_a, member_b),
+                RagdollJoint::Hinge { min, max, .. } => {
+                    Joint::hinge(member_a, member_b).with_limits(min, max)
+                }
+            }
+            .without_contacts();
+            // hinge angles count from the rest pose, not from however the character fell
+            joint.reference_rotation = Some(parent_rotation.inverse() * rotation);
+            joints.push(commands.spawn(joint).id());
+        }
+
+        SpawnedRagdoll { bodies, joints }
+    }
+}
+
+/// Bones of a glTF skinned mesh by name, with their pose at the time of death
+#[derive(Clone, Debug, Default)]
+pub struct RagdollSkeleton {
+    bones: HashMap<String, (Entity, GlobalTransform)>,
+}
+
+impl RagdollSkeleton {
+    /// Every named entity under `root`, like the joints of a spawned glTF scene
+    pub fn from_scene(
+        root: Entity,
+        children: &Query<&Children>,
+        bones: &Query<(&Name, &GlobalTransform)>,
+    ) -> Self {
+        let bones = children
+            .iter_descendants(root)
+            .filter_map(|entity| {
+                let (name, pose) = bones.get(entity).ok()?;
+                Some((bone_name(name).to_string(), (entity, *pose)))
+            })
+            .collect();
+        Self { bones }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&(Entity, GlobalTransform)> {
+        self.bones.get(bone_name(name))
+    }
+}
+
+/// Drop the rig prefix of a bone name, `mixamorig:Hips` becomes `Hips`
+fn bone_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// Poses the skeleton bone `bone` from this ragdoll body every frame
+#[derive(Component, Clone, Copy, Debug)]
+pub struct RagdollLink {
+    pub bone: Entity,
+    /// Pose of the bone relative to the body
+    pub offset: Transform,
+    /// Index of the bone in its `Ragdoll`, parents are posed before their children
+    pub order: usize,
+}
+
+/// Write the bones' local transforms from the ragdoll bodies, ahead of transform
+/// propagation. Bones in between two linked ones keep their local transform.
+pub(crate) fn pose_ragdoll_bones(
+    links: Query<(&RagdollLink, &Transform)>,
+    mut locals: Query<&mut Transform, Without<RagdollLink>>,
+    parents: Query<&ChildOf>,
+    globals: Query<&GlobalTransform>,
+) {
+    let mut links: Vec<_> = links.iter().collect();
+    if links.is_empty() {
+        return;
+    }
+    links.sort_unstable_by_key(|(link, _)| link.order);
+
+    let mut posed: HashMap<Entity, GlobalTransform> = HashMap::new();
+    for (link, body) in links {
+        let world = GlobalTransform::from(*body).mul_transform(link.offset);
+        let parent = parents
+            .get(link.bone)
+            .map(|child_of| world_pose(child_of.parent(), &posed, &parents, &locals, &globals))
+            .unwrap_or_default();
+        if let Ok(mut local) = locals.get_mut(link.bone) {
+            *local = world.reparented_to(&parent);
+        }
+        posed.insert(link.bone, world);
+    }
+}
+
+/// Pose of `entity` this frame, from the bones posed so far and the local transforms
+/// above them
+fn world_pose(
+    entity: Entity,
+    posed: &HashMap<Entity, GlobalTransform>,
+    parents: &Query<&ChildOf>,
+    locals: &Query<&mut Transform, Without<RagdollLink>>,
+    globals: &Query<&GlobalTransform>,
+) -> GlobalTransform {
+    if let Some(pose) = posed.get(&entity) {
+        return *pose;
+    }
+    match (parents.get(entity), locals.get(entity)) {
+        (Ok(child_of), Ok(local)) => {
+            world_pose(child_of.parent(), posed, parents, locals, globals).mul_transform(*local)
+        }
+        _ => globals.get(entity).copied().unwrap_or_default(),
+    }
+}
@@ -0,0 +1,57 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    bodies::{RigidbodyComponent, RigidbodyType},
+    prelude::PhysicsSet,
+};
+
+pub struct KinematicPlugin;
+
+impl Plugin for KinematicPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            drive_kinematic_targets.in_set(PhysicsSet::Prepare),
+        );
+    }
+}
+
+/// Pose a kinematic body moves to over the next step. Its velocity is derived from the
+/// move, so whatever it pushes or carries follows along. Bodies without one just keep
+/// the velocity they're given.
+#[derive(Component, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct KinematicTarget {
+    pub center: Vec3,
+    pub rotation: Quat,
+}
+
+impl KinematicTarget {
+    pub fn new(center: Vec3, rotation: Quat) -> Self {
+        Self { center, rotation }
+    }
+}
+
+fn drive_kinematic_targets(
+    mut query: Query<(&mut RigidbodyComponent, &KinematicTarget)>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+    if dt <= 0. {
+        return;
+    }
+
+    for (mut body, target) in query.iter_mut() {
+        if body.rbt != RigidbodyType::Kinematic {
+            continue;
+        }
+
+        let mut turn = target.rotation * body.collider.rotation.inverse();
+        // the short way around
+        if turn.w < 0. {
+            turn = -turn;
+        }
+        body.velocity.linear = (target.center - body.collider.center) / dt;
+        body.velocity.angular = turn.to_scaled_axis() / dt;
+    }
+}
@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+
+/// Bitmask of collision layers, one bit per layer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LayerMask(pub u32);
+
+impl LayerMask {
+    pub const ALL: Self = Self(u32::MAX);
+    pub const NONE: Self = Self(0);
+
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl std::ops::BitOr for LayerMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitAnd for LayerMask {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl std::ops::Not for LayerMask {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0)
+    }
+}
+
+/// Implemented by a game's own enum of named layers, each variant owning one bit
+pub trait PhysicsLayer: Copy {
+    fn to_bits(self) -> u32;
+}
+
+impl<L: PhysicsLayer> From<L> for LayerMask {
+    fn from(layer: L) -> Self {
+        Self(layer.to_bits())
+    }
+}
+
+impl<L: PhysicsLayer, const N: usize> From<[L; N]> for LayerMask {
+    fn from(layers: [L; N]) -> Self {
+        Self(layers.iter().fold(0, |bits, layer| bits | layer.to_bits()))
+    }
+}
+
+impl From<u32> for LayerMask {
+    fn from(bits: u32) -> Self {
+        Self(bits)
+    }
+}
+
+/// Layers used by the game
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GameLayer {
+    Default,
+    Player,
+    /// Props and anything else the player can push around
+    Props,
+    /// Small pieces that only collide with the level
+    Debris,
+    Projectiles,
+    Triggers,
+}
+
+impl PhysicsLayer for GameLayer {
+    fn to_bits(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// Layers a collider is part of, and the layers it interacts with.
+/// Colliders without this component are on every layer and see every layer.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CollisionLayers {
+    pub memberships: LayerMask,
+    pub filters: LayerMask,
+}
+
+impl Default for CollisionLayers {
+    fn default() -> Self {
+        Self {
+            memberships: LayerMask::ALL,
+            filters: LayerMask::ALL,
+        }
+    }
+}
+
+impl CollisionLayers {
+    pub fn new(memberships: impl Into<LayerMask>, filters: impl Into<LayerMask>) -> Self {
+        Self {
+            memberships: memberships.into(),
+            filters: filters.into(),
+        }
+    }
+
+    /// Whether the two collide, which needs each one to be looking for the other's layers
+    pub fn interacts_with(&self, other: &Self) -> bool {
+        self.memberships.intersects(other.filters) && other.memberships.intersects(self.filters)
+    }
+}
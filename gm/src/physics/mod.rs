@@ -1,4 +1,15 @@
 pub mod bodies;
+pub mod ccd;
 pub mod collisions;
+pub mod debug;
+pub mod fluids;
+pub mod forces;
+pub mod interpolation;
 pub mod joints;
+pub mod kinematic;
+pub mod layers;
 pub mod prelude;
+pub mod sleeping;
+pub mod snapshot;
+pub mod solver;
+pub mod spatial_query;
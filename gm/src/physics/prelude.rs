@@ -1,9 +1,74 @@
-pub use super::{bodies::*, collisions::*, joints::*};
-use bevy::app::App;
+pub use super::{
+    bodies::*, ccd::*, collisions::*, debug::*, fluids::*, forces::*, interpolation::*, joints::*,
+    kinematic::*, layers::*, sleeping::*, snapshot::*, solver::*, spatial_query::*,
+};
+use bevy::{app::App, prelude::*};
+
+use crate::gamestate::AppState;
+
+/// Default number of physics steps per second
+pub const DEFAULT_TICK_RATE: f64 = 60.;
+
+/// Order of the physics step inside `FixedUpdate`
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PhysicsSet {
+    /// Refresh collider geometry from the current body poses
+    Prepare,
+    BroadPhase,
+    NarrowPhase,
+    /// Apply forces to velocities, then solve contacts and joints
+    Solve,
+    /// Advance positions by the solved velocities
+    Integrate,
+    /// Publish the new poses for rendering
+    Sync,
+}
+
+pub struct ZphyPlugin {
+    /// Physics steps per second
+    pub tick_rate: f64,
+}
+
+impl Default for ZphyPlugin {
+    fn default() -> Self {
+        Self {
+            tick_rate: DEFAULT_TICK_RATE,
+        }
+    }
+}
+
+impl ZphyPlugin {
+    pub fn with_tick_rate(tick_rate: f64) -> Self {
+        Self { tick_rate }
+    }
+}
 
-pub struct ZphyPlugin;
 impl bevy::app::Plugin for ZphyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((CollisionPlugin, RigidBodyPlugin, JointPlugin));
+        app.insert_resource(Time::<Fixed>::from_hz(self.tick_rate))
+            .configure_sets(
+                FixedUpdate,
+                (
+                    PhysicsSet::Prepare,
+                    PhysicsSet::BroadPhase,
+                    PhysicsSet::NarrowPhase,
+                    PhysicsSet::Solve,
+                    PhysicsSet::Integrate,
+                    PhysicsSet::Sync,
+                )
+                    .chain()
+                    .run_if(in_state(AppState::Playing)),
+            )
+            .add_plugins((
+                CollisionPlugin,
+                RigidBodyPlugin,
+                CcdPlugin,
+                FluidPlugin,
+                KinematicPlugin,
+                JointPlugin,
+                SolverPlugin,
+                SleepingPlugin,
+                InterpolationPlugin,
+            ));
     }
 }
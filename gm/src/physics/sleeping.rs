@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use super::{
+    bodies::{RigidbodyComponent, RigidbodyType},
+    collisions::manifold::ContactManifolds,
+    joints::joint_system::Joint,
+    prelude::PhysicsSet,
+};
+
+pub struct SleepingPlugin;
+
+impl Plugin for SleepingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SleepSettings>()
+            .add_systems(FixedUpdate, update_sleeping.in_set(PhysicsSet::Sync));
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct SleepSettings {
+    pub enabled: bool,
+    /// Linear speed below which a body counts as resting
+    pub linear_threshold: f32,
+    /// Angular speed below which a body counts as resting
+    pub angular_threshold: f32,
+    /// Seconds a whole island has to rest before it falls asleep
+    pub time_to_sleep: f32,
+}
+
+impl Default for SleepSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            linear_threshold: 0.1,
+            angular_threshold: 0.1,
+            time_to_sleep: 0.5,
+        }
+    }
+}
+
+/// Union-find over body indices, grouping dynamic bodies that touch or are jointed
+struct Islands {
+    parents: Vec<usize>,
+}
+
+impl Islands {
+    fn new(len: usize) -> Self {
+        Self {
+            parents: (0..len).collect(),
+        }
+    }
+
+    fn root(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn join(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.root(a), self.root(b));
+        if a != b {
+            self.parents[a.max(b)] = a.min(b);
+        }
+    }
+}
+
+/// Track how long every body has been resting and put islands to sleep, or wake them, as one
+fn update_sleeping(
+    mut bodies: Query<(Entity, &mut RigidbodyComponent)>,
+    joints: Query<&Joint>,
+    manifolds: Res<ContactManifolds>,
+    settings: Res<SleepSettings>,
+    time: Res<Time>,
+) {
+    if !settings.enabled {
+        for (_, mut body) in bodies.iter_mut() {
+            if body.is_sleeping() {
+                body.wake_up();
+            }
+        }
+        return;
+    }
+
+    let dt = time.delta_secs();
+    let is_moving = |body: &RigidbodyComponent| {
+        body.velocity.linear.length() > settings.linear_threshold
+            || body.velocity.angular.length() > settings.angular_threshold
+    };
+
+    // static and kinematic bodies never sleep and don't link islands together
+    let mut entities = vec![];
+    let mut indices: HashMap<Entity, usize> = HashMap::new();
+    for (entity, mut body) in bodies.iter_mut() {
+        if body.rbt != RigidbodyType::Dynamic {
+            continue;
+        }
+
+        if body.is_sleeping() {
+            // something outside the solver gave it a push
+            if body.velocity.linear != Vec3::ZERO || body.velocity.angular != Vec3::ZERO {
+                body.wake_up();
+            }
+        } else if is_moving(&body) {
+            body.sleep_timer = 0.;
+        } else {
+            body.sleep_timer += dt;
+        }
+
+        indices.insert(entity, entities.len());
+        entities.push(entity);
+    }
+
+    let mut islands = Islands::new(entities.len());
+    for &(entity_a, entity_b) in manifolds.contacts.keys() {
+        let is_sensor = |entity| {
+            bodies
+                .get(entity)
+                .is_ok_and(|(_, body)| body.collider.is_sensor)
+        };
+        // sensors never push anything, so they can't wake it either
+        if is_sensor(entity_a) || is_sensor(entity_b) {
+            continue;
+        }
+
+        match (indices.get(&entity_a), indices.get(&entity_b)) {
+            (Some(&a), Some(&b)) => islands.join(a, b),
+            // a moving kinematic body pushing on a dynamic one keeps it awake
+            (Some(&dynamic), None) | (None, Some(&dynamic)) => {
+                let other = if indices.contains_key(&entity_a) {
+                    entity_b
+                } else {
+                    entity_a
+                };
+                let pushed = bodies.get(other).is_ok_and(|(_, other)| {
+                    other.rbt == RigidbodyType::Kinematic && is_moving(other)
+                });
+                let Ok((_, mut body)) = bodies.get_mut(entities[dynamic]) else {
+                    continue;
+                };
+                if pushed {
+                    body.wake_up();
+                }
+            }
+            (None, None) => {}
+        }
+    }
+    for joint in joints.iter() {
+        if joint.broken {
+            continue;
+        }
+        let (Some(a), Some(b)) = (joint.member_a.entity, joint.member_b.entity) else {
+            continue;
+        };
+        let (Some(&a), Some(&b)) = (indices.get(&a), indices.get(&b)) else {
+            continue;
+        };
+        islands.join(a, b);
+    }
+
+    // an island sleeps only once every body in it is asleep or has rested long enough
+    let mut restless = vec![false; entities.len()];
+    for (i, &entity) in entities.iter().enumerate() {
+        let Ok((_, body)) = bodies.get(entity) else {
+            continue;
+        };
+        if !body.is_sleeping() && body.sleep_timer < settings.time_to_sleep {
+            let root = islands.root(i);
+            restless[root] = true;
+        }
+    }
+
+    for (i, &entity) in entities.iter().enumerate() {
+        let root = islands.root(i);
+        let Ok((_, mut body)) = bodies.get_mut(entity) else {
+            continue;
+        };
+        if restless[root] {
+            if body.is_sleeping() {
+                body.wake_up();
+            }
+        } else if !body.is_sleeping() {
+            body.sleep();
+        }
+    }
+}
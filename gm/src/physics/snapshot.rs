@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    bodies::{RigidbodyComponent, RigidbodyType},
+    collisions::{ContactInfo, manifold::ContactManifolds},
+    forces::{ExternalForce, ExternalImpulse},
+    joints::Joint,
+    kinematic::KinematicTarget,
+};
+
+/// Full state of the physics world between two steps, for rollback and replays.
+/// Restoring a snapshot and stepping with the same inputs gives bit-identical results on
+/// the same platform.
+///
+/// Bodies and joints are kept by entity, so a snapshot only restores into the world it
+/// was captured from. Static bodies never change, so they're left out, along with the
+/// terrain and level geometry they share.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PhysicsSnapshot {
+    /// Sorted by entity, static bodies left out
+    pub bodies: Vec<(Entity, RigidbodyComponent)>,
+    /// Sorted by entity
+    pub joints: Vec<(Entity, Joint)>,
+    /// Sorted by entity
+    pub forces: Vec<(Entity, ExternalForce)>,
+    /// Sorted by entity, with the impulses the next step was going to apply
+    pub impulses: Vec<(Entity, ExternalImpulse)>,
+    /// Sorted by entity
+    pub kinematic_targets: Vec<(Entity, KinematicTarget)>,
+    /// Contact manifolds of the last step, carrying the impulses used to warm start the next
+    pub contacts: BTreeMap<(Entity, Entity), ContactInfo>,
+}
+
+impl PhysicsSnapshot {
+    pub fn capture(world: &mut World) -> Self {
+        let contacts = world
+            .get_resource::<ContactManifolds>()
+            .map(|manifolds| manifolds.contacts.clone())
+            .unwrap_or_default();
+
+        Self {
+            bodies: capture_components(world, is_moving),
+            joints: capture_components(world, all),
+            forces: capture_components(world, all),
+            impulses: capture_components(world, all),
+            kinematic_targets: capture_components(world, all),
+            contacts,
+        }
+    }
+
+    /// Put the world back in the captured state. Entities that became a moving body or a
+    /// joint since are despawned along with whatever else they carry, forces and targets
+    /// added since are removed. Ones despawned since are skipped, static bodies are left
+    /// as they are.
+    pub fn restore(&self, world: &mut World) {
+        restore_components(world, &self.bodies, is_moving, Added::Despawn);
+        restore_components(world, &self.joints, all, Added::Despawn);
+        restore_components(world, &self.forces, all, Added::Remove);
+        restore_components(world, &self.impulses, all, Added::Remove);
+        restore_components(world, &self.kinematic_targets, all, Added::Remove);
+        if let Some(mut manifolds) = world.get_resource_mut::<ContactManifolds>() {
+            manifolds.restore(self.contacts.clone());
+        }
+    }
+
+    pub fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> bincode::Result<Self> {
+        bincode::deserialize(bytes)
+    }
+}
+
+fn is_moving(body: &RigidbodyComponent) -> bool {
+    body.rbt != RigidbodyType::Static
+}
+
+fn all<C>(_: &C) -> bool {
+    true
+}
+
+/// Copies of every `C` that `tracked` accepts, sorted by entity
+fn capture_components<C: Component + Clone>(
+    world: &mut World,
+    tracked: fn(&C) -> bool,
+) -> Vec<(Entity, C)> {
+    let mut captured: Vec<_> = world
+        .query::<(Entity, &C)>()
+        .iter(world)
+        .filter(|(_, component)| tracked(component))
+        .map(|(entity, component)| (entity, component.clone()))
+        .collect();
+    captured.sort_unstable_by_key(|(entity, _)| *entity);
+    captured
+}
+
+/// What restoring does with an entity that got a tracked component after the capture
+#[derive(Clone, Copy)]
+enum Added {
+    Despawn,
+    Remove,
+}
+
+fn restore_components<C: Component + Clone>(
+    world: &mut World,
+    captured: &[(Entity, C)],
+    tracked: fn(&C) -> bool,
+    added_since: Added,
+) {
+    let kept: BTreeSet<Entity> = captured.iter().map(|(entity, _)| *entity).collect();
+    let added: Vec<Entity> = world
+        .query::<(Entity, &C)>()
+        .iter(world)
+        .filter(|(entity, component)| tracked(component) && !kept.contains(entity))
+        .map(|(entity, _)| entity)
+        .collect();
+    for entity in added {
+        match added_since {
+            Added::Despawn => world.entity_mut(entity).despawn(),
+            Added::Remove => {
+                world.entity_mut(entity).remove::<C>();
+            }
+        }
+    }
+
+    for (entity, component) in captured {
+        if let Ok(mut entity) = world.get_entity_mut(*entity) {
+            let added_back = !entity.contains::<C>();
+            entity.insert(component.clone());
+            // a body added back is moved to its `Transform`, the captured pose has to win
+            if added_back {
+                entity.insert(component.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::state::app::StatesPlugin;
+
+    use super::*;
+    use crate::{
+        gamestate::AppState,
+        physics::prelude::{Collider, DEFAULT_TICK_RATE, Damping, ZphyPlugin},
+    };
+
+    /// A stack of boxes falling onto the ground, with the boxes' entities
+    fn boxes_on_ground() -> (App, Vec<Entity>) {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, StatesPlugin))
+            .init_state::<AppState>()
+            .add_plugins(ZphyPlugin::default());
+
+        let ground = Collider::from_cuboid(Vec3::new(20., 0.5, 20.), Vec3::ZERO, Quat::IDENTITY);
+        app.world_mut()
+            .spawn(RigidbodyComponent::new_static(ground));
+        let boxes = (0..4)
+            .map(|i| {
+                let i = i as f32;
+                let center = Vec3::new(i * 0.3, 1.5 + i * 1.1, 0.);
+                let rotation = Quat::from_rotation_y(i * 0.4);
+                let collider = Collider::from_cuboid(Vec3::splat(0.5), center, rotation);
+                let body = RigidbodyComponent::new_dynamic(
+                    1.,
+                    collider,
+                    0.5,
+                    Vec3::ZERO,
+                    Vec3::ZERO,
+                    Vec3::ZERO,
+                    Damping::default(),
+                    0.,
+                );
+                app.world_mut().spawn(body).id()
+            })
+            .collect();
+        (app, boxes)
+    }
+
+    fn step(app: &mut App, steps: usize) {
+        for _ in 0..steps {
+            app.world_mut()
+                .resource_mut::<Time>()
+                .advance_by(Duration::from_secs_f64(1. / DEFAULT_TICK_RATE));
+            app.world_mut().run_schedule(FixedUpdate);
+        }
+    }
+
+    /// Every body, serialized so they compare bit for bit
+    fn body_bits(app: &mut App) -> Vec<(Entity, Vec<u8>)> {
+        let mut query = app.world_mut().query::<(Entity, &RigidbodyComponent)>();
+        let mut bodies: Vec<_> = query
+            .iter(app.world())
+            .map(|(entity, body)| (entity, bincode::serialize(body).unwrap()))
+            .collect();
+        bodies.sort_unstable_by_key(|(entity, _)| *entity);
+        bodies
+    }
+
+    #[test]
+    fn restored_world_replays_bit_for_bit() {
+        let (mut app, boxes) = boxes_on_ground();
+        step(&mut app, 30);
+        // not applied yet when captured, so the replay has to apply it again
+        app.world_mut()
+            .entity_mut(boxes[1])
+            .insert(ExternalImpulse::new(Vec3::new(3., 2., -1.)));
+
+        let snapshot = PhysicsSnapshot::capture(app.world_mut());
+        step(&mut app, 45);
+        let first_run = body_bits(&mut app);
+
+        snapshot.restore(app.world_mut());
+        step(&mut app, 45);
+        let second_run = body_bits(&mut app);
+
+        assert_eq!(first_run.len(), boxes.len() + 1);
+        assert_eq!(first_run, second_run);
+    }
+
+    #[test]
+    fn bodies_added_after_capture_are_despawned() {
+        let (mut app, _) = boxes_on_ground();
+        let snapshot = PhysicsSnapshot::capture(app.world_mut());
+
+        let collider = Collider::from_sphere(0.5, Vec3::Y * 4.);
+        let body = RigidbodyComponent::new_dynamic(
+            1.,
+            collider,
+            0.5,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Damping::default(),
+            0.,
+        );
+        let added = app
+            .world_mut()
+            .spawn((body, Transform::from_xyz(0., 4., 0.)))
+            .id();
+        step(&mut app, 5);
+
+        snapshot.restore(app.world_mut());
+        assert!(app.world().get_entity(added).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use bevy::prelude::*;
+
+use super::{PositionCorrection, SolverBody, SolverSettings};
+use crate::physics::{bodies::RigidbodyComponent, collisions::ContactInfo};
+
+#[derive(Clone, Copy, Debug)]
+struct ConstraintPoint {
+    r_a: Vec3,
+    r_b: Vec3,
+    penetration: f32,
+    normal_mass: f32,
+    tangent_mass: [f32; 2],
+    /// Target separating speed, from restitution and Baumgarte correction
+    velocity_bias: f32,
+    normal_impulse: f32,
+    tangent_impulse: [f32; 2],
+    pseudo_impulse: f32,
+}
+
+/// Non-penetration and friction constraint for one contact manifold
+#[derive(Clone, Debug)]
+pub struct ContactConstraint {
+    pub key: (Entity, Entity),
+    a: usize,
+    b: usize,
+    /// Points from b towards a
+    normal: Vec3,
+    tangents: [Vec3; 2],
+    friction: f32,
+    points: Vec<ConstraintPoint>,
+}
+
+impl ContactConstraint {
+    pub fn new(
+        key: (Entity, Entity),
+        (a, b): (usize, usize),
+        (body_a, body_b): (&RigidbodyComponent, &RigidbodyComponent),
+        contact: &ContactInfo,
+        solver_bodies: &[SolverBody],
+        settings: &SolverSettings,
+        dt: f32,
+    ) -> Option<Self> {
+        // a body can't push against itself
+        if a == b {
+            return None;
+        }
+        let solver_a = &solver_bodies[a];
+        let solver_b = &solver_bodies[b];
+        if solver_a.inverse_mass + solver_b.inverse_mass == 0. {
+            return None;
+        }
+
+        let normal = contact.normal;
+        let (t1, t2) = normal.any_orthonormal_pair();
+        let tangents = [t1, t2];
+        let friction = (body_a.friction * body_b.friction).sqrt();
+        let restitution = body_a.restitution.max(body_b.restitution);
+
+        let points = contact
+            .points
+            .iter()
+            .map(|point| {
+                let r_a = point.point_a - solver_a.center;
+                let r_b = point.point_b - solver_b.center;
+
+                let normal_mass = 1.
+                    / (solver_a.inverse_effective_mass(r_a, normal)
+                        + solver_b.inverse_effective_mass(r_b, normal));
+                let tangent_mass = tangents.map(|tangent| {
+                    1. / (solver_a.inverse_effective_mass(r_a, tangent)
+                        + solver_b.inverse_effective_mass(r_b, tangent))
+                });
+
+                let relative = solver_a.velocity_at(r_a) - solver_b.velocity_at(r_b);
+                let closing_speed = relative.dot(normal);
+                let mut velocity_bias = 0.;
+                if closing_speed < -settings.restitution_threshold {
+                    velocity_bias = -restitution * closing_speed;
+                }
+                if settings.position_correction == PositionCorrection::Baumgarte {
+                    velocity_bias += settings.baumgarte / dt
+                        * (point.penetration - settings.linear_slop).max(0.);
+                }
+
+                ConstraintPoint {
+                    r_a,
+                    r_b,
+                    penetration: point.penetration,
+                    normal_mass,
+                    tangent_mass,
+                    velocity_bias,
+                    normal_impulse: point.normal_impulse,
+                    tangent_impulse: tangents.map(|tangent| point.tangent_impulse.dot(tangent)),
+                    pseudo_impulse: 0.,
+                }
+            })
+            .collect();
+
+        Some(Self {
+            key,
+            a,
+            b,
+            normal,
+            tangents,
+            friction,
+            points,
+        })
+    }
+
+    fn bodies<'a>(&self, bodies: &'a mut [SolverBody]) -> (&'a mut SolverBody, &'a mut SolverBody) {
+        debug_assert_ne!(self.a, self.b, "a contact between a body and itself");
+        if self.a < self.b {
+            let (left, right) = bodies.split_at_mut(self.b);
+            (&mut left[self.a], &mut right[0])
+        } else {
+            let (left, right) = bodies.split_at_mut(self.a);
+            (&mut right[0], &mut left[self.b])
+        }
+    }
+
+    pub fn reset_impulses(&mut self) {
+        for point in self.points.iter_mut() {
+            point.normal_impulse = 0.;
+            point.tangent_impulse = [0.; 2];
+        }
+    }
+
+    /// Reapply last step's impulses so the iterations start close to the answer
+    pub fn warm_start(&self, bodies: &mut [SolverBody]) {
+        let (a, b) = self.bodies(bodies);
+        for point in &self.points {
+            let impulse = self.normal * point.normal_impulse
+                + self.tangents[0] * point.tangent_impulse[0]
+                + self.tangents[1] * point.tangent_impulse[1];
+            a.apply_impulse(impulse, point.r_a);
+            b.apply_impulse(-impulse, point.r_b);
+        }
+    }
+
+    pub fn solve_velocity(&mut self, bodies: &mut [SolverBody]) {
+        let normal = self.normal;
+        let tangents = self.tangents;
+        let friction = self.friction;
+        let (a, b) = self.bodies(bodies);
+
+        for point in self.points.iter_mut() {
+            // friction first, bounded by the normal impulse of the last iteration
+            let max_friction = friction * point.normal_impulse;
+            for (i, tangent) in tangents.iter().enumerate() {
+                let relative = a.velocity_at(point.r_a) - b.velocity_at(point.r_b);
+                let lambda = -relative.dot(*tangent) * point.tangent_mass[i];
+
+                let old = point.tangent_impulse[i];
+                point.tangent_impulse[i] = (old + lambda).clamp(-max_friction, max_friction);
+                let impulse = *tangent * (point.tangent_impulse[i] - old);
+
+                a.apply_impulse(impulse, point.r_a);
+                b.apply_impulse(-impulse, point.r_b);
+            }
+
+            let relative = a.velocity_at(point.r_a) - b.velocity_at(point.r_b);
+            let lambda = (point.velocity_bias - relative.dot(normal)) * point.normal_mass;
+
+            // accumulated impulse may shrink but never pull the bodies together
+            let old = point.normal_impulse;
+            point.normal_impulse = (old + lambda).max(0.);
+            let impulse = normal * (point.normal_impulse - old);
+
+            a.apply_impulse(impulse, point.r_a);
+            b.apply_impulse(-impulse, point.r_b);
+        }
+    }
+
+    /// Split impulse pass, pushing the bodies apart with pseudo velocities
+    pub fn solve_position(
+        &mut self,
+        bodies: &mut [SolverBody],
+        settings: &SolverSettings,
+        dt: f32,
+    ) {
+        let normal = self.normal;
+        let (a, b) = self.bodies(bodies);
+
+        for point in self.points.iter_mut() {
+            let target =
+                settings.baumgarte / dt * (point.penetration - settings.linear_slop).max(0.);
+            let relative = a.pseudo_velocity_at(point.r_a) - b.pseudo_velocity_at(point.r_b);
+            let lambda = (target - relative.dot(normal)) * point.normal_mass;
+
+            let old = point.pseudo_impulse;
+            point.pseudo_impulse = (old + lambda).max(0.);
+            let impulse = normal * (point.pseudo_impulse - old);
+
+            a.apply_pseudo_impulse(impulse, point.r_a);
+            b.apply_pseudo_impulse(-impulse, point.r_b);
+        }
+    }
+
+    /// Save the accumulated impulses back into the manifold for the next step
+    pub fn store_impulses(&self, contact: &mut ContactInfo) {
+        for (stored, point) in contact.points.iter_mut().zip(&self.points) {
+            stored.normal_impulse = point.normal_impulse;
+            stored.tangent_impulse = self.tangents[0] * point.tangent_impulse[0]
+                + self.tangents[1] * point.tangent_impulse[1];
+        }
+    }
+}
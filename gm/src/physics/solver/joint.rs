@@ -0,0 +1,506 @@
+use bevy::prelude::*;
+
+use super::{SolverBody, SolverSettings};
+use crate::physics::joints::{Joint, JointImpulses, JointMember, JointType};
+
+/// What a row holds, which also picks the stored impulse it warm starts from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RowKind {
+    Linear,
+    Angular,
+    Limit,
+    /// Motors don't count towards the force that breaks a joint
+    Motor,
+}
+
+/// The stored impulse of a kind of row, in world space
+fn stored(impulses: &mut JointImpulses, kind: RowKind) -> &mut Vec3 {
+    match kind {
+        RowKind::Linear => &mut impulses.linear,
+        RowKind::Angular => &mut impulses.angular,
+        RowKind::Limit => &mut impulses.limit,
+        RowKind::Motor => &mut impulses.motor,
+    }
+}
+
+/// One scalar velocity constraint. Its impulse pushes b along `linear` and turns it
+/// along `angular_b`, and pushes a the opposite way.
+#[derive(Clone, Copy, Debug)]
+struct JointRow {
+    linear: Vec3,
+    angular_a: Vec3,
+    angular_b: Vec3,
+    mass: f32,
+    /// Relative velocity the row drives towards
+    target: f32,
+    impulse: f32,
+    min_impulse: f32,
+    max_impulse: f32,
+    kind: RowKind,
+}
+
+impl JointRow {
+    fn new(
+        (linear, angular_a, angular_b): (Vec3, Vec3, Vec3),
+        target: f32,
+        (min_impulse, max_impulse): (f32, f32),
+        kind: RowKind,
+        a: &SolverBody,
+        b: &SolverBody,
+    ) -> Option<Self> {
+        let inverse_mass = (a.inverse_mass + b.inverse_mass) * linear.length_squared()
+            + angular_a.dot(a.inverse_inertia * angular_a)
+            + angular_b.dot(b.inverse_inertia * angular_b);
+        if inverse_mass <= f32::EPSILON {
+            return None;
+        }
+
+        Some(Self {
+            linear,
+            angular_a,
+            angular_b,
+            mass: 1. / inverse_mass,
+            target,
+            impulse: 0.,
+            min_impulse,
+            max_impulse,
+            kind,
+        })
+    }
+
+    /// Row keeping the anchors `r_a` and `r_b` together along `direction`
+    fn linear(
+        direction: Vec3,
+        (r_a, r_b): (Vec3, Vec3),
+        target: f32,
+        bounds: (f32, f32),
+        a: &SolverBody,
+        b: &SolverBody,
+    ) -> Option<Self> {
+        let jacobian = (direction, r_a.cross(direction), r_b.cross(direction));
+        Self::new(jacobian, target, bounds, RowKind::Linear, a, b)
+    }
+
+    /// Row on the relative angular velocity around `axis`
+    fn angular(
+        axis: Vec3,
+        target: f32,
+        bounds: (f32, f32),
+        a: &SolverBody,
+        b: &SolverBody,
+    ) -> Option<Self> {
+        Self::new(
+            (Vec3::ZERO, axis, axis),
+            target,
+            bounds,
+            RowKind::Angular,
+            a,
+            b,
+        )
+    }
+
+    fn limit(mut self) -> Self {
+        self.kind = RowKind::Limit;
+        self
+    }
+
+    fn motor(mut self) -> Self {
+        self.kind = RowKind::Motor;
+        self
+    }
+
+    /// World space direction the row pushes b along, or turns it around
+    fn direction(&self) -> Vec3 {
+        if self.linear == Vec3::ZERO {
+            self.angular_b
+        } else {
+            self.linear
+        }
+    }
+
+    fn apply(&self, a: &mut SolverBody, b: &mut SolverBody, impulse: f32) {
+        a.linear -= self.linear * a.inverse_mass * impulse;
+        a.angular -= a.inverse_inertia * self.angular_a * impulse;
+        b.linear += self.linear * b.inverse_mass * impulse;
+        b.angular += b.inverse_inertia * self.angular_b * impulse;
+    }
+
+    fn velocity(&self, a: &SolverBody, b: &SolverBody) -> f32 {
+        self.linear.dot(b.linear) + self.angular_b.dot(b.angular)
+            - self.linear.dot(a.linear)
+            - self.angular_a.dot(a.angular)
+    }
+
+    fn solve(&mut self, a: &mut SolverBody, b: &mut SolverBody) {
+        let lambda = (self.target - self.velocity(a, b)) * self.mass;
+        let old = self.impulse;
+        self.impulse = (old + lambda).clamp(self.min_impulse, self.max_impulse);
+        self.apply(a, b, self.impulse - old);
+    }
+}
+
+/// Keeps two anchors together, solved as one 3x3 block so long lever arms converge
+#[derive(Clone, Copy, Debug)]
+struct PointRow {
+    r_a: Vec3,
+    r_b: Vec3,
+    mass: Mat3,
+    target: Vec3,
+    impulse: Vec3,
+}
+
+impl PointRow {
+    fn new((r_a, r_b): (Vec3, Vec3), target: Vec3, a: &SolverBody, b: &SolverBody) -> Option<Self> {
+        let skew = |r: Vec3| Mat3::from_cols(r.cross(Vec3::X), r.cross(Vec3::Y), r.cross(Vec3::Z));
+        let (skew_a, skew_b) = (skew(r_a), skew(r_b));
+        let inverse_mass = Mat3::from_diagonal(Vec3::splat(a.inverse_mass + b.inverse_mass))
+            - skew_a * a.inverse_inertia * skew_a
+            - skew_b * b.inverse_inertia * skew_b;
+        if inverse_mass.determinant().abs() <= f32::EPSILON {
+            return None;
+        }
+
+        Some(Self {
+            r_a,
+            r_b,
+            mass: inverse_mass.inverse(),
+            target,
+            impulse: Vec3::ZERO,
+        })
+    }
+
+    fn solve(&mut self, a: &mut SolverBody, b: &mut SolverBody) {
+        let relative = b.velocity_at(self.r_b) - a.velocity_at(self.r_a);
+        let impulse = self.mass * (self.target - relative);
+        self.impulse += impulse;
+
+        a.apply_impulse(-impulse, self.r_a);
+        b.apply_impulse(impulse, self.r_b);
+    }
+}
+
+const UNBOUNDED: (f32, f32) = (f32::NEG_INFINITY, f32::INFINITY);
+const PUSH_ONLY: (f32, f32) = (0., f32::INFINITY);
+
+/// Pose of one side of a joint, with the world being a fixed body at the origin
+#[derive(Clone, Copy, Debug)]
+pub struct JointPose {
+    pub center: Vec3,
+    pub rotation: Quat,
+}
+
+impl JointPose {
+    pub const WORLD: Self = Self {
+        center: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+    };
+
+    pub(crate) fn anchor(&self, member: &JointMember) -> Vec3 {
+        self.center + self.rotation * member.anchor
+    }
+
+    pub(crate) fn axis(&self, member: &JointMember) -> Vec3 {
+        self.rotation * member.axis
+    }
+}
+
+/// Velocity constraints of one joint for this step. Drift is always corrected with a
+/// Baumgarte bias, whichever `PositionCorrection` the contacts use.
+#[derive(Clone, Debug)]
+pub struct JointConstraint {
+    pub entity: Entity,
+    a: usize,
+    b: usize,
+    rows: Vec<JointRow>,
+    point: Option<PointRow>,
+    break_force: Option<f32>,
+}
+
+impl JointConstraint {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        entity: Entity,
+        joint: &Joint,
+        (a, b): (usize, usize),
+        (pose_a, pose_b): (JointPose, JointPose),
+        reference_rotation: Quat,
+        solver_bodies: &[SolverBody],
+        settings: &SolverSettings,
+        dt: f32,
+    ) -> Option<Self> {
+        // `Joint::new` refuses these, but a joint can still be edited or deserialized into one
+        if a == b {
+            return None;
+        }
+        let body_a = &solver_bodies[a];
+        let body_b = &solver_bodies[b];
+        if body_a.inverse_mass + body_b.inverse_mass == 0. {
+            return None;
+        }
+
+        let beta = settings.baumgarte / dt;
+        let anchor_a = pose_a.anchor(&joint.member_a);
+        let anchor_b = pose_b.anchor(&joint.member_b);
+        let axis = pose_a.axis(&joint.member_a);
+        let r_b = anchor_b - body_b.center;
+        let separation = anchor_b - anchor_a;
+
+        // how far b is turned away from where the joint started, in a's frame
+        let mut twist = pose_a.rotation.inverse() * pose_b.rotation * reference_rotation.inverse();
+        if twist.w < 0. {
+            twist = -twist;
+        }
+
+        let mut rows = vec![];
+        let mut point = None;
+        match joint.joint_type {
+            JointType::BallSocket | JointType::Hinge => {
+                let r_a = anchor_a - body_a.center;
+                point = PointRow::new((r_a, r_b), -beta * separation, body_a, body_b);
+            }
+            JointType::Slider => {
+                // held at b's anchor, so sliding doesn't move the lever arm on b
+                let r_a = anchor_b - body_a.center;
+                let (t1, t2) = axis.any_orthonormal_pair();
+                for direction in [t1, t2] {
+                    let target = -beta * separation.dot(direction);
+                    rows.extend(JointRow::linear(
+                        direction,
+                        (r_a, r_b),
+                        target,
+                        UNBOUNDED,
+                        body_a,
+                        body_b,
+                    ));
+                }
+
+                let rotation_error = pose_a.rotation * twist.to_scaled_axis();
+                for direction in [Vec3::X, Vec3::Y, Vec3::Z] {
+                    let target = -beta * rotation_error.dot(direction);
+                    rows.extend(JointRow::angular(
+                        direction, target, UNBOUNDED, body_a, body_b,
+                    ));
+                }
+
+                let position = separation.dot(axis);
+                if let Some(limits) = joint.limits {
+                    if position < limits.min {
+                        let target = beta * (limits.min - position);
+                        rows.extend(
+                            JointRow::linear(axis, (r_a, r_b), target, PUSH_ONLY, body_a, body_b)
+                                .map(JointRow::limit),
+                        );
+                    } else if position > limits.max {
+                        let target = beta * (position - limits.max);
+                        rows.extend(
+                            JointRow::linear(-axis, (r_a, r_b), target, PUSH_ONLY, body_a, body_b)
+                                .map(JointRow::limit),
+                        );
+                    }
+                }
+                if let Some(motor) = joint.motor {
+                    let max_impulse = motor.max_force * dt;
+                    rows.extend(
+                        JointRow::linear(
+                            axis,
+                            (r_a, r_b),
+                            motor.target_velocity,
+                            (-max_impulse, max_impulse),
+                            body_a,
+                            body_b,
+                        )
+                        .map(JointRow::motor),
+                    );
+                }
+            }
+        }
+
+        if joint.joint_type == JointType::Hinge {
+            let axis_b = pose_b.axis(&joint.member_b);
+            let misalignment = axis.cross(axis_b);
+            let (t1, t2) = axis.any_orthonormal_pair();
+            for direction in [t1, t2] {
+                let target = -beta * misalignment.dot(direction);
+                rows.extend(JointRow::angular(
+                    direction, target, UNBOUNDED, body_a, body_b,
+                ));
+            }
+
+            let angle = 2.
+                * Vec3::new(twist.x, twist.y, twist.z)
+                    .dot(joint.member_a.axis)
+                    .atan2(twist.w);
+            if let Some(limits) = joint.limits {
+                if angle < limits.min {
+                    let target = beta * (limits.min - angle);
+                    rows.extend(
+                        JointRow::angular(axis, target, PUSH_ONLY, body_a, body_b)
+                            .map(JointRow::limit),
+                    );
+                } else if angle > limits.max {
+                    let target = beta * (angle - limits.max);
+                    rows.extend(
+                        JointRow::angular(-axis, target, PUSH_ONLY, body_a, body_b)
+                            .map(JointRow::limit),
+                    );
+                }
+            }
+            if let Some(motor) = joint.motor {
+                let max_impulse = motor.max_force * dt;
+                rows.extend(
+                    JointRow::angular(
+                        axis,
+                        motor.target_velocity,
+                        (-max_impulse, max_impulse),
+                        body_a,
+                        body_b,
+                    )
+                    .map(JointRow::motor),
+                );
+            }
+        }
+
+        if settings.warm_starting {
+            let mut impulses = joint.impulses;
+            for row in rows.iter_mut() {
+                row.impulse = stored(&mut impulses, row.kind)
+                    .dot(row.direction())
+                    .clamp(row.min_impulse, row.max_impulse);
+            }
+            if let Some(point) = point.as_mut() {
+                point.impulse = impulses.linear;
+            }
+        }
+
+        Some(Self {
+            entity,
+            a,
+            b,
+            rows,
+            point,
+            break_force: joint.break_force,
+        })
+    }
+
+    fn bodies<'a>(&self, bodies: &'a mut [SolverBody]) -> (&'a mut SolverBody, &'a mut SolverBody) {
+        debug_assert_ne!(self.a, self.b, "a joint between a body and itself");
+        if self.a < self.b {
+            let (left, right) = bodies.split_at_mut(self.b);
+            (&mut left[self.a], &mut right[0])
+        } else {
+            let (left, right) = bodies.split_at_mut(self.a);
+            (&mut right[0], &mut left[self.b])
+        }
+    }
+
+    /// Reapply last step's impulses so the iterations start close to the answer
+    pub fn warm_start(&self, bodies: &mut [SolverBody]) {
+        let (a, b) = self.bodies(bodies);
+        for row in &self.rows {
+            row.apply(a, b, row.impulse);
+        }
+        if let Some(point) = &self.point {
+            a.apply_impulse(-point.impulse, point.r_a);
+            b.apply_impulse(point.impulse, point.r_b);
+        }
+    }
+
+    pub fn solve_velocity(&mut self, bodies: &mut [SolverBody]) {
+        let (a, b) = self.bodies(bodies);
+        for row in self.rows.iter_mut() {
+            row.solve(a, b);
+        }
+        if let Some(point) = self.point.as_mut() {
+            point.solve(a, b);
+        }
+    }
+
+    /// Force the joint held this step, leaving out its motor
+    pub fn force(&self, dt: f32) -> f32 {
+        let impulse_squared: f32 = self
+            .rows
+            .iter()
+            .filter(|row| row.kind != RowKind::Motor)
+            .map(|row| row.impulse * row.impulse)
+            .sum::<f32>()
+            + self
+                .point
+                .map_or(0., |point| point.impulse.length_squared());
+        impulse_squared.sqrt() / dt
+    }
+
+    /// Impulses applied this step, for the joint to warm start the next one
+    pub fn impulses(&self) -> JointImpulses {
+        let mut impulses = JointImpulses::default();
+        for row in &self.rows {
+            *stored(&mut impulses, row.kind) += row.direction() * row.impulse;
+        }
+        if let Some(point) = &self.point {
+            impulses.linear += point.impulse;
+        }
+        impulses
+    }
+
+    /// Whether the joint held more than its break force this step
+    pub fn should_break(&self, dt: f32) -> bool {
+        self.break_force
+            .is_some_and(|break_force| self.force(dt) > break_force)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::state::app::StatesPlugin;
+
+    use super::*;
+    use crate::{
+        gamestate::AppState,
+        physics::prelude::{
+            Collider, DEFAULT_TICK_RATE, Damping, Gravity, RigidbodyComponent, ZphyPlugin,
+        },
+    };
+
+    #[test]
+    fn hanging_body_keeps_the_impulse_holding_it_up() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, StatesPlugin))
+            .init_state::<AppState>()
+            .add_plugins(ZphyPlugin::default());
+
+        let mass = 2.;
+        let collider = Collider::from_sphere(0.5, Vec3::new(0., -2., 0.));
+        let body = RigidbodyComponent::new_dynamic(
+            mass,
+            collider,
+            0.5,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Damping::default(),
+            0.,
+        );
+        let body = app.world_mut().spawn(body).id();
+        let joint = Joint::ball_socket(
+            JointMember::world(Vec3::ZERO, Vec3::Y),
+            JointMember::new(body, Vec3::new(0., 2., 0.), Vec3::Y),
+        );
+        let joint = app.world_mut().spawn(joint).id();
+
+        let dt = 1. / DEFAULT_TICK_RATE;
+        for _ in 0..60 {
+            app.world_mut()
+                .resource_mut::<Time>()
+                .advance_by(Duration::from_secs_f64(dt));
+            app.world_mut().run_schedule(FixedUpdate);
+        }
+
+        let weight = -app.world().resource::<Gravity>().0 * mass * dt as f32;
+        let held = app.world().get::<Joint>(joint).unwrap().impulses.linear;
+        assert!(
+            held.distance(weight) < weight.length() * 0.05,
+            "{held} != {weight}"
+        );
+    }
+}
@@ -0,0 +1,327 @@
+pub mod contact;
+pub mod joint;
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use contact::ContactConstraint;
+use joint::{JointConstraint, JointPose};
+
+use super::{
+    bodies::{RigidbodyComponent, RigidbodyType, apply_forces},
+    collisions::manifold::ContactManifolds,
+    joints::{Joint, JointBroken},
+    prelude::PhysicsSet,
+};
+
+pub struct SolverPlugin;
+
+impl Plugin for SolverPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SolverSettings>().add_systems(
+            FixedUpdate,
+            solve_constraints
+                .in_set(PhysicsSet::Solve)
+                .after(apply_forces),
+        );
+    }
+}
+
+/// How overlap left over after the velocity solve gets pushed apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PositionCorrection {
+    /// Feed penetration back into the velocity solve as a bias. Cheap, but adds energy.
+    Baumgarte,
+    /// Solve penetration separately with pseudo velocities that never reach the real
+    /// ones, so pushing bodies apart doesn't make them bounce
+    SplitImpulse,
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct SolverSettings {
+    pub velocity_iterations: usize,
+    /// Only used with `PositionCorrection::SplitImpulse`
+    pub position_iterations: usize,
+    pub position_correction: PositionCorrection,
+    /// Fraction of the penetration, and of joint drift, corrected every step
+    pub baumgarte: f32,
+    /// Penetration allowed before correcting, keeps resting contacts from jittering
+    pub linear_slop: f32,
+    /// Closing speed below which contacts don't bounce
+    pub restitution_threshold: f32,
+    /// Start each step from the impulses of the previous one
+    pub warm_starting: bool,
+}
+
+impl Default for SolverSettings {
+    fn default() -> Self {
+        Self {
+            velocity_iterations: 8,
+            position_iterations: 3,
+            position_correction: PositionCorrection::SplitImpulse,
+            baumgarte: 0.2,
+            linear_slop: 0.005,
+            restitution_threshold: 1.,
+            warm_starting: true,
+        }
+    }
+}
+
+/// Copy of the body state the solver works on, written back once it's done
+#[derive(Clone, Copy, Debug)]
+pub struct SolverBody {
+    pub linear: Vec3,
+    pub angular: Vec3,
+    pub pseudo_linear: Vec3,
+    pub pseudo_angular: Vec3,
+    pub inverse_mass: f32,
+    pub inverse_inertia: Mat3,
+    pub center: Vec3,
+}
+
+impl SolverBody {
+    pub fn from_body(body: &RigidbodyComponent) -> Self {
+        // sleeping bodies hold still like static ones until the island wakes up
+        let (inverse_mass, inverse_inertia) =
+            if body.rbt == RigidbodyType::Dynamic && !body.is_sleeping() {
+                (
+                    body.inverse_mass,
+                    body.get_inverse_inertia_world(&body.collider.rotation),
+                )
+            } else {
+                (0., Mat3::ZERO)
+            };
+
+        Self {
+            linear: body.velocity.linear,
+            angular: body.velocity.angular,
+            pseudo_linear: Vec3::ZERO,
+            pseudo_angular: Vec3::ZERO,
+            inverse_mass,
+            inverse_inertia,
+            center: body.collider.center,
+        }
+    }
+
+    /// Immovable body, standing in for the world at the far end of a joint
+    pub fn fixed(center: Vec3) -> Self {
+        Self {
+            linear: Vec3::ZERO,
+            angular: Vec3::ZERO,
+            pseudo_linear: Vec3::ZERO,
+            pseudo_angular: Vec3::ZERO,
+            inverse_mass: 0.,
+            inverse_inertia: Mat3::ZERO,
+            center,
+        }
+    }
+
+    /// Velocity of the point at offset `r` from the center
+    pub fn velocity_at(&self, r: Vec3) -> Vec3 {
+        self.linear + self.angular.cross(r)
+    }
+
+    pub fn pseudo_velocity_at(&self, r: Vec3) -> Vec3 {
+        self.pseudo_linear + self.pseudo_angular.cross(r)
+    }
+
+    pub fn apply_impulse(&mut self, impulse: Vec3, r: Vec3) {
+        self.linear += impulse * self.inverse_mass;
+        self.angular += self.inverse_inertia * r.cross(impulse);
+    }
+
+    pub fn apply_pseudo_impulse(&mut self, impulse: Vec3, r: Vec3) {
+        self.pseudo_linear += impulse * self.inverse_mass;
+        self.pseudo_angular += self.inverse_inertia * r.cross(impulse);
+    }
+
+    /// Inverse of the mass felt by an impulse along `direction` applied at offset `r`
+    pub fn inverse_effective_mass(&self, r: Vec3, direction: Vec3) -> f32 {
+        let r_cross = r.cross(direction);
+        self.inverse_mass + r_cross.dot(self.inverse_inertia * r_cross)
+    }
+}
+
+/// Sequential impulse solve over every joint and contact manifold of this step
+pub(crate) fn solve_constraints(
+    mut bodies: Query<&mut RigidbodyComponent>,
+    mut joints: Query<(Entity, &mut Joint)>,
+    mut broken_joints: EventWriter<JointBroken>,
+    mut manifolds: ResMut<ContactManifolds>,
+    settings: Res<SolverSettings>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+    if dt <= 0. {
+        return;
+    }
+
+    let mut solver_bodies: Vec<SolverBody> = vec![];
+    let mut indices: HashMap<Entity, usize> = HashMap::new();
+    let mut body_index = |entity: Entity, solver_bodies: &mut Vec<SolverBody>| {
+        if let Some(&index) = indices.get(&entity) {
+            return Some(index);
+        }
+        let body = bodies.get(entity).ok()?;
+        indices.insert(entity, solver_bodies.len());
+        solver_bodies.push(SolverBody::from_body(body));
+        Some(solver_bodies.len() - 1)
+    };
+
+    // solved in entity order, so the result doesn't depend on how the query iterates
+    let mut joint_entities: Vec<Entity> = joints.iter().map(|(entity, _)| entity).collect();
+    joint_entities.sort_unstable();
+
+    let mut joint_constraints: Vec<JointConstraint> = vec![];
+    for entity in joint_entities {
+        let Ok((_, mut joint)) = joints.get_mut(entity) else {
+            continue;
+        };
+        if joint.broken {
+            continue;
+        }
+
+        // the world is a fixed body that never shows up in the query
+        let mut side = |member_entity: Option<Entity>, solver_bodies: &mut Vec<SolverBody>| {
+            let Some(member_entity) = member_entity else {
+                solver_bodies.push(SolverBody::fixed(Vec3::ZERO));
+                return Some((solver_bodies.len() - 1, JointPose::WORLD, true));
+            };
+            let body = bodies.get(member_entity).ok()?;
+            let pose = JointPose {
+                center: body.collider.center,
+                rotation: body.collider.rotation,
+            };
+            let resting = body.is_resting();
+            Some((body_index(member_entity, solver_bodies)?, pose, resting))
+        };
+        let (Some((a, pose_a, resting_a)), Some((b, pose_b, resting_b))) = (
+            side(joint.member_a.entity, &mut solver_bodies),
+            side(joint.member_b.entity, &mut solver_bodies),
+        ) else {
+            continue;
+        };
+        if resting_a && resting_b {
+            continue;
+        }
+
+        let reference_rotation = *joint
+            .reference_rotation
+            .get_or_insert(pose_a.rotation.inverse() * pose_b.rotation);
+        joint_constraints.extend(JointConstraint::new(
+            entity,
+            &joint,
+            (a, b),
+            (pose_a, pose_b),
+            reference_rotation,
+            &solver_bodies,
+            &settings,
+            dt,
+        ));
+    }
+
+    let mut constraints: Vec<ContactConstraint> = vec![];
+    for (&(entity_a, entity_b), contact) in manifolds.contacts.iter() {
+        let (Ok(body_a), Ok(body_b)) = (bodies.get(entity_a), bodies.get(entity_b)) else {
+            continue;
+        };
+        if body_a.is_resting() && body_b.is_resting() {
+            continue;
+        }
+        if body_a.collider.is_sensor || body_b.collider.is_sensor {
+            continue;
+        }
+
+        let (Some(a), Some(b)) = (
+            body_index(entity_a, &mut solver_bodies),
+            body_index(entity_b, &mut solver_bodies),
+        ) else {
+            continue;
+        };
+
+        if let Some(constraint) = ContactConstraint::new(
+            (entity_a, entity_b),
+            (a, b),
+            (body_a, body_b),
+            contact,
+            &solver_bodies,
+            &settings,
+            dt,
+        ) {
+            constraints.push(constraint);
+        }
+    }
+
+    if settings.warm_starting {
+        for constraint in joint_constraints.iter() {
+            constraint.warm_start(&mut solver_bodies);
+        }
+        for constraint in constraints.iter() {
+            constraint.warm_start(&mut solver_bodies);
+        }
+    } else {
+        for constraint in constraints.iter_mut() {
+            constraint.reset_impulses();
+        }
+    }
+
+    for _ in 0..settings.velocity_iterations {
+        for constraint in joint_constraints.iter_mut() {
+            constraint.solve_velocity(&mut solver_bodies);
+        }
+        for constraint in constraints.iter_mut() {
+            constraint.solve_velocity(&mut solver_bodies);
+        }
+    }
+
+    for constraint in joint_constraints.iter() {
+        let Ok((_, mut joint)) = joints.get_mut(constraint.entity) else {
+            continue;
+        };
+        joint.impulses = constraint.impulses();
+        if !constraint.should_break(dt) {
+            continue;
+        }
+        joint.broken = true;
+        broken_joints.write(JointBroken {
+            joint: constraint.entity,
+            force: constraint.force(dt),
+        });
+    }
+
+    if settings.position_correction == PositionCorrection::SplitImpulse {
+        for _ in 0..settings.position_iterations {
+            for constraint in constraints.iter_mut() {
+                constraint.solve_position(&mut solver_bodies, &settings, dt);
+            }
+        }
+    }
+
+    for (entity, index) in indices {
+        let Ok(mut body) = bodies.get_mut(entity) else {
+            continue;
+        };
+        if body.rbt != RigidbodyType::Dynamic || body.is_sleeping() {
+            continue;
+        }
+
+        let solved = &solver_bodies[index];
+        body.velocity.linear = solved.linear;
+        body.velocity.angular = solved.angular;
+
+        // split impulse pushes the pose directly and is then forgotten
+        body.collider.center += solved.pseudo_linear * dt;
+        let pseudo_speed = solved.pseudo_angular.length();
+        if pseudo_speed > f32::EPSILON {
+            let delta =
+                Quat::from_axis_angle(solved.pseudo_angular / pseudo_speed, pseudo_speed * dt);
+            body.collider.rotation = (delta * body.collider.rotation).normalize();
+        }
+    }
+
+    for constraint in constraints {
+        if let Some(contact) = manifolds.contacts.get_mut(&constraint.key) {
+            constraint.store_impulses(contact);
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashSet;
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use super::{
+    bodies::{RigidbodyComponent, Velocity},
+    collisions::{
+        Collider,
+        broadphase::{Aabb, BroadPhase, Unindexed},
+        cast::{CastHit, ray_cast, shape_cast, shape_cast_entering},
+        collider_systems::get_collision_info,
+    },
+    layers::{CollisionLayers, LayerMask},
+};
+
+/// Which colliders a spatial query is allowed to hit
+#[derive(Clone, Debug)]
+pub struct SpatialQueryFilter {
+    /// Only colliders that are a member of one of these layers are hit
+    pub mask: LayerMask,
+    pub excluded_entities: HashSet<Entity>,
+    /// Skip sensor colliders, for queries standing in for solid movement
+    pub exclude_sensors: bool,
+}
+
+impl Default for SpatialQueryFilter {
+    fn default() -> Self {
+        Self {
+            mask: LayerMask::ALL,
+            excluded_entities: HashSet::new(),
+            exclude_sensors: false,
+        }
+    }
+}
+
+impl SpatialQueryFilter {
+    pub fn with_mask(mut self, mask: LayerMask) -> Self {
+        self.mask = mask;
+        self
+    }
+
+    pub fn with_excluded_entities(mut self, entities: impl IntoIterator<Item = Entity>) -> Self {
+        self.excluded_entities.extend(entities);
+        self
+    }
+
+    pub fn without_sensors(mut self) -> Self {
+        self.exclude_sensors = true;
+        self
+    }
+
+    fn allows(
+        &self,
+        entity: Entity,
+        collider: &Collider,
+        layers: Option<&CollisionLayers>,
+    ) -> bool {
+        let memberships = layers.map_or(LayerMask::ALL, |layers| layers.memberships);
+        memberships.intersects(self.mask)
+            && !self.excluded_entities.contains(&entity)
+            && !(self.exclude_sensors && collider.is_sensor)
+    }
+}
+
+/// A collider found by a spatial query
+#[derive(Clone, Copy, Debug)]
+pub struct QueryHit {
+    pub entity: Entity,
+    /// World space point on the hit collider
+    pub point: Vec3,
+    /// Surface normal of the hit collider at `point`, facing the query
+    pub normal: Vec3,
+    /// Distance travelled by a cast, or how deep an overlap goes
+    pub distance: f32,
+}
+
+impl QueryHit {
+    fn from_cast(entity: Entity, hit: CastHit) -> Self {
+        Self {
+            entity,
+            point: hit.point,
+            normal: hit.normal,
+            distance: hit.distance,
+        }
+    }
+}
+
+/// Ray casts, shape casts and overlap tests against every rigidbody collider
+#[derive(SystemParam)]
+pub struct SpatialQuery<'w, 's> {
+    colliders: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static RigidbodyComponent,
+            Option<&'static CollisionLayers>,
+        ),
+    >,
+    unindexed: Query<'w, 's, Entity, With<Unindexed>>,
+    broadphase: Res<'w, BroadPhase>,
+}
+
+impl SpatialQuery<'_, '_> {
+    fn candidates<'a>(
+        &'a self,
+        bounds: Aabb,
+        filter: &'a SpatialQueryFilter,
+    ) -> impl Iterator<Item = (Entity, &'a Collider)> + 'a {
+        // bodies added since the proxies were built aren't culled by them, and may still be
+        // marked right after the rebuild. Despawned ones are skipped by the lookup.
+        self.broadphase
+            .overlapping(bounds)
+            .filter(move |entity| !self.unindexed.contains(*entity))
+            .chain(self.unindexed.iter())
+            .filter_map(move |entity| self.colliders.get(entity).ok())
+            .filter(move |(entity, body, layers)| filter.allows(*entity, &body.collider, *layers))
+            .filter(move |(_, body, _)| body.collider.aabb().intersects(&bounds))
+            .map(|(entity, body, _)| (entity, &body.collider))
+    }
+
+    /// Body of a collider the queries see, to follow up on a hit
+    pub fn body(&self, entity: Entity) -> Option<&RigidbodyComponent> {
+        self.colliders.get(entity).ok().map(|(_, body, _)| body)
+    }
+
+    /// Closest collider hit by a ray
+    pub fn cast_ray(
+        &self,
+        origin: Vec3,
+        direction: Dir3,
+        max_distance: f32,
+        filter: &SpatialQueryFilter,
+    ) -> Option<QueryHit> {
+        self.cast_ray_all(origin, direction, max_distance, filter)
+            .into_iter()
+            .next()
+    }
+
+    /// Every collider hit by a ray, closest first
+    pub fn cast_ray_all(
+        &self,
+        origin: Vec3,
+        direction: Dir3,
+        max_distance: f32,
+        filter: &SpatialQueryFilter,
+    ) -> Vec<QueryHit> {
+        let end = origin + direction * max_distance;
+        let bounds = Aabb::new(origin.min(end), origin.max(end));
+
+        let mut hits: Vec<QueryHit> = self
+            .candidates(bounds, filter)
+            .filter_map(|(entity, collider)| {
+                ray_cast(collider, origin, *direction, max_distance)
+                    .map(|hit| QueryHit::from_cast(entity, hit))
+            })
+            .collect();
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        hits
+    }
+
+    /// First collider touched by `shape` when moved from its current pose along `direction`
+    pub fn cast_shape(
+        &self,
+        shape: &Collider,
+        direction: Dir3,
+        max_distance: f32,
+        filter: &SpatialQueryFilter,
+    ) -> Option<QueryHit> {
+        self.cast_shape_all(shape, direction, max_distance, filter)
+            .into_iter()
+            .next()
+    }
+
+    /// Every collider touched by `shape` along the way, closest first. Colliders it
+    /// already overlaps are hit at a distance of zero.
+    pub fn cast_shape_all(
+        &self,
+        shape: &Collider,
+        direction: Dir3,
+        max_distance: f32,
+        filter: &SpatialQueryFilter,
+    ) -> Vec<QueryHit> {
+        self.shape_hits(shape, direction, max_distance, filter, shape_cast)
+    }
+
+    /// First collider `shape` runs into when moved along `direction`, passing through
+    /// whatever it already overlaps, down to single triangles of a mesh or heightfield
+    pub fn cast_shape_entering(
+        &self,
+        shape: &Collider,
+        direction: Dir3,
+        max_distance: f32,
+        filter: &SpatialQueryFilter,
+    ) -> Option<QueryHit> {
+        self.shape_hits(shape, direction, max_distance, filter, shape_cast_entering)
+            .into_iter()
+            .next()
+    }
+
+    fn shape_hits(
+        &self,
+        shape: &Collider,
+        direction: Dir3,
+        max_distance: f32,
+        filter: &SpatialQueryFilter,
+        cast: fn(&Collider, &Collider, Vec3, f32) -> Option<CastHit>,
+    ) -> Vec<QueryHit> {
+        let start = shape.aabb();
+        let end = Aabb::from_center_half_extents(
+            start.center() + direction * max_distance,
+            start.half_extents(),
+        );
+
+        let mut hits: Vec<QueryHit> = self
+            .candidates(start.merge(&end), filter)
+            .filter_map(|(entity, collider)| {
+                cast(shape, collider, *direction, max_distance)
+                    .map(|hit| QueryHit::from_cast(entity, hit))
+            })
+            .collect();
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        hits
+    }
+
+    /// Every collider overlapping a sphere
+    pub fn overlap_sphere(
+        &self,
+        center: Vec3,
+        radius: f32,
+        filter: &SpatialQueryFilter,
+    ) -> Vec<QueryHit> {
+        self.overlap_shape(&Collider::from_sphere(radius, center), filter)
+    }
+
+    /// Every collider overlapping an axis aligned box
+    pub fn overlap_aabb(&self, aabb: Aabb, filter: &SpatialQueryFilter) -> Vec<QueryHit> {
+        let shape = Collider::from_cuboid(aabb.half_extents(), aabb.center(), Quat::IDENTITY);
+        self.overlap_shape(&shape, filter)
+    }
+
+    /// Every collider overlapping `shape` at its current pose, `normal` pushing `shape` out
+    pub fn overlap_shape(&self, shape: &Collider, filter: &SpatialQueryFilter) -> Vec<QueryHit> {
+        self.candidates(shape.aabb(), filter)
+            .filter_map(|(entity, collider)| {
+                // cached vertices are only refreshed at the start of a physics step
+                let mut collider = collider.clone();
+                collider.update_geometry();
+
+                let contact =
+                    get_collision_info(shape, &Velocity::ZERO, &collider, &Velocity::ZERO)?;
+                Some(QueryHit {
+                    entity,
+                    point: contact.contact_point_b,
+                    normal: contact.normal,
+                    distance: contact.penetration_depth,
+                })
+            })
+            .collect()
+    }
+}
@@ -1,19 +1,63 @@
 use crate::gamestate::AppState;
-use crate::physics::collisions::collider_systems::detect_player_collisions;
-use crate::physics::{bodies::RigidbodyComponent, prelude::Collider};
-use bevy::{prelude::*, window::CursorGrabMode};
-
-use super::{
-    player_data::{Player, PlayerPositioning},
-    player_info::{PlayerId, PlayerInfo, PlayerLevelInfo, PlayerUsername},
-    player_stats::PlayerStats,
+use crate::physics::{
+    bodies::{RigidbodyComponent, RigidbodyType},
+    ccd::sweep_ccd_bodies,
+    collisions::broadphase::update_query_proxies,
+    fluids::FluidVolume,
+    forces::Gravity,
+    interpolation::PhysicsInterpolation,
+    layers::{CollisionLayers, LayerMask},
+    prelude::{Collider, PhysicsSet},
+    spatial_query::{QueryHit, SpatialQuery, SpatialQueryFilter},
 };
+use crate::terrain::{terrain_body, terrain_mesh, world_terrain};
+use bevy::{ecs::system::SystemParam, prelude::*, window::CursorGrabMode};
+use serde::{Deserialize, Serialize};
+
+use super::player_data::{Player, PlayerPositioning};
 
 const JUMP_FORCE: f32 = 55.;
+/// Decay rate of the player's velocity per second, it's scaled by `exp(-rate * dt)`
+const PLAYER_LINEAR_DAMPING: f32 = 5.3;
+/// Most surfaces a single move slides along before giving up on the rest of it
+const MAX_SLIDES: usize = 4;
+const MAX_DEPENETRATION_ITERATIONS: usize = 4;
+/// Movement shorter than this is dropped
+const MIN_MOVE_DISTANCE: f32 = 1e-4;
+/// Speed away from the ground above which the character counts as leaving it
+const LEAVE_GROUND_SPEED: f32 = 0.1;
+/// Swimming speed, as a fraction of walking speed
+const SWIM_SPEED_FACTOR: f32 = 0.5;
+/// Upward speed of a swim stroke, taken with the jump key
+const SWIM_STROKE: f32 = 15.;
+/// Upward acceleration of a swimmer, floating them back up to the surface
+const SWIM_BUOYANCY: f32 = 10.;
 
+/// Moves every `Player` by its `PlayerInput`. Needs no window or renderer, so it runs on
+/// the server too.
 pub struct ControllerPlugin;
 
 impl Plugin for ControllerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            (
+                insert_player_interpolation.in_set(PhysicsSet::Prepare),
+                (update_swimming, player_movement, move_character)
+                    .chain()
+                    .after(update_query_proxies)
+                    .after(sweep_ccd_bodies)
+                    .in_set(PhysicsSet::Integrate),
+                push_player_poses.in_set(PhysicsSet::Sync),
+            ),
+        );
+    }
+}
+
+/// Camera, cursor and keyboard input of the player at this machine, needs a window
+pub struct LocalPlayerPlugin;
+
+impl Plugin for LocalPlayerPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(AppState::Playing), setup_camera)
             .add_systems(
@@ -21,10 +65,7 @@ impl Plugin for ControllerPlugin {
                 (
                     mouse_movement.run_if(in_state(crate::gamestate::AppState::Playing)),
                     lock_cursor.run_if(in_state(crate::gamestate::AppState::Playing)),
-                    player_movement
-                        .after(detect_player_collisions)
-                        .run_if(in_state(crate::gamestate::AppState::Playing)),
-                    apply_player_forces.run_if(in_state(crate::gamestate::AppState::Playing)),
+                    gather_player_input.run_if(in_state(crate::gamestate::AppState::Playing)),
                 ),
             );
     }
@@ -41,15 +82,498 @@ pub fn update_fov(mut query: Query<&mut Projection>, mut query1: Query<&mut Came
     }
 }
 
-fn apply_player_forces(mut query: Query<(&mut Player, &mut Transform)>, time: Res<Time>) {
-    if let Ok((mut player, mut transform)) = query.single_mut() {
-        let linear_damping = 0.085;
-        player.pos.vel *= 1.0 - linear_damping;
-        player.pos.vel.y += -9.18 * 25. * time.delta_secs();
+/// What moves players besides their own input
+#[derive(SystemParam)]
+pub struct Surroundings<'w, 's> {
+    fluids: Query<'w, 's, &'static FluidVolume>,
+    gravity: Res<'w, Gravity>,
+}
+
+impl Surroundings<'_, '_> {
+    /// A player swims once their middle is under the surface of a fluid
+    pub fn is_swimming(&self, loc: Vec3) -> bool {
+        self.fluids.iter().any(|fluid| fluid.depth(loc).is_some())
+    }
+
+    pub fn movement_state(
+        &self,
+        player: &Player,
+        controller: &CharacterController,
+    ) -> MovementState {
+        let loc = player.pos.loc;
+        let fluid_drag = self
+            .fluids
+            .iter()
+            .filter(|fluid| fluid.depth(loc).is_some())
+            .map(|fluid| fluid.linear_drag)
+            .fold(0., f32::max);
+        MovementState {
+            pos: player.pos.clone(),
+            speed: player.stats.speed.speed,
+            gravity: self.gravity.0 * controller.gravity_scale,
+            fluid_drag,
+        }
+    }
+}
+
+/// Everything `step` needs to move a player
+#[derive(Clone, Debug)]
+pub struct MovementState {
+    pub pos: PlayerPositioning,
+    /// Walking speed, from `PlayerStats`
+    pub speed: f32,
+    /// Pull on the player while airborne, `Gravity` times the controller's gravity scale
+    pub gravity: Vec3,
+    /// Drag of the fluid the player is in, 0 out of fluids
+    pub fluid_drag: f32,
+}
+
+/// Turn a player's input and the forces on them into a new velocity for one physics
+/// step. Pure, so clients can predict their own movement and replay it when the server
+/// corrects them. Moving through the world is left to `CharacterController::move_player`.
+pub fn step(state: &MovementState, input: &PlayerInput, dt: f32) -> MovementState {
+    let mut pos = state.pos.clone();
+    let forward: Vec3 = pos.dir * Vec3::NEG_Z;
+    let right: Vec3 = pos.dir * Vec3::X;
 
-        transform.translation.x += player.pos.vel.x * time.delta_secs();
-        transform.translation.z += player.pos.vel.z * time.delta_secs();
-        transform.translation.y += player.pos.vel.y * time.delta_secs();
+    let forward_horizontal = Vec3::new(forward.x, 0.0, forward.z).normalize_or_zero();
+    let right_horizontal = Vec3::new(right.x, 0.0, right.z).normalize_or_zero();
+
+    // swimmers go wherever they look, walkers stay level
+    let direction = if pos.swimming {
+        (forward * input.movement.y + right * input.movement.x).normalize_or_zero()
+    } else {
+        (forward_horizontal * input.movement.y + right_horizontal * input.movement.x)
+            .normalize_or_zero()
+    };
+
+    let mut speed = if input.sprint {
+        state.speed * 1.5
+    } else {
+        state.speed
+    };
+    if pos.swimming {
+        speed *= SWIM_SPEED_FACTOR;
+    }
+
+    let horizontal_movement = direction * speed * dt;
+    pos.vel += speed * horizontal_movement;
+    if input.jump && pos.swimming {
+        pos.vel.y = pos.vel.y.max(SWIM_STROKE);
+    } else if input.jump && pos.grounded {
+        pos.vel.y = JUMP_FORCE;
+        pos.grounded = false;
+    }
+
+    pos.vel *= (-PLAYER_LINEAR_DAMPING * dt).exp();
+    if pos.swimming {
+        // the player bobs up to the surface instead of falling, held back by the fluid's drag
+        pos.vel *= (-state.fluid_drag * dt).exp();
+        pos.vel.y += SWIM_BUOYANCY * dt;
+    } else if !pos.grounded {
+        // standing on walkable ground cancels gravity, so slopes don't slide the player down
+        pos.vel += state.gravity * dt;
+    }
+
+    MovementState {
+        pos,
+        ..state.clone()
+    }
+}
+
+fn update_swimming(mut query: Query<&mut Player>, surroundings: Surroundings) {
+    for mut player in query.iter_mut() {
+        player.pos.swimming = surroundings.is_swimming(player.pos.loc);
+    }
+}
+
+pub fn player_movement(
+    mut query: Query<(&mut Player, &mut PlayerInput, &CharacterController)>,
+    surroundings: Surroundings,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+    for (mut player, mut input, controller) in query.iter_mut() {
+        let state = surroundings.movement_state(&player, controller);
+        player.pos = step(&state, &input, dt).pos;
+        input.jump = false;
+    }
+}
+
+/// Kinematic capsule moved by `Player::pos.vel` with move-and-slide. It is pushed out of
+/// colliders but never pushed by them, and shoves the dynamic bodies it walks into.
+#[derive(Component, Clone, Debug)]
+pub struct CharacterController {
+    pub radius: f32,
+    /// Half the distance between the centers of the capsule's caps
+    pub half_length: f32,
+    /// Steepest slope, in radians, the character can stand on and walk up
+    pub max_slope_angle: f32,
+    /// Tallest ledge the character walks onto without jumping
+    pub step_height: f32,
+    /// How far down the character is pulled to stay on the ground over slopes and steps
+    pub snap_distance: f32,
+    /// Gap kept between the capsule and what it touches, so the next cast doesn't start inside
+    pub skin_width: f32,
+    /// Weighs the character against the dynamic bodies it pushes
+    pub mass: f32,
+    /// Multiplier of `Gravity` while airborne, well above 1 for snappy jumps
+    pub gravity_scale: f32,
+    /// Normal of the ground the character stands on, `None` while airborne
+    pub ground_normal: Option<Vec3>,
+    /// Body the character stands on and rides along with, `None` while airborne
+    pub ground_entity: Option<Entity>,
+}
+
+impl Default for CharacterController {
+    fn default() -> Self {
+        Self {
+            radius: 1.,
+            half_length: 4.,
+            max_slope_angle: 45f32.to_radians(),
+            step_height: 1.5,
+            snap_distance: 1.,
+            skin_width: 0.05,
+            mass: 80.,
+            gravity_scale: 23.4,
+            ground_normal: None,
+            ground_entity: None,
+        }
+    }
+}
+
+impl CharacterController {
+    /// The capsule, standing upright with its center at `position`
+    pub fn collider(&self, position: Vec3) -> Collider {
+        Collider::from_capsule(self.radius, self.half_length, position, Quat::IDENTITY)
+    }
+
+    /// The capsule grown by the skin twice over, overlapping whatever the character rests against
+    pub fn contact_collider(&self, position: Vec3) -> Collider {
+        Collider::from_capsule(
+            self.radius + self.skin_width * 2.,
+            self.half_length,
+            position,
+            Quat::IDENTITY,
+        )
+    }
+
+    pub fn is_walkable(&self, normal: Vec3) -> bool {
+        normal.dot(Vec3::Y) >= self.max_slope_angle.cos()
+    }
+
+    /// Normal to slide along. Too steep slopes act as walls, so they can't be climbed.
+    fn slide_normal(&self, normal: Vec3) -> Vec3 {
+        if normal.y > 0. && !self.is_walkable(normal) {
+            normal.with_y(0.).normalize_or(normal)
+        } else {
+            normal
+        }
+    }
+
+    /// Distance the capsule can travel from `position` before its skin touches something
+    fn sweep(
+        &self,
+        query: &SpatialQuery,
+        position: Vec3,
+        direction: Dir3,
+        distance: f32,
+        filter: &SpatialQueryFilter,
+    ) -> (f32, Option<QueryHit>) {
+        let hit = query.cast_shape(
+            &self.collider(position),
+            direction,
+            distance + self.skin_width,
+            filter,
+        );
+        let travel = hit.map_or(distance, |hit| {
+            (hit.distance - self.skin_width).clamp(0., distance)
+        });
+        (travel, hit)
+    }
+
+    /// Normal of the surface under `hit`. The capsule's rounded bottom touches ledges on
+    /// their edge, which gives a slanted normal even though the top is flat.
+    fn ground_normal(
+        &self,
+        query: &SpatialQuery,
+        hit: &QueryHit,
+        filter: &SpatialQueryFilter,
+    ) -> Vec3 {
+        let inward = -hit.normal.with_y(0.).normalize_or_zero();
+        let origin = hit.point + (inward + Vec3::Y) * self.skin_width;
+        query
+            .cast_ray(origin, Dir3::NEG_Y, self.skin_width * 2., filter)
+            .map_or(hit.normal, |ground| ground.normal)
+    }
+
+    /// Push the capsule out of anything it was left overlapping
+    fn depenetrate(
+        &self,
+        query: &SpatialQuery,
+        mut position: Vec3,
+        filter: &SpatialQueryFilter,
+    ) -> Vec3 {
+        for _ in 0..MAX_DEPENETRATION_ITERATIONS {
+            let Some(deepest) = query
+                .overlap_shape(&self.collider(position), filter)
+                .into_iter()
+                .max_by(|a, b| a.distance.total_cmp(&b.distance))
+            else {
+                break;
+            };
+            position += deepest.normal * (deepest.distance + self.skin_width);
+        }
+        position
+    }
+
+    /// Move by `motion`, sliding along whatever is in the way. Surfaces touched are pushed
+    /// to `hits`, with the normal that was slid along.
+    fn move_and_slide(
+        &self,
+        query: &SpatialQuery,
+        mut position: Vec3,
+        motion: Vec3,
+        filter: &SpatialQueryFilter,
+        hits: &mut Vec<QueryHit>,
+    ) -> Vec3 {
+        let mut remaining = motion;
+        for _ in 0..MAX_SLIDES {
+            let distance = remaining.length();
+            if distance < MIN_MOVE_DISTANCE {
+                break;
+            }
+            let Ok(direction) = Dir3::new(remaining) else {
+                break;
+            };
+
+            let (travel, hit) = self.sweep(query, position, direction, distance, filter);
+            position += direction * travel;
+            let Some(hit) = hit else {
+                break;
+            };
+
+            let normal = self.slide_normal(hit.normal);
+            remaining = direction * (distance - travel);
+            remaining -= normal * remaining.dot(normal);
+            hits.push(QueryHit { normal, ..hit });
+        }
+        position
+    }
+
+    /// Try to climb onto a ledge in the way of `motion`: rise, move across, settle back down.
+    /// Only succeeds when the character lands on walkable ground.
+    fn step_up(
+        &self,
+        query: &SpatialQuery,
+        position: Vec3,
+        motion: Vec3,
+        filter: &SpatialQueryFilter,
+    ) -> Option<Vec3> {
+        let horizontal = motion.with_y(0.);
+        let direction = Dir3::new(horizontal).ok()?;
+
+        let (rise, _) = self.sweep(query, position, Dir3::Y, self.step_height, filter);
+        if rise < MIN_MOVE_DISTANCE {
+            return None;
+        }
+        let raised = position + Vec3::Y * rise;
+
+        let (travel, _) = self.sweep(query, raised, direction, horizontal.length(), filter);
+        if travel < MIN_MOVE_DISTANCE {
+            return None;
+        }
+        let moved = raised + direction * travel;
+
+        let (fall, ground) = self.sweep(query, moved, Dir3::NEG_Y, rise, filter);
+        self.is_walkable(self.ground_normal(query, &ground?, filter))
+            .then_some(moved - Vec3::Y * fall)
+    }
+
+    /// Move a player by their velocity over `dt` with move-and-slide, stepping up ledges
+    /// and following the ground, then look for ground under their new position. The body
+    /// they stand on carries them along first, it already moved this step. Gives the
+    /// surfaces run into, with the direction and speed they were hit at.
+    ///
+    /// The server and a client replaying its predictions both move players through here,
+    /// so they end up in the same place.
+    pub fn move_player(
+        &mut self,
+        query: &SpatialQuery,
+        pos: &mut PlayerPositioning,
+        filter: &SpatialQueryFilter,
+        dt: f32,
+    ) -> Vec<(Entity, Vec3, f32)> {
+        let carried = self.ground_entity.and_then(|ground| {
+            let motion = query.body(ground)?.point_motion(pos.loc, dt);
+            Some((ground, motion))
+        });
+        let mut carry = Vec3::ZERO;
+        if let Some((ground, motion)) = carried {
+            let filter = filter.clone().with_excluded_entities([ground]);
+            let carried_to = self.move_and_slide(query, pos.loc, motion, &filter, &mut vec![]);
+            carry = carried_to - pos.loc;
+            pos.loc = carried_to;
+        }
+
+        let start = self.depenetrate(query, pos.loc, filter);
+        let mut velocity = pos.vel;
+        let mut motion = velocity * dt;
+        // follow the ground instead of walking into or off it, unless jumping
+        if let Some(ground) = self.ground_normal.filter(|_| velocity.y <= 0.) {
+            let length = motion.length();
+            motion = (motion - ground * motion.dot(ground)).normalize_or_zero() * length;
+        }
+
+        let mut hits = vec![];
+        let mut position = self.move_and_slide(query, start, motion, filter, &mut hits);
+
+        let blocked = hits.iter().any(|hit| !self.is_walkable(hit.normal));
+        let stepped = (self.ground_normal.is_some() && blocked)
+            .then(|| self.step_up(query, start, motion, filter))
+            .flatten()
+            .filter(|stepped| {
+                (*stepped - start).with_y(0.).length() > (position - start).with_y(0.).length()
+            });
+        if let Some(stepped) = stepped {
+            position = stepped;
+            hits.retain(|hit| self.is_walkable(hit.normal));
+        }
+
+        let mut pushes = vec![];
+        for hit in &hits {
+            let into_surface = velocity.dot(hit.normal);
+            if into_surface < 0. {
+                velocity -= hit.normal * into_surface;
+                pushes.push((hit.entity, -hit.normal, -into_surface));
+            }
+        }
+
+        // stay glued to the ground while walking, but let go when jumping off it
+        let probe = if self.ground_normal.is_some() {
+            self.snap_distance
+        } else {
+            self.skin_width
+        };
+        self.land(query, &mut position, &mut velocity, probe, filter);
+        if self.ground_normal.is_none() {
+            // jumping or walking off a moving body keeps its momentum
+            velocity += carry / dt;
+        }
+
+        pos.loc = position;
+        pos.vel = velocity;
+        pos.grounded = self.ground_normal.is_some();
+        pushes
+    }
+
+    /// Look for ground under a player put somewhere new, like where the server says they
+    /// are, so their next move follows it and rides along with it
+    pub fn find_ground(
+        &mut self,
+        query: &SpatialQuery,
+        pos: &mut PlayerPositioning,
+        filter: &SpatialQueryFilter,
+    ) {
+        let probe = if pos.grounded {
+            self.snap_distance
+        } else {
+            self.skin_width
+        };
+        self.land(query, &mut pos.loc, &mut pos.vel, probe, filter);
+        pos.grounded = self.ground_normal.is_some();
+    }
+
+    /// Stand on walkable ground up to `probe` below `position`, unless moving away from it
+    fn land(
+        &mut self,
+        query: &SpatialQuery,
+        position: &mut Vec3,
+        velocity: &mut Vec3,
+        probe: f32,
+        filter: &SpatialQueryFilter,
+    ) {
+        let (fall, ground) = self.sweep(query, *position, Dir3::NEG_Y, probe, filter);
+        let ground = ground
+            .map(|hit| (hit.entity, self.ground_normal(query, &hit, filter)))
+            .filter(|&(_, normal)| self.is_walkable(normal))
+            .filter(|&(_, normal)| velocity.y <= 0. || velocity.dot(normal) < LEAVE_GROUND_SPEED);
+        self.ground_normal = ground.map(|(_, normal)| normal);
+        self.ground_entity = ground.map(|(entity, _)| entity);
+        if self.ground_normal.is_some() {
+            position.y -= fall;
+            velocity.y = 0.;
+        }
+    }
+}
+
+type CharacterQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static mut Player,
+        &'static mut CharacterController,
+        Option<&'static CollisionLayers>,
+    ),
+>;
+
+/// What a character collides with: everything its layers allow, except itself and sensors
+pub fn character_filter(entity: Entity, layers: Option<&CollisionLayers>) -> SpatialQueryFilter {
+    SpatialQueryFilter::default()
+        .with_mask(layers.map_or(LayerMask::ALL, |layers| layers.filters))
+        .with_excluded_entities([entity])
+        .without_sensors()
+}
+
+fn move_character(
+    mut characters: CharacterQuery,
+    mut world: ParamSet<(SpatialQuery, Query<&mut RigidbodyComponent>)>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+    let mut pushes = vec![];
+
+    for (entity, mut player, mut controller, layers) in characters.iter_mut() {
+        let filter = character_filter(entity, layers);
+        let hits = controller.move_player(&world.p0(), &mut player.pos, &filter, dt);
+        pushes.extend(
+            hits.into_iter()
+                .map(|(entity, direction, speed)| (entity, direction, speed, controller.mass)),
+        );
+    }
+
+    // dynamic bodies in the way are shoved up to the character's speed, less so the heavier they are
+    let mut bodies = world.p1();
+    for (entity, direction, speed, mass) in pushes {
+        let Ok(mut body) = bodies.get_mut(entity) else {
+            continue;
+        };
+        if body.rbt != RigidbodyType::Dynamic {
+            continue;
+        }
+
+        let missing = (speed - body.velocity.linear.dot(direction)).max(0.);
+        let share = (mass * body.inverse_mass).min(1.);
+        body.velocity.linear += direction * missing * share;
+        body.wake_up();
+    }
+}
+
+fn insert_player_interpolation(
+    mut commands: Commands,
+    query: Query<(Entity, &Player), Without<PhysicsInterpolation>>,
+) {
+    for (entity, player) in query.iter() {
+        commands
+            .entity(entity)
+            .insert(PhysicsInterpolation::translation_only(player.pos.loc));
+    }
+}
+
+fn push_player_poses(mut query: Query<(&Player, &mut PhysicsInterpolation)>) {
+    for (player, mut interpolation) in query.iter_mut() {
+        interpolation.push(player.pos.loc, Quat::IDENTITY);
     }
 }
 
@@ -89,65 +613,49 @@ fn setup_camera(
         player_entity.add_child(cam);
     }
 
-    let cuboid = Cuboid::new(100., 1., 100.);
+    let (terrain, transform) = world_terrain();
     commands.spawn((
-        Mesh3d(meshes.add(cuboid)),
+        Mesh3d(meshes.add(terrain_mesh(&terrain))),
         MeshMaterial3d(materials.add(Color::WHITE)),
-        RigidbodyComponent::new_static(Collider::from_cuboid(
-            cuboid.half_size,
-            Vec3::ZERO,
-            Quat::from_euler(EulerRot::XYZ, 0., 0., 0.),
-        )),
-        Transform::from_xyz(0., -10., 0.),
+        terrain_body(terrain, &transform),
+        transform,
     ));
 }
 
-pub fn player_movement(
-    mut player_q: Query<(&mut Transform, &mut super::player_data::Player)>,
+/// Movement keys held this frame, latched until the next physics step consumes them
+#[derive(Component, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PlayerInput {
+    /// `x` is strafe (right positive), `y` is forward
+    pub movement: Vec2,
+    pub sprint: bool,
+    pub jump: bool,
+}
+
+fn gather_player_input(
+    mut query: Query<&mut PlayerInput>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
-    time: Res<Time>,
 ) {
-    if let Ok((transform, mut player)) = player_q.single_mut() {
-        let mut direction = Vec3::ZERO;
-        let forward: Vec3 = transform.forward().into();
-        let right: Vec3 = transform.right().into();
-
-        let forward_horizontal = Vec3::new(forward.x, 0.0, forward.z).normalize();
-        let right_horizontal = Vec3::new(right.x, 0.0, right.z).normalize();
-
+    for mut input in query.iter_mut() {
+        let mut movement = Vec2::ZERO;
         if keyboard_input.pressed(KeyCode::KeyW) {
-            direction += forward_horizontal;
+            movement.y += 1.;
         }
         if keyboard_input.pressed(KeyCode::KeyS) {
-            direction -= forward_horizontal;
+            movement.y -= 1.;
         }
         if keyboard_input.pressed(KeyCode::KeyA) {
-            direction -= right_horizontal;
+            movement.x -= 1.;
         }
         if keyboard_input.pressed(KeyCode::KeyD) {
-            direction += right_horizontal;
-        }
-
-        if direction.length_squared() > 0.0 {
-            direction = direction.normalize();
+            movement.x += 1.;
         }
 
-        let speed = if keyboard_input.pressed(KeyCode::ShiftLeft) {
-            player.stats.speed.speed * 1.5
-        } else {
-            player.stats.speed.speed
-        };
-
-        let horizontal_movement = direction * speed * time.delta_secs();
-
-        player.pos.vel += speed * horizontal_movement; //.with_y(0.);
-        //println!("{:?}", player.pos.grounded);
-        if keyboard_input.just_pressed(KeyCode::Space) && player.pos.grounded {
-            player.pos.vel.y = JUMP_FORCE;
-            player.pos.grounded = false;
+        input.movement = movement;
+        input.sprint = keyboard_input.pressed(KeyCode::ShiftLeft);
+        // only set here and cleared by the physics step, so a tap between ticks isn't lost
+        if keyboard_input.just_pressed(KeyCode::Space) {
+            input.jump = true;
         }
-
-        player.pos.set_loc(transform.translation);
     }
 }
 
@@ -4,7 +4,7 @@ pub mod player_info;
 pub mod player_stats;
 
 use bevy::prelude::*;
-use controller::ControllerPlugin;
+use controller::{ControllerPlugin, LocalPlayerPlugin};
 
 use crate::ui::UiPlugin;
 
@@ -12,6 +12,6 @@ pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((ControllerPlugin, UiPlugin));
+        app.add_plugins((ControllerPlugin, LocalPlayerPlugin, UiPlugin));
     }
 }
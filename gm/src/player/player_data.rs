@@ -2,11 +2,13 @@ use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use super::{
+    controller::{CharacterController, PlayerInput},
     player_info::{PlayerId, PlayerInfo, PlayerLevelInfo, PlayerUsername},
     player_stats::PlayerStats,
 };
 
 #[derive(Clone, Debug, Component, Serialize, Deserialize)]
+#[require(PlayerInput, CharacterController)]
 pub struct Player {
     pub info: PlayerInfo,
     pub pos: PlayerPositioning,
@@ -39,6 +41,7 @@ impl Default for Player {
                 dir: Quat::default(),
                 vel: Vec3::ZERO,
                 grounded: false,
+                swimming: false,
             },
             stats: PlayerStats::default(),
         }
@@ -51,6 +54,8 @@ pub struct PlayerPositioning {
     pub dir: Quat,
     pub vel: Vec3,
     pub grounded: bool,
+    /// In a `FluidVolume` deep enough to swim, instead of walking
+    pub swimming: bool,
 }
 
 #[allow(unused)]
@@ -61,6 +66,7 @@ impl PlayerPositioning {
             dir,
             vel: Vec3::ZERO,
             grounded: false,
+            swimming: false,
         }
     }
 
@@ -88,4 +94,10 @@ impl PlayerPositioning {
     pub fn set_grounded(&mut self, grounded: bool) {
         self.grounded = grounded;
     }
+    pub fn is_swimming(&self) -> bool {
+        self.swimming
+    }
+    pub fn set_swimming(&mut self, swimming: bool) {
+        self.swimming = swimming;
+    }
 }
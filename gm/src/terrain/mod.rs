@@ -0,0 +1,186 @@
+pub mod noise;
+
+use bevy::{
+    asset::RenderAssetUsages,
+    prelude::*,
+    render::mesh::{Indices, PrimitiveTopology},
+};
+use noise::fractal_noise;
+
+use crate::physics::{
+    bodies::RigidbodyComponent,
+    collisions::{Collider, heightfield::Heightfield},
+};
+
+pub struct TerrainPlugin;
+
+impl Plugin for TerrainPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, build_heightmap_terrains);
+    }
+}
+
+/// Rolling terrain shaped by seeded fractal noise
+#[derive(Clone, Debug)]
+pub struct NoiseTerrain {
+    pub seed: u32,
+    /// Samples along X and Z
+    pub resolution: UVec2,
+    pub cell_size: f32,
+    /// Heights stay roughly within `[-amplitude, amplitude]`
+    pub amplitude: f32,
+    /// Rough distance between two hills
+    pub feature_size: f32,
+    pub octaves: u32,
+}
+
+impl Default for NoiseTerrain {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            resolution: UVec2::splat(101),
+            cell_size: 1.,
+            amplitude: 4.,
+            feature_size: 30.,
+            octaves: 4,
+        }
+    }
+}
+
+/// Ground of the game world and where it sits, the same on the server and every client
+pub fn world_terrain() -> (Heightfield, Transform) {
+    (
+        NoiseTerrain::default().heightfield(),
+        Transform::from_xyz(0., -10., 0.),
+    )
+}
+
+impl NoiseTerrain {
+    pub fn heightfield(&self) -> Heightfield {
+        Heightfield::from_fn(
+            self.resolution.x as usize,
+            self.resolution.y as usize,
+            Vec2::splat(self.cell_size),
+            |position| {
+                fractal_noise(self.seed, position / self.feature_size, self.octaves)
+                    * self.amplitude
+            },
+        )
+    }
+}
+
+/// Heightfield with one sample per pixel of a grayscale image, black at 0 and white at
+/// `max_height`. Image rows run along Z. `None` if the pixels can't be read, or
+/// `cell_size` isn't positive.
+pub fn heightfield_from_image(
+    image: &Image,
+    cell_size: f32,
+    max_height: f32,
+) -> Option<Heightfield> {
+    let (width, height) = (image.width(), image.height());
+    if width < 2 || height < 2 || cell_size <= 0. {
+        return None;
+    }
+
+    let mut heights = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let color = image.get_color_at(x, y).ok()?.to_srgba();
+            heights.push((color.red + color.green + color.blue) / 3. * max_height);
+        }
+    }
+    Some(Heightfield::new(
+        heights,
+        width as usize,
+        height as usize,
+        Vec2::splat(cell_size),
+    ))
+}
+
+/// Render mesh of a heightfield, with the same triangles its collider uses
+pub fn terrain_mesh(heightfield: &Heightfield) -> Mesh {
+    let (columns, rows) = (heightfield.columns, heightfield.rows);
+    let mut positions = Vec::with_capacity(columns * rows);
+    let mut normals = Vec::with_capacity(columns * rows);
+    let mut uvs = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+        for column in 0..columns {
+            positions.push(heightfield.point(column, row).to_array());
+            uvs.push([
+                column as f32 / (columns - 1) as f32,
+                row as f32 / (rows - 1) as f32,
+            ]);
+
+            let (left, right) = (column.saturating_sub(1), (column + 1).min(columns - 1));
+            let (back, front) = (row.saturating_sub(1), (row + 1).min(rows - 1));
+            let slope_x = (heightfield.height(right, row) - heightfield.height(left, row))
+                / ((right - left) as f32 * heightfield.cell_size.x);
+            let slope_z = (heightfield.height(column, front) - heightfield.height(column, back))
+                / ((front - back) as f32 * heightfield.cell_size.y);
+            normals.push(Vec3::new(-slope_x, 1., -slope_z).normalize().to_array());
+        }
+    }
+
+    let mut indices = Vec::with_capacity((columns - 1) * (rows - 1) * 6);
+    for row in 0..rows - 1 {
+        for column in 0..columns - 1 {
+            let index = |column: usize, row: usize| (row * columns + column) as u32;
+            let (i00, i10) = (index(column, row), index(column + 1, row));
+            let (i01, i11) = (index(column, row + 1), index(column + 1, row + 1));
+            // matches `Heightfield::cell_triangles`
+            indices.extend([i00, i01, i11, i00, i11, i10]);
+        }
+    }
+
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+    .with_inserted_indices(Indices::U32(indices))
+}
+
+/// Static body for a heightfield placed by `transform`. The collider ignores scale.
+pub fn terrain_body(heightfield: Heightfield, transform: &Transform) -> RigidbodyComponent {
+    RigidbodyComponent::new_static(Collider::from_heightfield(
+        heightfield,
+        transform.translation,
+        transform.rotation,
+    ))
+}
+
+/// Turns the entity into terrain once `image` has loaded, giving it the mesh and a static
+/// heightfield body at its `Transform`, then removes itself
+#[derive(Component, Clone, Debug)]
+pub struct HeightmapTerrain {
+    pub image: Handle<Image>,
+    pub cell_size: f32,
+    pub max_height: f32,
+}
+
+fn build_heightmap_terrains(
+    query: Query<(Entity, &HeightmapTerrain, &Transform)>,
+    images: Res<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut commands: Commands,
+) {
+    for (entity, terrain, transform) in &query {
+        let Some(image) = images.get(&terrain.image) else {
+            continue;
+        };
+
+        commands.entity(entity).remove::<HeightmapTerrain>();
+        let Some(heightfield) =
+            heightfield_from_image(image, terrain.cell_size, terrain.max_height)
+        else {
+            warn!("heightmap of {entity} has no readable pixels");
+            continue;
+        };
+        commands.entity(entity).insert((
+            Mesh3d(meshes.add(terrain_mesh(&heightfield))),
+            terrain_body(heightfield, transform),
+        ));
+    }
+}
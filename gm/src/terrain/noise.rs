@@ -0,0 +1,48 @@
+use std::f32::consts::{SQRT_2, TAU};
+
+use bevy::prelude::*;
+
+/// Seeded 2D gradient noise, smooth and roughly within [-1, 1]
+pub fn gradient_noise(seed: u32, point: Vec2) -> f32 {
+    let cell = point.floor();
+    let local = point - cell;
+    let (x, y) = (cell.x as i32, cell.y as i32);
+    let corner = |dx: i32, dy: i32| {
+        gradient(seed, x + dx, y + dy).dot(local - Vec2::new(dx as f32, dy as f32))
+    };
+
+    let fade = local * local * local * (local * (local * 6. - 15.) + 10.);
+    let bottom = corner(0, 0).lerp(corner(1, 0), fade.x);
+    let top = corner(0, 1).lerp(corner(1, 1), fade.x);
+    bottom.lerp(top, fade.y) * SQRT_2
+}
+
+/// `octaves` layers of gradient noise, each at twice the frequency and half the
+/// amplitude of the last, normalized back to roughly [-1, 1]
+pub fn fractal_noise(seed: u32, point: Vec2, octaves: u32) -> f32 {
+    let mut total = 0.;
+    let mut amplitude = 1.;
+    let mut frequency = 1.;
+    let mut range = 0.;
+    for octave in 0..octaves.max(1) {
+        total += gradient_noise(seed.wrapping_add(octave), point * frequency) * amplitude;
+        range += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.;
+    }
+    total / range
+}
+
+/// Unit gradient of a lattice point
+fn gradient(seed: u32, x: i32, y: i32) -> Vec2 {
+    Vec2::from_angle(hash(seed, x, y) as f32 / u32::MAX as f32 * TAU)
+}
+
+fn hash(seed: u32, x: i32, y: i32) -> u32 {
+    let mut h = seed ^ (x as u32).wrapping_mul(0x27d4_eb2d) ^ (y as u32).wrapping_mul(0x1656_67b1);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^ (h >> 15)
+}
@@ -1,23 +1,71 @@
+mod net;
+
 use std::{
-    io::{Read, Write},
-    net::TcpListener,
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use bevy::{app::ScheduleRunnerPlugin, log::LogPlugin, prelude::*, state::app::StatesPlugin};
+use gm::{
+    connection::transport::DEFAULT_PORT,
+    gamestate::AppState,
+    physics::prelude::{Collider, DEFAULT_TICK_RATE, Damping, RigidbodyComponent, ZphyPlugin},
+    player::controller::ControllerPlugin,
+    terrain::{terrain_body, world_terrain},
 };
+use net::{NetServer, ServerNetPlugin};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let listener = TcpListener::bind("127.0.0.1")?;
+    let addr: SocketAddr = match std::env::args().nth(1) {
+        Some(addr) => addr.parse()?,
+        None => (Ipv4Addr::UNSPECIFIED, DEFAULT_PORT).into(),
+    };
+    let net = NetServer::new(addr)?;
 
-    for mut stream in listener.incoming().flatten() {
-        let mut buffer = [0; 512];
-        if let Ok(bytes) = stream.read(&mut buffer) {
-            if let Ok(message) = bincode::deserialize::<String>(&buffer[0..bytes]) {
-                println!("{message}");
-                if let Ok(serialized) = bincode::serialize(&String::from("pong")) {
-                    if let Err(e) = stream.write_all(&serialized) {
-                        eprintln!("{e}");
-                    }
-                }
-            }
-        }
-    }
+    App::new()
+        .add_plugins(
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                1. / DEFAULT_TICK_RATE,
+            ))),
+        )
+        .add_plugins((LogPlugin::default(), StatesPlugin))
+        // the simulation only steps while playing, which a server always is
+        .init_state::<AppState>()
+        .insert_resource(net)
+        .add_plugins((
+            // clients replay their inputs at their own tick rate, which has to be the same
+            ZphyPlugin::with_tick_rate(DEFAULT_TICK_RATE),
+            ControllerPlugin,
+            ServerNetPlugin,
+        ))
+        .add_systems(Startup, (log_address, spawn_world))
+        .run();
     Ok(())
 }
+
+fn log_address(net: Res<NetServer>) {
+    match net.transport.local_addr() {
+        Ok(addr) => info!("listening on {addr}"),
+        Err(e) => error!("couldn't read the address listened on: {e}"),
+    }
+}
+
+fn spawn_world(mut commands: Commands) {
+    let (terrain, transform) = world_terrain();
+    commands.spawn((terrain_body(terrain, &transform), transform));
+
+    for i in 0..3 {
+        let center = Vec3::new(i as f32 * 3. - 3., 5., -10.);
+        let collider = Collider::from_cuboid(Vec3::splat(0.5), center, Quat::IDENTITY);
+        commands.spawn(RigidbodyComponent::new_dynamic(
+            10.,
+            collider,
+            0.5,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Damping::default(),
+            0.,
+        ));
+    }
+}
@@ -0,0 +1,435 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::SocketAddr,
+};
+
+use bevy::prelude::*;
+use gm::{
+    connection::{
+        protocol::{
+            BodyState, ClientMessage, NetworkId, PlayerState, ServerMessage, WorldSnapshot,
+        },
+        transport::{Channel, ServerTransport, connection_config},
+    },
+    physics::prelude::{PhysicsSet, RigidbodyComponent, RigidbodyType},
+    player::{
+        controller::PlayerInput,
+        player_data::{Player, PlayerPositioning},
+        player_info::{PlayerId, PlayerInfo},
+    },
+};
+use renet::{Bytes, ClientId, RenetServer, ServerEvent};
+
+/// Where joining players appear, above the terrain
+const SPAWN_POINT: Vec3 = Vec3::new(0., 10., 0.);
+/// Fixed ticks between two snapshots
+const SNAPSHOT_INTERVAL: u64 = 2;
+/// Inputs waiting to be applied at most per player. A client running ahead of the server
+/// loses its oldest ones, and is corrected by the next snapshot.
+const MAX_QUEUED_INPUTS: usize = 8;
+/// Longest chat message passed on, longer ones are cut
+const MAX_CHAT_CHARS: usize = 256;
+/// Chat messages a client can send in a row before being slowed down to
+/// `CHAT_MESSAGES_PER_SECOND`. Messages past that are dropped.
+const CHAT_BURST: f32 = 5.;
+const CHAT_MESSAGES_PER_SECOND: f32 = 1.;
+
+/// Accepts clients, moves their players by the inputs they send and sends everyone the
+/// resulting state every few fixed ticks. Needs a `NetServer`.
+pub struct ServerNetPlugin;
+
+impl Plugin for ServerNetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<JoinedPlayers>()
+            .init_resource::<NetworkIds>()
+            .init_resource::<ServerTick>()
+            .init_resource::<ChatAllowances>()
+            .add_systems(PreUpdate, receive_client_packets)
+            .add_systems(Update, replicate_bodies)
+            .add_systems(
+                FixedUpdate,
+                (
+                    apply_queued_input.in_set(PhysicsSet::Prepare),
+                    broadcast_snapshot.after(PhysicsSet::Sync),
+                ),
+            )
+            .add_systems(PostUpdate, send_client_packets);
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct NetServer {
+    pub server: RenetServer,
+    pub transport: ServerTransport,
+}
+
+impl NetServer {
+    pub fn new(addr: SocketAddr) -> io::Result<Self> {
+        Ok(Self {
+            server: RenetServer::new(connection_config()),
+            transport: ServerTransport::new(addr)?,
+        })
+    }
+
+    fn send(&mut self, client_id: ClientId, message: &ServerMessage) {
+        match message.to_bytes() {
+            Ok(bytes) => self
+                .server
+                .send_message(client_id, message.channel(), bytes),
+            Err(e) => error!("couldn't encode {message:?}: {e}"),
+        }
+    }
+
+    /// Send to every client that has joined, the others get the whole world on joining
+    fn broadcast(&mut self, joined: &JoinedPlayers, message: &ServerMessage) {
+        let bytes = match message.to_bytes() {
+            Ok(bytes) => Bytes::from(bytes),
+            Err(e) => return error!("couldn't encode {message:?}: {e}"),
+        };
+        for client_id in joined.0.keys() {
+            self.server
+                .send_message(*client_id, message.channel(), bytes.clone());
+        }
+    }
+}
+
+/// Player entity of each client that has joined
+#[derive(Resource, Default, Debug)]
+pub struct JoinedPlayers(pub HashMap<ClientId, Entity>);
+
+/// Ids handed to the bodies clients are told about, kept by entity to announce despawns
+#[derive(Resource, Default, Debug)]
+struct NetworkIds {
+    next: u64,
+    by_entity: HashMap<Entity, NetworkId>,
+}
+
+/// Inputs a client sent that haven't been applied yet, one is applied every fixed tick
+/// like the client predicted it
+#[derive(Component, Default, Debug)]
+pub struct InputQueue {
+    inputs: VecDeque<(u32, PlayerInput, Quat)>,
+    /// Sequence of the last input applied, acknowledged in snapshots
+    last_applied: u32,
+}
+
+impl InputQueue {
+    /// Queue an input in order, unless it's a duplicate or older than one already taken
+    fn push(&mut self, sequence: u32, input: PlayerInput, dir: Quat) {
+        let newest = self
+            .inputs
+            .back()
+            .map_or(self.last_applied, |(seq, ..)| *seq);
+        if sequence <= newest {
+            return;
+        }
+        self.inputs.push_back((sequence, input, dir));
+        if self.inputs.len() > MAX_QUEUED_INPUTS {
+            self.inputs.pop_front();
+        }
+    }
+}
+
+/// Chat messages each client may still send, refilled over time
+#[derive(Resource, Default, Debug)]
+struct ChatAllowances(HashMap<ClientId, ChatAllowance>);
+
+#[derive(Clone, Copy, Debug)]
+struct ChatAllowance {
+    messages: f32,
+    /// Seconds since startup when `messages` was last refilled
+    updated: f64,
+}
+
+impl ChatAllowances {
+    /// Take a message from the client's allowance, `false` when there's none left
+    fn take(&mut self, client_id: ClientId, now: f64) -> bool {
+        let allowance = self.0.entry(client_id).or_insert(ChatAllowance {
+            messages: CHAT_BURST,
+            updated: now,
+        });
+        let refill = (now - allowance.updated) as f32 * CHAT_MESSAGES_PER_SECOND;
+        allowance.messages = (allowance.messages + refill).min(CHAT_BURST);
+        allowance.updated = now;
+        if allowance.messages < 1. {
+            return false;
+        }
+        allowance.messages -= 1.;
+        true
+    }
+}
+
+/// `text` cut to `MAX_CHAT_CHARS` characters
+fn truncate_chat(mut text: String) -> String {
+    if let Some((end, _)) = text.char_indices().nth(MAX_CHAT_CHARS) {
+        text.truncate(end);
+    }
+    text
+}
+
+/// Fixed ticks simulated so far
+#[derive(Resource, Default, Debug)]
+pub struct ServerTick(pub u64);
+
+fn receive_client_packets(
+    mut commands: Commands,
+    mut net: ResMut<NetServer>,
+    mut joined: ResMut<JoinedPlayers>,
+    mut chat_allowances: ResMut<ChatAllowances>,
+    mut players: Query<(&Player, &mut InputQueue)>,
+    bodies: Query<(&NetworkId, &RigidbodyComponent)>,
+    time: Res<Time>,
+) {
+    let NetServer { server, transport } = net.as_mut();
+    if let Err(e) = transport.update(time.delta(), server) {
+        error!("couldn't receive: {e}");
+    }
+
+    while let Some(event) = net.server.get_event() {
+        match event {
+            ServerEvent::ClientConnected { client_id } => {
+                let addr = net.transport.client_addr(client_id);
+                info!("client {client_id} connected from {addr:?}");
+            }
+            ServerEvent::ClientDisconnected { client_id, reason } => {
+                info!("client {client_id} disconnected: {reason}");
+                chat_allowances.0.remove(&client_id);
+                let Some(entity) = joined.0.remove(&client_id) else {
+                    continue;
+                };
+                if let Ok((player, _)) = players.get(entity) {
+                    let despawn = ServerMessage::DespawnPlayer(player.info.id.clone());
+                    net.broadcast(&joined, &despawn);
+                }
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+
+    let mut messages = Vec::new();
+    for client_id in net.server.clients_id() {
+        for channel in Channel::ALL {
+            while let Some(bytes) = net.server.receive_message(client_id, channel) {
+                match ClientMessage::from_bytes(&bytes) {
+                    Ok(message) => messages.push((client_id, message)),
+                    Err(e) => warn!("dropped a message from client {client_id}: {e}"),
+                }
+            }
+        }
+    }
+
+    // players joining in this batch only get spawned once the commands apply, they're
+    // kept here until then so later messages in the batch still find them
+    let mut joining: HashMap<Entity, (Player, InputQueue)> = HashMap::new();
+    for (client_id, message) in messages {
+        let entity = joined.0.get(&client_id).copied();
+        match (message, entity) {
+            (ClientMessage::Join { info }, None) => {
+                info!("{} joined as client {client_id}", info.username);
+                // ids are handed out here, so no client can take over another's player
+                let info = PlayerInfo {
+                    id: PlayerId::new_id(),
+                    ..info
+                };
+                let player = Player {
+                    info,
+                    pos: PlayerPositioning::new(SPAWN_POINT, Quat::IDENTITY),
+                    ..Default::default()
+                };
+                for entity in joined.0.values() {
+                    let other = match joining.get(entity) {
+                        Some((other, _)) => other,
+                        None => match players.get(*entity) {
+                            Ok((other, _)) => other,
+                            Err(_) => continue,
+                        },
+                    };
+                    net.send(client_id, &ServerMessage::SpawnPlayer(other.clone()));
+                }
+                for (id, body) in bodies.iter() {
+                    let body = body.clone();
+                    net.send(client_id, &ServerMessage::SpawnBody { id: *id, body });
+                }
+                net.send(
+                    client_id,
+                    &ServerMessage::Welcome {
+                        player: player.clone(),
+                    },
+                );
+                net.broadcast(&joined, &ServerMessage::SpawnPlayer(player.clone()));
+                let entity = commands.spawn_empty().id();
+                joining.insert(entity, (player, InputQueue::default()));
+                joined.0.insert(client_id, entity);
+            }
+            (
+                ClientMessage::Input {
+                    sequence,
+                    input,
+                    dir,
+                },
+                Some(entity),
+            ) => {
+                if let Some((_, queue)) = joining.get_mut(&entity) {
+                    queue.push(sequence, input, dir);
+                } else if let Ok((_, mut queue)) = players.get_mut(entity) {
+                    queue.push(sequence, input, dir);
+                }
+            }
+            (ClientMessage::Chat(text), Some(entity)) => {
+                if !chat_allowances.take(client_id, time.elapsed_secs_f64()) {
+                    warn!("dropped a chat message from client {client_id} sending too many");
+                    continue;
+                }
+                let text = truncate_chat(text);
+                let from = match joining.get(&entity) {
+                    Some((player, _)) => player.info.username.clone(),
+                    None => match players.get(entity) {
+                        Ok((player, _)) => player.info.username.clone(),
+                        Err(_) => continue,
+                    },
+                };
+                net.broadcast(&joined, &ServerMessage::Chat { from, text });
+            }
+            // nothing but a join is taken before joining, and only one
+            (_, _) => {}
+        }
+    }
+
+    for (entity, joiner) in joining {
+        commands.entity(entity).insert(joiner);
+    }
+}
+
+/// Hand every player the next input their client sent. When none arrived in time they
+/// keep the last one, minus the jump.
+fn apply_queued_input(mut players: Query<(&mut Player, &mut PlayerInput, &mut InputQueue)>) {
+    for (mut player, mut latched, mut queue) in players.iter_mut() {
+        if let Some((sequence, input, dir)) = queue.inputs.pop_front() {
+            queue.last_applied = sequence;
+            let Some((input, dir)) = sanitize_input(input, dir) else {
+                warn!("dropped a malformed input from {}", player.info.username);
+                continue;
+            };
+            *latched = input;
+            player.pos.dir = dir;
+        }
+    }
+}
+
+/// Clients can send anything, so the step only gets a finite unit rotation and movement
+/// no longer than one. `None` if the input can't be made into that.
+fn sanitize_input(mut input: PlayerInput, dir: Quat) -> Option<(PlayerInput, Quat)> {
+    if !input.movement.is_finite() || !dir.is_finite() {
+        return None;
+    }
+    let dir = dir.normalize();
+    if !dir.is_finite() {
+        return None;
+    }
+    input.movement = input.movement.clamp_length_max(1.);
+    Some((input, dir))
+}
+
+/// Give new moving bodies an id and tell clients about them, and about removed ones.
+/// Static bodies like the terrain are built by clients themselves.
+fn replicate_bodies(
+    mut commands: Commands,
+    mut net: ResMut<NetServer>,
+    mut ids: ResMut<NetworkIds>,
+    joined: Res<JoinedPlayers>,
+    added: Query<(Entity, &RigidbodyComponent), Without<NetworkId>>,
+    mut removed: RemovedComponents<RigidbodyComponent>,
+) {
+    for entity in removed.read() {
+        if let Some(id) = ids.by_entity.remove(&entity) {
+            net.broadcast(&joined, &ServerMessage::DespawnBody(id));
+        }
+    }
+
+    for (entity, body) in added.iter() {
+        if body.rbt == RigidbodyType::Static {
+            continue;
+        }
+        let id = NetworkId(ids.next);
+        ids.next += 1;
+        ids.by_entity.insert(entity, id);
+        commands.entity(entity).insert(id);
+        let body = body.clone();
+        net.broadcast(&joined, &ServerMessage::SpawnBody { id, body });
+    }
+}
+
+fn broadcast_snapshot(
+    mut net: ResMut<NetServer>,
+    mut tick: ResMut<ServerTick>,
+    joined: Res<JoinedPlayers>,
+    players: Query<(&Player, &InputQueue)>,
+    bodies: Query<(&NetworkId, &RigidbodyComponent)>,
+    time: Res<Time>,
+) {
+    tick.0 += 1;
+    if !tick.0.is_multiple_of(SNAPSHOT_INTERVAL) || joined.0.is_empty() {
+        return;
+    }
+
+    let players = joined
+        .0
+        .values()
+        .filter_map(|entity| players.get(*entity).ok())
+        .map(|(player, queue)| PlayerState {
+            id: player.info.id.clone(),
+            pos: player.pos.clone(),
+            last_input: queue.last_applied,
+        })
+        .collect();
+    let mut bodies: Vec<BodyState> = bodies
+        .iter()
+        .map(|(id, body)| BodyState {
+            id: *id,
+            center: body.collider.center,
+            rotation: body.collider.rotation,
+            velocity: body.velocity,
+        })
+        .collect();
+    bodies.sort_unstable_by_key(|body| body.id);
+
+    let snapshot = WorldSnapshot {
+        tick: tick.0,
+        time: time.elapsed_secs_f64(),
+        players,
+        bodies,
+    };
+    net.broadcast(&joined, &ServerMessage::Snapshot(snapshot));
+}
+
+fn send_client_packets(mut net: ResMut<NetServer>) {
+    let NetServer { server, transport } = net.as_mut();
+    if let Err(e) = transport.send_packets(server) {
+        error!("couldn't send: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chat_is_cut_to_length_on_char_boundaries() {
+        let long = "é".repeat(MAX_CHAT_CHARS + 10);
+        assert_eq!(truncate_chat(long).chars().count(), MAX_CHAT_CHARS);
+        assert_eq!(truncate_chat("hi".to_string()), "hi");
+    }
+
+    #[test]
+    fn chat_past_the_burst_waits_for_the_allowance_to_refill() {
+        let mut allowances = ChatAllowances::default();
+        let sent = (0..10).filter(|_| allowances.take(1, 0.)).count();
+        assert_eq!(sent, CHAT_BURST as usize);
+        assert!(allowances.take(2, 0.), "clients have an allowance each");
+
+        assert!(!allowances.take(1, 0.5));
+        assert!(allowances.take(1, 1.));
+        assert!(!allowances.take(1, 1.));
+    }
+}